@@ -31,6 +31,7 @@ mod hello_swap {
         pub fn swap(&mut self, mut x_bucket: Bucket) -> (Bucket, Bucket) {
             let input = x_bucket.take(self.price);
             let output = self.y_vault.take(1);
+            info!("swapped {} for {}", input.amount(), output.amount());
             self.x_vault.put(input);
             (output, x_bucket)
         }
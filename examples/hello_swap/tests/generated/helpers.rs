@@ -0,0 +1,67 @@
+// Generated by scrypto_testenv::generate_helper_stubs, do not edit
+use scrypto::prelude::*;
+use scrypto_testenv::*;
+use std::ops::DerefMut;
+
+pub struct HelloSwapTestHelper {
+    pub env: TestEnvironment,
+    /// Component called by the methods, has to be set after instantiating the blueprint
+    pub component_address: Option<ComponentAddress>,
+}
+
+impl TestHelperExecution for HelloSwapTestHelper {
+    fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {
+        &mut self.env
+    }
+}
+
+impl HelloSwapTestHelper {
+    pub fn new(env: TestEnvironment) -> Self {
+        Self {
+            env,
+            component_address: None,
+        }
+    }
+
+    pub fn instantiate(
+        &mut self,
+        arg_x_address: ResourceAddress,
+        arg_y_bucket: (ResourceAddress, Decimal),
+        arg_price: Decimal,
+    ) -> &mut Self {
+        let package_address = self.env.package_address("hello_swap");
+        let arg_y_bucket_name = self.name("arg_y_bucket");
+        let account = self.env.account;
+        self.add_instructions("instantiate", |builder| {
+            builder
+                .withdraw_from_account(account, arg_y_bucket.0, arg_y_bucket.1)
+                .take_from_worktop(arg_y_bucket.0, arg_y_bucket.1, &arg_y_bucket_name)
+                .with_name_lookup(|builder, lookup| {
+                    let arg_y_bucket = lookup.bucket(&arg_y_bucket_name);
+                    builder.call_function(
+                        package_address,
+                        "HelloSwap",
+                        "instantiate",
+                        manifest_args!(arg_x_address, arg_y_bucket, arg_price),
+                    )
+                })
+        });
+        self
+    }
+
+    pub fn swap(&mut self, arg_x_bucket: (ResourceAddress, Decimal)) -> &mut Self {
+        let component_address = self.component_address.expect("Component address not set");
+        let arg_x_bucket_name = self.name("arg_x_bucket");
+        let account = self.env.account;
+        self.add_instructions("swap", |builder| {
+            builder
+                .withdraw_from_account(account, arg_x_bucket.0, arg_x_bucket.1)
+                .take_from_worktop(arg_x_bucket.0, arg_x_bucket.1, &arg_x_bucket_name)
+                .with_name_lookup(|builder, lookup| {
+                    let arg_x_bucket = lookup.bucket(&arg_x_bucket_name);
+                    builder.call_method(component_address, "swap", manifest_args!(arg_x_bucket))
+                })
+        });
+        self
+    }
+}
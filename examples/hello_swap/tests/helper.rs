@@ -1,11 +1,10 @@
 use radix_engine::system::system_modules::execution_trace::ResourceSpecifier::Amount;
-use radix_transactions::builder::ManifestBuilder;
 use scrypto::prelude::*;
 use scrypto_testenv::*;
-use std::mem;
+use std::ops::DerefMut;
 
 impl TestHelperExecution for HelloSwapTestHelper {
-    fn env(&mut self) -> &mut TestEnvironment {
+    fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {
         &mut self.env
     }
 }
@@ -36,26 +35,24 @@ impl HelloSwapTestHelper {
         y_amount: Decimal,
         price: Decimal,
     ) -> &mut HelloSwapTestHelper {
-        // with the next ManifestBuilder update this can be simplified to
-        // let manifest_builder = mem::take(&mut self.environment.manifest_builder);
-        let manifest_builder = mem::replace(&mut self.env.manifest_builder, ManifestBuilder::new());
-        self.env.manifest_builder = manifest_builder
-            .withdraw_from_account(self.env.account, y_address, y_amount)
-            .take_from_worktop(y_address, y_amount, self.name("y_bucket"))
-            .with_name_lookup(|builder, lookup| {
-                let y_bucket = lookup.bucket(self.name("y_bucket"));
-                builder.call_function(
-                    self.env.package_address("hello_swap"),
-                    "HelloSwap",
-                    "instantiate",
-                    manifest_args!(x_address, y_bucket, price),
-                )
-            });
-        // To support instruction labels we are tracking:
-        // instruction_count = the total amount of new instructions added in this function
-        // label_instruction_id = (local) instruction id which you want to assign to the label
-        // after the ManifestBuilder supports labels upstream this can be simplified
-        self.env.new_instruction("instantiate", 3, 2);
+        let y_bucket_name = self.name("y_bucket");
+        let account = self.env.account;
+        let package_address = self.env.package_address("hello_swap");
+        // The label is assigned to the last instruction added, the call of instantiate
+        self.add_instructions("instantiate", |builder| {
+            builder
+                .withdraw_from_account(account, y_address, y_amount)
+                .take_from_worktop(y_address, y_amount, &y_bucket_name)
+                .with_name_lookup(|builder, lookup| {
+                    let y_bucket = lookup.bucket(&y_bucket_name);
+                    builder.call_function(
+                        package_address,
+                        "HelloSwap",
+                        "instantiate",
+                        manifest_args!(x_address, y_bucket, price),
+                    )
+                })
+        });
         self
     }
 
@@ -64,15 +61,18 @@ impl HelloSwapTestHelper {
         x_address: ResourceAddress,
         x_amount: Decimal,
     ) -> &mut HelloSwapTestHelper {
-        let manifest_builder = mem::replace(&mut self.env.manifest_builder, ManifestBuilder::new());
-        self.env.manifest_builder = manifest_builder
-            .withdraw_from_account(self.env.account, x_address, x_amount)
-            .take_from_worktop(x_address, x_amount, self.name("x_bucket"))
-            .with_name_lookup(|builder, lookup| {
-                let x_bucket = lookup.bucket(self.name("x_bucket"));
-                builder.call_method(self.pool_address.unwrap(), "swap", manifest_args!(x_bucket))
-            });
-        self.env.new_instruction("swap", 3, 2);
+        let x_bucket_name = self.name("x_bucket");
+        let account = self.env.account;
+        let pool_address = self.pool_address.unwrap();
+        self.add_instructions("swap", |builder| {
+            builder
+                .withdraw_from_account(account, x_address, x_amount)
+                .take_from_worktop(x_address, x_amount, &x_bucket_name)
+                .with_name_lookup(|builder, lookup| {
+                    let x_bucket = lookup.bucket(&x_bucket_name);
+                    builder.call_method(pool_address, "swap", manifest_args!(x_bucket))
+                })
+        });
         self
     }
 
@@ -82,7 +82,9 @@ impl HelloSwapTestHelper {
         price: Decimal,
         verbose: bool,
     ) -> Receipt {
-        self.instantiate(self.x_address(), self.y_address(), y_amount, price);
+        let x_address = self.env.resource(TestAddress::X);
+        let y_address = self.env.resource(TestAddress::Y);
+        self.instantiate(x_address, y_address, y_amount, price);
         let receipt = self.execute_expect_success(verbose);
         let (pool_address, price): (ComponentAddress, Decimal) = receipt.outputs("instantiate")[0];
         self.pool_address = Some(pool_address);
@@ -91,8 +93,8 @@ impl HelloSwapTestHelper {
     }
 
     pub fn swap_expect_failure(&mut self, x_amount: Decimal) {
-        self.swap(self.x_address(), x_amount)
-            .execute_expect_failure(true);
+        let x_address = self.env.resource(TestAddress::X);
+        self.swap(x_address, x_amount).execute_expect_failure(true);
     }
 
     pub fn swap_expect_success(
@@ -101,51 +103,19 @@ impl HelloSwapTestHelper {
         y_amount_expected: Decimal,
         x_remainder_expected: Decimal,
     ) {
-        let receipt = self
-            .swap(self.x_address(), x_amount)
-            .execute_expect_success(true);
+        let x_address = self.env.resource(TestAddress::X);
+        let y_address = self.env.resource(TestAddress::Y);
+        let receipt = self.swap(x_address, x_amount).execute_expect_success(true);
         let output_buckets = receipt.output_buckets("swap");
 
         assert_eq!(
             output_buckets,
             vec![vec![
-                Amount(self.y_address(), y_amount_expected),
-                Amount(self.x_address(), x_remainder_expected)
+                Amount(y_address, y_amount_expected),
+                Amount(x_address, x_remainder_expected)
             ]],
         );
     }
-
-    pub fn a_address(&self) -> ResourceAddress {
-        self.env.a_address
-    }
-
-    pub fn b_address(&self) -> ResourceAddress {
-        self.env.b_address
-    }
-
-    pub fn x_address(&self) -> ResourceAddress {
-        self.env.x_address
-    }
-
-    pub fn y_address(&self) -> ResourceAddress {
-        self.env.y_address
-    }
-
-    pub fn v_address(&self) -> ResourceAddress {
-        self.env.v_address
-    }
-
-    pub fn u_address(&self) -> ResourceAddress {
-        self.env.u_address
-    }
-
-    pub fn j_nft_address(&self) -> ResourceAddress {
-        self.env.j_nft_address
-    }
-
-    pub fn k_nft_address(&self) -> ResourceAddress {
-        self.env.k_nft_address
-    }
 }
 
 pub fn instantiate_expect_success(y_amount: Decimal, price: Decimal) {
@@ -155,8 +125,10 @@ pub fn instantiate_expect_success(y_amount: Decimal, price: Decimal) {
 
 pub fn instantiate_expect_failure(y_amount: Decimal, price: Decimal) {
     let mut helper = HelloSwapTestHelper::new();
+    let x_address = helper.env.resource(TestAddress::X);
+    let y_address = helper.env.resource(TestAddress::Y);
     helper
-        .instantiate(helper.x_address(), helper.y_address(), y_amount, price)
+        .instantiate(x_address, y_address, y_amount, price)
         .execute_expect_failure(true);
 }
 
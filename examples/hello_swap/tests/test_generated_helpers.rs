@@ -0,0 +1,30 @@
+use radix_engine::system::system_modules::execution_trace::ResourceSpecifier::Amount;
+use scrypto::prelude::*;
+use scrypto_testenv::*;
+
+// Helpers generated by `generate_helper_stubs`, kept up to date by the tests of scrypto_testenv
+mod generated {
+    include!("generated/helpers.rs");
+}
+use generated::HelloSwapTestHelper;
+
+#[test]
+fn test_generated_helpers() {
+    let packages: HashMap<&str, &str> = vec![("hello_swap", ".")].into_iter().collect();
+    let mut helper = HelloSwapTestHelper::new(TestEnvironment::new(packages));
+    let (x_address, y_address) = (helper.env.x_address, helper.env.y_address);
+
+    let receipt = helper
+        .instantiate(x_address, (y_address, dec!(10)), dec!(2))
+        .execute_expect_success(false);
+    let (component_address, _): (ComponentAddress, Decimal) = receipt.outputs("instantiate")[0];
+    helper.component_address = Some(component_address);
+
+    let receipt = helper
+        .swap((x_address, dec!(3)))
+        .execute_expect_success(false);
+    assert_eq!(
+        receipt.output_buckets("swap"),
+        vec![vec![Amount(y_address, dec!(1)), Amount(x_address, dec!(1))]]
+    );
+}
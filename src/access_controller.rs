@@ -0,0 +1,254 @@
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+use std::mem;
+
+use crate::TestEnvironment;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessControllerRole {
+    Primary,
+    Recovery,
+    Confirmation,
+}
+
+/// Account whose owner badge is held by an access controller, see `securify_account`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurifiedAccount {
+    pub account: ComponentAddress,
+    pub access_controller: ComponentAddress,
+    pub primary_key: PublicKey,
+    pub recovery_key: PublicKey,
+    pub confirmation_key: PublicKey,
+    pub timed_recovery_delay_in_minutes: Option<u32>,
+}
+
+impl SecurifiedAccount {
+    pub fn key(&self, role: AccessControllerRole) -> PublicKey {
+        match role {
+            AccessControllerRole::Primary => self.primary_key,
+            AccessControllerRole::Recovery => self.recovery_key,
+            AccessControllerRole::Confirmation => self.confirmation_key,
+        }
+    }
+
+    fn rule_set(&self) -> RuleSet {
+        RuleSet {
+            primary_role: signature_rule(&self.primary_key),
+            recovery_role: signature_rule(&self.recovery_key),
+            confirmation_role: signature_rule(&self.confirmation_key),
+        }
+    }
+}
+
+fn signature_rule(public_key: &PublicKey) -> AccessRule {
+    rule!(require(NonFungibleGlobalId::from_public_key(public_key)))
+}
+
+impl TestEnvironment {
+    /// Moves the owner badge of the account registered under `label` into a new access
+    /// controller. The primary role stays with the key of the account, the recovery and
+    /// confirmation roles get fresh keys.
+    pub fn securify_account(
+        &mut self,
+        label: &str,
+        timed_recovery_delay_in_minutes: Option<u32>,
+    ) -> SecurifiedAccount {
+        let test_account = self.test_account(label).clone();
+        let (recovery_key, _) = self.new_key_pair();
+        let (confirmation_key, _) = self.new_key_pair();
+        let mut securified_account = SecurifiedAccount {
+            account: test_account.address,
+            access_controller: test_account.address,
+            primary_key: test_account.key,
+            recovery_key: recovery_key.into(),
+            confirmation_key: confirmation_key.into(),
+            timed_recovery_delay_in_minutes,
+        };
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                test_account.address,
+                ACCOUNT_SECURIFY_IDENT,
+                manifest_args!(),
+            )
+            .take_all_from_worktop(ACCOUNT_OWNER_BADGE, "owner_badge")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_function(
+                    ACCESS_CONTROLLER_PACKAGE,
+                    ACCESS_CONTROLLER_BLUEPRINT,
+                    ACCESS_CONTROLLER_CREATE_IDENT,
+                    AccessControllerCreateManifestInput {
+                        controlled_asset: lookup.bucket("owner_badge"),
+                        rule_set: securified_account.rule_set(),
+                        timed_recovery_delay_in_minutes,
+                        address_reservation: None,
+                    },
+                )
+            })
+            .build();
+        securified_account.access_controller = self
+            .test_runner
+            .execute_manifest(manifest, vec![test_account.global_id()])
+            .expect_commit_success()
+            .new_component_addresses()[0];
+        securified_account
+    }
+
+    /// Proposes `new_primary_key` as primary role, signed by the key of `proposer`
+    /// (primary or recovery role)
+    pub fn initiate_recovery(
+        &mut self,
+        securified_account: &SecurifiedAccount,
+        proposer: AccessControllerRole,
+        new_primary_key: PublicKey,
+    ) {
+        let method = match proposer {
+            AccessControllerRole::Primary => ACCESS_CONTROLLER_INITIATE_RECOVERY_AS_PRIMARY_IDENT,
+            AccessControllerRole::Recovery => ACCESS_CONTROLLER_INITIATE_RECOVERY_AS_RECOVERY_IDENT,
+            AccessControllerRole::Confirmation => {
+                panic!("The confirmation role can't initiate a recovery")
+            }
+        };
+        let proposal = Self::recovery_proposal(securified_account, new_primary_key);
+        self.execute_access_controller_call(
+            securified_account,
+            proposer,
+            method,
+            manifest_args!(proposal.rule_set, proposal.timed_recovery_delay_in_minutes),
+        );
+    }
+
+    /// Confirms the recovery to `new_primary_key` initiated by `proposer`, signed by the key
+    /// of `confirmer` (any other role). Updates the primary key of `securified_account`.
+    pub fn confirm_recovery(
+        &mut self,
+        securified_account: &mut SecurifiedAccount,
+        proposer: AccessControllerRole,
+        confirmer: AccessControllerRole,
+        new_primary_key: PublicKey,
+    ) {
+        let method = match proposer {
+            AccessControllerRole::Primary => {
+                ACCESS_CONTROLLER_QUICK_CONFIRM_PRIMARY_ROLE_RECOVERY_PROPOSAL_IDENT
+            }
+            AccessControllerRole::Recovery => {
+                ACCESS_CONTROLLER_QUICK_CONFIRM_RECOVERY_ROLE_RECOVERY_PROPOSAL_IDENT
+            }
+            AccessControllerRole::Confirmation => {
+                panic!("The confirmation role can't initiate a recovery")
+            }
+        };
+        let proposal = Self::recovery_proposal(securified_account, new_primary_key);
+        self.execute_access_controller_call(
+            securified_account,
+            confirmer,
+            method,
+            manifest_args!(proposal.rule_set, proposal.timed_recovery_delay_in_minutes),
+        );
+        securified_account.primary_key = new_primary_key;
+    }
+
+    fn recovery_proposal(
+        securified_account: &SecurifiedAccount,
+        new_primary_key: PublicKey,
+    ) -> RecoveryProposal {
+        RecoveryProposal {
+            rule_set: SecurifiedAccount {
+                primary_key: new_primary_key,
+                ..securified_account.clone()
+            }
+            .rule_set(),
+            timed_recovery_delay_in_minutes: securified_account.timed_recovery_delay_in_minutes,
+        }
+    }
+
+    fn execute_access_controller_call(
+        &mut self,
+        securified_account: &SecurifiedAccount,
+        signer: AccessControllerRole,
+        method: &str,
+        arguments: ManifestArgs,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(securified_account.access_controller, method, arguments)
+            .build();
+        let initial_proofs = vec![NonFungibleGlobalId::from_public_key(
+            &securified_account.key(signer),
+        )];
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Puts the owner badge proof of a securified account into the auth zone, so following
+    /// instructions can call protected methods of the account (e.g. withdraws). Requires
+    /// the primary role, so execute as the account holding the primary key. Securified accounts
+    /// can't lock fees before the proof is created, so let another component pay (see `fee_config`).
+    pub fn create_access_controller_proof(
+        &mut self,
+        access_controller: ComponentAddress,
+    ) -> &mut Self {
+        let manifest_builder = mem::replace(&mut self.manifest_builder, ManifestBuilder::new());
+        self.manifest_builder = manifest_builder.call_method(
+            access_controller,
+            ACCESS_CONTROLLER_CREATE_PROOF_IDENT,
+            manifest_args!(),
+        );
+        self.instruction_counter += 1;
+        self
+    }
+}
+
+#[test]
+fn test_access_controller_recovery() {
+    use crate::{FeePayer, TestHelperExecution};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    test_environment.fee_config.payer = FeePayer::Component(FAUCET);
+    let vault = test_environment.new_account("vault");
+    test_environment.new_account("backup");
+    let backup_key = test_environment.test_account("backup").key;
+    let mut securified_account = test_environment.securify_account("vault", None);
+    let access_controller = securified_account.access_controller;
+    let withdraw_from_vault = |test_environment: &mut TestEnvironment| {
+        test_environment.create_access_controller_proof(access_controller);
+        let manifest_builder = mem::replace(
+            &mut test_environment.manifest_builder,
+            ManifestBuilder::new(),
+        );
+        test_environment.manifest_builder =
+            manifest_builder.withdraw_from_account(vault, XRD, dec!(1));
+        test_environment.instruction_counter += 1;
+    };
+
+    withdraw_from_vault(&mut test_environment);
+    test_environment
+        .execute_as("vault", false)
+        .execution_receipt
+        .expect_commit_success();
+
+    test_environment.initiate_recovery(
+        &securified_account,
+        AccessControllerRole::Recovery,
+        backup_key,
+    );
+    test_environment.confirm_recovery(
+        &mut securified_account,
+        AccessControllerRole::Recovery,
+        AccessControllerRole::Confirmation,
+        backup_key,
+    );
+
+    withdraw_from_vault(&mut test_environment);
+    test_environment
+        .execute_as("vault", false)
+        .execution_receipt
+        .expect_commit_failure();
+    withdraw_from_vault(&mut test_environment);
+    test_environment
+        .execute_as("backup", false)
+        .execution_receipt
+        .expect_commit_success();
+}
@@ -0,0 +1,204 @@
+use radix_engine::{
+    errors::{RuntimeError, SystemModuleError},
+    system::system_modules::auth::AuthError,
+    transaction::{TransactionOutcome, TransactionResult},
+};
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+/// Outcome of calling a method with a certain set of badges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthOutcome {
+    Allowed,
+    Denied,
+    /// Failed for another reason than missing authorization
+    Failed(String),
+}
+
+struct AuthCase {
+    name: String,
+    allowed: Vec<Vec<ResourceAddress>>,
+    call: Box<dyn Fn(ManifestBuilder) -> ManifestBuilder>,
+}
+
+/// Table of calls and the badge combinations allowed to make them, see `assert_auth_matrix`:
+/// ```ignore
+/// let matrix = AuthMatrix::new(vec![admin_badge, user_badge])
+///     .case("withdraw", vec![vec![admin_badge]], move |builder| {
+///         builder.call_method(vault, "withdraw", manifest_args!(dec!(1)))
+///     })
+///     .case("deposit", vec![vec![admin_badge], vec![user_badge]], ...);
+/// test_environment.assert_auth_matrix(&matrix);
+/// ```
+pub struct AuthMatrix {
+    badges: Vec<ResourceAddress>,
+    cases: Vec<AuthCase>,
+}
+
+impl AuthMatrix {
+    /// `badges` have to be held by the main account, each combination of them is tried
+    pub fn new(badges: Vec<ResourceAddress>) -> Self {
+        Self {
+            badges,
+            cases: vec![],
+        }
+    }
+
+    /// Adds a call which has to succeed whenever the presented badges include one of the
+    /// `allowed` combinations and fail with an auth error otherwise (`vec![vec![]]` for public calls)
+    pub fn case<F>(mut self, name: &str, allowed: Vec<Vec<ResourceAddress>>, call: F) -> Self
+    where
+        F: Fn(ManifestBuilder) -> ManifestBuilder + 'static,
+    {
+        self.cases.push(AuthCase {
+            name: name.to_string(),
+            allowed,
+            call: Box::new(call),
+        });
+        self
+    }
+
+    fn combinations(&self) -> Vec<Vec<ResourceAddress>> {
+        (0..1usize << self.badges.len())
+            .map(|mask| {
+                self.badges
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| mask & (1 << index) != 0)
+                    .map(|(_, badge)| *badge)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl TestEnvironment {
+    /// Sets the rule of `role` in `module` of `entity`, presenting the admin badge as owner
+    pub fn set_role(
+        &mut self,
+        entity: GlobalAddress,
+        module: ModuleId,
+        role: &str,
+        rule: AccessRule,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .set_role(entity, module, RoleKey::new(role), rule)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Outcome of `call` with proofs of `badges` from the main account, run against
+    /// a revive of the current state, so the environment itself is left untouched
+    pub fn auth_outcome<F>(&self, badges: &[ResourceAddress], call: F) -> AuthOutcome
+    where
+        F: FnOnce(ManifestBuilder) -> ManifestBuilder,
+    {
+        let mut test_environment = self.create_snapshot().revive();
+        let mut manifest_builder = ManifestBuilder::new().lock_fee_from_faucet();
+        for badge in badges {
+            manifest_builder =
+                manifest_builder.create_proof_from_account_of_amount(self.account, *badge, dec!(1));
+        }
+        let manifest = call(manifest_builder).deposit_batch(self.account).build();
+        let initial_proofs = self.signatures(&[self.account]);
+        let receipt = test_environment
+            .test_runner
+            .execute_manifest(manifest, initial_proofs);
+        match &receipt.result {
+            TransactionResult::Commit(commit_result) => match &commit_result.outcome {
+                TransactionOutcome::Success(_) => AuthOutcome::Allowed,
+                TransactionOutcome::Failure(RuntimeError::SystemModuleError(
+                    SystemModuleError::AuthError(AuthError::Unauthorized(_)),
+                )) => AuthOutcome::Denied,
+                TransactionOutcome::Failure(error) => AuthOutcome::Failed(format!("{:?}", error)),
+            },
+            result => AuthOutcome::Failed(format!("{:?}", result)),
+        }
+    }
+
+    /// Runs every case of `matrix` with every combination of its badges and panics
+    /// listing all calls whose outcome differs from the expected allow/deny
+    pub fn assert_auth_matrix(&self, matrix: &AuthMatrix) {
+        let mut mismatches = vec![];
+        for case in &matrix.cases {
+            for badges in matrix.combinations() {
+                let expected = match case
+                    .allowed
+                    .iter()
+                    .any(|allowed| allowed.iter().all(|badge| badges.contains(badge)))
+                {
+                    true => AuthOutcome::Allowed,
+                    false => AuthOutcome::Denied,
+                };
+                let actual = self.auth_outcome(&badges, &case.call);
+                if actual != expected {
+                    mismatches.push(format!(
+                        "{} with {:?}: expected {:?}, got {:?}",
+                        case.name, badges, expected, actual
+                    ));
+                }
+            }
+        }
+        assert!(
+            mismatches.is_empty(),
+            "Auth matrix mismatches:\n{}",
+            mismatches.join("\n")
+        );
+    }
+}
+
+#[test]
+fn test_auth_matrix() {
+    use crate::TestEnvironmentBuilder;
+
+    let mut test_environment = TestEnvironmentBuilder::new().badge("manager").build();
+    let (account, admin_badge, manager_badge, x_address) = (
+        test_environment.account,
+        test_environment.admin_badge_address,
+        test_environment.badge("manager"),
+        test_environment.x_address,
+    );
+    let pool = test_environment.create_one_resource_pool(x_address);
+    let protected_deposit = move |builder: ManifestBuilder| {
+        builder
+            .withdraw_from_account(account, x_address, dec!(1))
+            .take_from_worktop(x_address, dec!(1), "x")
+            .with_name_lookup(|builder, lookup| {
+                let x_bucket = lookup.bucket("x");
+                builder.call_method(pool, "protected_deposit", manifest_args!(x_bucket))
+            })
+    };
+    let get_vault_amount = move |builder: ManifestBuilder| {
+        builder.call_method(pool, "get_vault_amount", manifest_args!())
+    };
+
+    test_environment.assert_auth_matrix(
+        &AuthMatrix::new(vec![admin_badge, manager_badge])
+            .case(
+                "protected_deposit",
+                vec![vec![admin_badge]],
+                protected_deposit,
+            )
+            .case("get_vault_amount", vec![vec![]], get_vault_amount),
+    );
+
+    test_environment.set_role(
+        pool.into(),
+        ModuleId::Main,
+        "pool_manager_role",
+        rule!(require(manager_badge)),
+    );
+
+    test_environment.assert_auth_matrix(&AuthMatrix::new(vec![admin_badge, manager_badge]).case(
+        "protected_deposit",
+        vec![vec![manager_badge]],
+        protected_deposit,
+    ));
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Result of `TestHelperExecution::bench`, holding one sample per iteration
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    /// Total cost units (execution + finalization) consumed per iteration
+    pub cost_units: Vec<u32>,
+    /// Wall-clock time spent per iteration
+    pub durations: Vec<Duration>,
+}
+
+impl BenchReport {
+    pub fn iterations(&self) -> usize {
+        self.cost_units.len()
+    }
+
+    pub fn min_cost_units(&self) -> u32 {
+        self.cost_units.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn max_cost_units(&self) -> u32 {
+        self.cost_units.iter().copied().max().unwrap_or_default()
+    }
+
+    pub fn mean_cost_units(&self) -> f64 {
+        if self.cost_units.is_empty() {
+            return 0.0;
+        }
+        self.cost_units
+            .iter()
+            .map(|&units| units as f64)
+            .sum::<f64>()
+            / self.iterations() as f64
+    }
+
+    /// Executing the same flow against the same ledger state has to consume
+    /// exactly the same amount of cost units every time
+    pub fn is_cost_deterministic(&self) -> bool {
+        self.min_cost_units() == self.max_cost_units()
+    }
+
+    pub fn min_duration(&self) -> Duration {
+        self.durations.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn max_duration(&self) -> Duration {
+        self.durations.iter().copied().max().unwrap_or_default()
+    }
+
+    pub fn mean_duration(&self) -> Duration {
+        if self.durations.is_empty() {
+            return Duration::ZERO;
+        }
+        self.durations.iter().sum::<Duration>() / self.durations.len() as u32
+    }
+
+    pub fn median_duration(&self) -> Duration {
+        let mut durations = self.durations.clone();
+        durations.sort();
+        durations
+            .get(durations.len() / 2)
+            .copied()
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,398 @@
+use scrypto::prelude::hash;
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::environment::{cached_entry_counts, clear_memory_caches};
+use crate::{TestEnvError, TestEnvironmentConfig, TestEnvironmentSnapshot};
+
+/// Set to `1` to disable all environment and package caching
+pub const NO_CACHE_ENV: &str = "TESTENV_NO_CACHE";
+
+/// Set to `1` to share cached environments between test binaries via the disk cache
+pub const DISK_CACHE_ENV: &str = "TESTENV_DISK_CACHE";
+
+// Lock files older than this are considered left over from a crashed process
+const STALE_LOCK_AGE: Duration = Duration::from_secs(600);
+
+static DISK_CACHE_ENABLED: AtomicBool = AtomicBool::new(false);
+static CACHE_DISABLED: AtomicBool = AtomicBool::new(false);
+static MAX_ENVIRONMENTS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// Serializes tests asserting cache hits and misses, since `clear` empties the caches
+// shared by all tests of the binary
+#[cfg(test)]
+static CACHE_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+static ENVIRONMENT_HITS: AtomicUsize = AtomicUsize::new(0);
+static ENVIRONMENT_MISSES: AtomicUsize = AtomicUsize::new(0);
+static PACKAGE_HITS: AtomicUsize = AtomicUsize::new(0);
+static PACKAGE_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Cache usage of this process so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub environment_hits: usize,
+    pub environment_misses: usize,
+    pub package_hits: usize,
+    pub package_misses: usize,
+    /// Environments currently held in memory (including packageless base environments)
+    pub cached_environments: usize,
+    pub cached_packages: usize,
+}
+
+pub fn stats() -> CacheStats {
+    let (cached_environments, cached_packages) = cached_entry_counts();
+    CacheStats {
+        environment_hits: ENVIRONMENT_HITS.load(Ordering::SeqCst),
+        environment_misses: ENVIRONMENT_MISSES.load(Ordering::SeqCst),
+        package_hits: PACKAGE_HITS.load(Ordering::SeqCst),
+        package_misses: PACKAGE_MISSES.load(Ordering::SeqCst),
+        cached_environments,
+        cached_packages,
+    }
+}
+
+pub(crate) fn record_environment_lookup(hit: bool) {
+    match hit {
+        true => ENVIRONMENT_HITS.fetch_add(1, Ordering::SeqCst),
+        false => ENVIRONMENT_MISSES.fetch_add(1, Ordering::SeqCst),
+    };
+}
+
+pub(crate) fn record_package_lookup(hit: bool) {
+    match hit {
+        true => PACKAGE_HITS.fetch_add(1, Ordering::SeqCst),
+        false => PACKAGE_MISSES.fetch_add(1, Ordering::SeqCst),
+    };
+}
+
+/// Caps the number of environments cached in memory, evicting the least recently used ones
+/// (`None` for no limit, the default)
+pub fn set_max_environments(max: Option<usize>) {
+    MAX_ENVIRONMENTS.store(max.unwrap_or(usize::MAX), Ordering::SeqCst);
+}
+
+pub(crate) fn max_environments() -> usize {
+    MAX_ENVIRONMENTS.load(Ordering::SeqCst)
+}
+
+/// Disables caching for this process: every TestEnvironment is generated, and every
+/// package compiled, from scratch. Alternatively set `TESTENV_NO_CACHE=1`.
+pub fn disable() {
+    CACHE_DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Re-enables caching after `disable` (has no effect if `TESTENV_NO_CACHE=1` is set)
+pub fn enable() {
+    CACHE_DISABLED.store(false, Ordering::SeqCst);
+}
+
+/// Drops all environments and compiled packages cached by this process (the disk cache is kept)
+pub fn clear() {
+    clear_memory_caches();
+}
+
+#[cfg(test)]
+pub(crate) fn lock_cache_for_test() -> MutexGuard<'static, ()> {
+    CACHE_TEST_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn cache_enabled() -> bool {
+    !CACHE_DISABLED.load(Ordering::SeqCst)
+        && !std::env::var(NO_CACHE_ENV).is_ok_and(|value| value == "1")
+}
+
+/// Enables the on-disk environment cache in `target/testenv-cache` for this process.
+/// Alternatively set `TESTENV_DISK_CACHE=1`.
+pub fn enable_disk_cache() {
+    DISK_CACHE_ENABLED.store(true, Ordering::SeqCst);
+}
+
+fn disk_cache_enabled() -> bool {
+    DISK_CACHE_ENABLED.load(Ordering::SeqCst)
+        || std::env::var(DISK_CACHE_ENV).is_ok_and(|value| value == "1")
+}
+
+fn cache_dir() -> PathBuf {
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
+    PathBuf::from(target_dir).join("testenv-cache")
+}
+
+/// Hash of the paths, sizes and modification times of all files of a package (except build
+/// output), changing whenever its source changes. Only file metadata is read, so fingerprinting
+/// stays cheap on cache hits.
+pub(crate) fn package_fingerprint(package_dir: &Path) -> u64 {
+    let mut hasher = StableHasher::default();
+    hash_dir(package_dir, package_dir, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_dir(root: &Path, dir: &Path, hasher: &mut StableHasher) {
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        let file_name = path.file_name().unwrap_or_default();
+        if file_name == "target" || file_name == ".git" {
+            continue;
+        }
+        if path.is_dir() {
+            hash_dir(root, &path, hasher);
+        } else if let Ok(metadata) = std::fs::metadata(&path) {
+            path.strip_prefix(root).unwrap_or(&path).hash(hasher);
+            metadata.len().hash(hasher);
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_nanos())
+                .hash(hasher);
+        }
+    }
+}
+
+/// Combined fingerprint of all packages of `config`
+pub(crate) fn packages_fingerprint(config: &TestEnvironmentConfig) -> u64 {
+    let mut hasher = StableHasher::default();
+    for package_dir in config.packages.values() {
+        package_fingerprint(package_dir).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Snapshot file of `config`, `None` if the disk cache is disabled or the config
+/// can't be cached across processes (simulator hooks are only comparable within a process)
+pub(crate) fn disk_cache_path(config: &TestEnvironmentConfig) -> Option<PathBuf> {
+    if !cache_enabled() || !disk_cache_enabled() || config.simulator_hook.is_some() {
+        return None;
+    }
+    Some(disk_cache_file(config))
+}
+
+fn disk_cache_file(config: &TestEnvironmentConfig) -> PathBuf {
+    let mut hasher = StableHasher::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    config.hash(&mut hasher);
+    packages_fingerprint(config).hash(&mut hasher);
+    cache_dir().join(format!("{}.snapshot", hasher.finish_hash()))
+}
+
+/// Hasher whose output stays the same across Rust releases, unlike the one of `DefaultHasher`,
+/// for keys persisted on disk: a Blake2b hash over all bytes fed by the `Hash` implementations
+#[derive(Default)]
+struct StableHasher {
+    bytes: Vec<u8>,
+}
+
+impl StableHasher {
+    fn finish_hash(&self) -> scrypto::prelude::Hash {
+        hash(&self.bytes)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut first_bytes = [0u8; 8];
+        first_bytes.copy_from_slice(&self.finish_hash().0[..8]);
+        u64::from_le_bytes(first_bytes)
+    }
+}
+
+/// Exclusive lock on a cache file across processes, released on drop
+pub(crate) struct DiskCacheLock {
+    lock_path: PathBuf,
+}
+
+impl DiskCacheLock {
+    pub(crate) fn acquire(path: &Path) -> Result<Self, TestEnvError> {
+        std::fs::create_dir_all(cache_dir())
+            .map_err(|error| disk_cache_failed(&cache_dir(), error))?;
+        let lock_path = path.with_extension("lock");
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(_) => return Ok(Self { lock_path }),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    let is_stale = std::fs::metadata(&lock_path)
+                        .and_then(|metadata| metadata.modified())
+                        .is_ok_and(|modified| {
+                            modified.elapsed().unwrap_or_default() > STALE_LOCK_AGE
+                        });
+                    if is_stale {
+                        let _ = std::fs::remove_file(&lock_path);
+                    } else {
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                Err(error) => return Err(disk_cache_failed(&lock_path, error)),
+            }
+        }
+    }
+}
+
+impl Drop for DiskCacheLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.lock_path);
+    }
+}
+
+pub(crate) fn load_from_disk(
+    path: &Path,
+    config: &TestEnvironmentConfig,
+) -> Option<TestEnvironmentSnapshot> {
+    match path.exists() {
+        true => Some(TestEnvironmentSnapshot::load(path, config.clone())),
+        false => None,
+    }
+}
+
+/// Writes via a temporary file, so other processes never read a partially written snapshot
+pub(crate) fn save_to_disk(
+    path: &Path,
+    snapshot: &TestEnvironmentSnapshot,
+) -> Result<(), TestEnvError> {
+    let temporary_path = path.with_extension("tmp");
+    snapshot
+        .write(&temporary_path)
+        .map_err(|error| disk_cache_failed(&temporary_path, error))?;
+    std::fs::rename(&temporary_path, path).map_err(|error| disk_cache_failed(path, error))
+}
+
+fn disk_cache_failed(path: &Path, error: std::io::Error) -> TestEnvError {
+    TestEnvError::DiskCacheFailed {
+        path: path.to_path_buf(),
+        error: error.to_string(),
+    }
+}
+
+#[test]
+fn test_disk_cache_file() {
+    let config = TestEnvironmentConfig::default();
+
+    let path = disk_cache_file(&config);
+
+    assert_eq!(disk_cache_file(&config), path);
+    assert!(path.starts_with(cache_dir()));
+    assert_ne!(
+        disk_cache_file(&TestEnvironmentConfig {
+            additional_accounts: 1,
+            ..Default::default()
+        }),
+        path
+    );
+}
+
+#[test]
+fn test_disk_cache_errors() {
+    use crate::TestEnvironment;
+
+    let path = cache_dir().join("missing").join("environment.snapshot");
+
+    assert!(matches!(
+        DiskCacheLock::acquire(&path),
+        Err(TestEnvError::DiskCacheFailed { .. })
+    ));
+    let snapshot =
+        TestEnvironment::new(std::collections::HashMap::<&str, &str>::new()).create_snapshot();
+    assert!(matches!(
+        save_to_disk(&path, &snapshot),
+        Err(TestEnvError::DiskCacheFailed { .. })
+    ));
+}
+
+#[test]
+fn test_stable_hasher() {
+    let mut hasher = StableHasher::default();
+    "abc".hash(&mut hasher);
+    assert_eq!(
+        hasher.finish_hash().to_string(),
+        "a8e931975e1836520484ccfeb090f274336c42e1a667b44e19771b114221ba8d"
+    );
+    assert_eq!(hasher.finish(), 0x5236185e9731e9a8);
+}
+
+#[test]
+fn test_package_fingerprint() {
+    let package_dir = std::env::temp_dir().join("testenv_test_package_fingerprint");
+    std::fs::create_dir_all(package_dir.join("src")).unwrap();
+    std::fs::create_dir_all(package_dir.join("target")).unwrap();
+    std::fs::write(package_dir.join("src/lib.rs"), "fn a() {}").unwrap();
+    let fingerprint = package_fingerprint(&package_dir);
+
+    std::fs::write(package_dir.join("target/output"), "ignored").unwrap();
+    assert_eq!(package_fingerprint(&package_dir), fingerprint);
+
+    std::fs::write(package_dir.join("src/lib.rs"), "fn changed() {}").unwrap();
+    assert_ne!(package_fingerprint(&package_dir), fingerprint);
+
+    std::fs::remove_dir_all(&package_dir).unwrap();
+}
+
+#[test]
+fn test_clear() {
+    use crate::TestEnvironmentBuilder;
+
+    let build = || {
+        TestEnvironmentBuilder::new()
+            .additional_accounts(13)
+            .build()
+    };
+    let _cache_guard = lock_cache_for_test();
+    build();
+    // With `TESTENV_NO_CACHE=1` every build is a miss
+    let expected_hits = match cache_enabled() {
+        true => 1,
+        false => 0,
+    };
+    assert_eq!(build().setup_timings.cache_hits, expected_hits);
+
+    clear();
+
+    assert_eq!(build().setup_timings.cache_misses, 1);
+}
+
+#[test]
+fn test_stats() {
+    use crate::TestEnvironmentBuilder;
+
+    let build = || {
+        TestEnvironmentBuilder::new()
+            .additional_accounts(17)
+            .build()
+    };
+    let _cache_guard = lock_cache_for_test();
+    build();
+    let stats_before = stats();
+
+    build();
+
+    let stats_after = stats();
+    match cache_enabled() {
+        true => {
+            assert!(stats_after.environment_hits > stats_before.environment_hits);
+            assert!(stats_after.cached_environments > 0);
+        }
+        false => {
+            assert!(stats_after.environment_misses > stats_before.environment_misses);
+            assert_eq!(stats_after.cached_environments, 0);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use radix_engine::blueprints::package::PackageDefinition;
+use scrypto::prelude::Level;
+use scrypto_compiler::{EnvironmentVariableAction, Profile, ScryptoCompiler};
+use std::path::Path;
+
+use crate::{CompilerOptions, TestEnvError};
+
+/// Compiles the package in `package_dir`, with the defaults of `LedgerSimulator::compile`
+/// unless `options` are customized
+pub(crate) fn compile(
+    package_dir: &Path,
+    options: &CompilerOptions,
+) -> Result<(Vec<u8>, PackageDefinition), TestEnvError> {
+    let mut compiler_builder = ScryptoCompiler::builder();
+    compiler_builder.manifest_path(package_dir.join("Cargo.toml"));
+    if *options == CompilerOptions::default() {
+        // Same as `CompileProfile::FastWithTraceLogs` used by `LedgerSimulator::compile`
+        compiler_builder
+            .optimize_with_wasm_opt(None)
+            .log_level(Level::Trace);
+    }
+    if options.debug {
+        compiler_builder.profile(Profile::Debug);
+    }
+    if options.coverage {
+        compiler_builder.coverage();
+    }
+    for feature in &options.features {
+        compiler_builder.feature(feature);
+    }
+    for (name, value) in &options.env_vars {
+        compiler_builder.env(name, EnvironmentVariableAction::Set(value.clone()));
+    }
+    // The compiler passes cargo's output through to the terminal, only its error is kept
+    let mut build_artifacts =
+        compiler_builder
+            .compile()
+            .map_err(|error| TestEnvError::CompilationFailed {
+                package_dir: package_dir.to_path_buf(),
+                error: format!("{:?}", error),
+            })?;
+    let build_artifacts = build_artifacts.remove(0);
+    Ok((
+        build_artifacts.wasm.content,
+        build_artifacts.package_definition.content,
+    ))
+}
+
+#[test]
+fn test_compilation_failed() {
+    let package_dir = Path::new("testdata/invalid");
+
+    match compile(package_dir, &CompilerOptions::default()) {
+        Err(TestEnvError::CompilationFailed {
+            package_dir: failed_package_dir,
+            error,
+        }) => {
+            assert_eq!(failed_package_dir, package_dir);
+            assert!(!error.is_empty());
+        }
+        _ => panic!("Expected the compilation of {:?} to fail", package_dir),
+    }
+}
@@ -3,6 +3,11 @@ use scrypto::prelude::*;
 // MAX_SUPPLY = 5708990770823839524233143877.797980545530986496
 pub const MAX_SUPPLY: Decimal = Decimal(I192::from_digits([0, 0, 16777216]));
 
+// Symbols of the edge case resources available in every TestEnvironment
+pub const MAX_SUPPLY_SYMBOL: &str = "MAXSUPPLY"; // MAX_SUPPLY with 18 decimals
+pub const ZERO_DIVISIBILITY_SYMBOL: &str = "NODIV"; // divisibility 0
+pub const SINGLE_ATTO_SYMBOL: &str = "ATTO"; // total supply of 1 atto
+
 #[test]
 fn test_max_supply() {
     assert_eq!(MAX_SUPPLY, Decimal(I192::from(2).pow(152)))
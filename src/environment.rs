@@ -1,22 +1,61 @@
 use radix_engine::{
-    blueprints::package::PackageDefinition,
-    system::system_modules::execution_trace::{ResourceSpecifier, WorktopChange},
-    transaction::TransactionReceipt,
+    blueprints::{
+        account::{DepositEvent as AccountDepositEvent, WithdrawEvent as AccountWithdrawEvent},
+        package::PackageDefinition,
+    },
+    errors::{RuntimeError, SystemModuleError},
+    system::{
+        bootstrap::GenesisDataChunk,
+        system_modules::{
+            auth::AuthError,
+            execution_trace::{ResourceSpecifier, WorktopChange},
+        },
+        type_info::{TypeInfoField, TypeInfoSubstate},
+    },
+    transaction::{
+        BalanceChange, ExecutionConfig, ExecutionCostBreakdownItem, TransactionOutcome,
+        TransactionReceipt, TransactionResult,
+    },
+    updates::{ProtocolUpdate, ProtocolUpdates},
     vm::NoExtension,
 };
 use radix_substate_store_impls::memory_db::InMemorySubstateDatabase;
-use radix_transactions::{builder::ManifestBuilder, prelude::*};
+use radix_substate_store_interface::{
+    db_key_mapper::SpreadPrefixKeyMapper,
+    interface::{CommittableSubstateDatabase, SubstateDatabase},
+};
+use radix_transactions::{
+    builder::ManifestBuilder,
+    manifest::{compile, decompile, BlobProvider},
+    prelude::*,
+};
+use regex::Regex;
 use scrypto::prelude::*;
 use scrypto_test::ledger_simulator::{
     CustomGenesis, LedgerSimulator, LedgerSimulatorBuilder, LedgerSimulatorSnapshot,
 };
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 use std::{
+    cell::OnceCell,
     mem,
+    ops::DerefMut,
     path::{Path, PathBuf},
 };
 
-use crate::MAX_SUPPLY;
+use crate::cache::{
+    cache_enabled, disk_cache_path, load_from_disk, max_environments, package_fingerprint,
+    packages_fingerprint, record_environment_lookup, record_package_lookup, save_to_disk,
+    DiskCacheLock,
+};
+use crate::compiler::compile;
+use crate::timings::record_setup_timings;
+use crate::workspace::compile_workspace;
+use crate::{
+    BenchReport, RecordedEvent, SetupTimings, TestEnvError, MAX_SUPPLY, MAX_SUPPLY_SYMBOL,
+    SINGLE_ATTO_SYMBOL, ZERO_DIVISIBILITY_SYMBOL,
+};
 
 #[macro_export]
 macro_rules! nft_id {
@@ -38,22 +77,63 @@ macro_rules! nft_ids {
     };
 }
 
+// XRD handed out per faucet call (only one call per transaction is allowed)
+const FAUCET_FREE_XRD: Decimal = dec!(10000);
+
+// Key of the account collecting surplus faucet XRD, the simulator hands out keys counting up from 1
+const FAUCET_SINK_KEY: u64 = u64::MAX;
+
+// Epochs a transaction notarized only to carry a tip stays valid for
+const TIP_EPOCH_RANGE: u64 = 10;
+
 const INSTRUCTION_COUNTER_INIT: usize = 1; // lock_standard_test_fee will be added always as first instruction automatically
 
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
 
 type CompiledPackage = (Vec<u8>, PackageDefinition);
 
+// Cache keys include a fingerprint of the package sources, so source changes within
+// a long-running process (e.g. under `cargo watch`) invalidate cached entries
+type PackageCacheKey = (PathBuf, u64);
+
+// Bounds concurrent cargo builds, which are CPU and memory hungry themselves
+const MAX_PARALLEL_COMPILATIONS: usize = 4;
+
+// Environment variable the engine writes the coverage data of instrumented blueprints to
+const COVERAGE_DIRECTORY: &str = "COVERAGE_DIRECTORY";
+type EnvironmentCacheKey = (TestEnvironmentConfig, u64);
+
+pub type SimulatorBuilder = LedgerSimulatorBuilder<NoExtension, InMemorySubstateDatabase>;
+
 lazy_static! {
-    static ref TEST_ENVIRONMENT_CACHE: RwLock<HashMap<BTreeSet<PathBuf>, TestEnvironmentSnapshot>> =
+    static ref TEST_ENVIRONMENT_CACHE: RwLock<HashMap<EnvironmentCacheKey, CachedEnvironment>> =
         RwLock::new(HashMap::new());
-    static ref PACKAGE_CACHE: RwLock<HashMap<PathBuf, CompiledPackage>> =
+    static ref PACKAGE_CACHE: RwLock<HashMap<PackageCacheKey, CompiledPackage>> =
         RwLock::new(HashMap::new());
+    // Per cache entry locks, held while the entry is generated
+    static ref ENVIRONMENT_GENERATION_LOCKS: GenerationLocks<EnvironmentCacheKey> =
+        Mutex::new(HashMap::new());
+    static ref PACKAGE_COMPILATION_LOCKS: GenerationLocks<PackageCacheKey> =
+        Mutex::new(HashMap::new());
+}
+
+type GenerationLocks<K> = Mutex<HashMap<K, Arc<Mutex<()>>>>;
+
+struct CachedEnvironment {
+    snapshot: TestEnvironmentSnapshot,
+    // Tick of the last lookup, for evicting the least recently used environment
+    last_used: AtomicU64,
 }
 
+static CACHE_TICK: AtomicU64 = AtomicU64::new(0);
+
 fn get_cache<K: Hash + Eq, V: Clone>(cache: &RwLock<HashMap<K, V>>, key: &K) -> Option<V> {
+    if !cache_enabled() {
+        return None;
+    }
     let read_lock = cache.read().unwrap();
     match read_lock.get(key) {
         Some(state) => Some(state.clone()),
@@ -62,29 +142,806 @@ fn get_cache<K: Hash + Eq, V: Clone>(cache: &RwLock<HashMap<K, V>>, key: &K) ->
 }
 
 // Optimized getter for TEST_ENVIRONMENT_CACHE, avoids unnecessary clone with direct revive
-fn get_cache_test_environment(key: &BTreeSet<PathBuf>) -> Option<TestEnvironment> {
+fn get_cache_test_environment(key: &EnvironmentCacheKey) -> Option<TestEnvironment> {
+    if !cache_enabled() {
+        return None;
+    }
     let read_lock = TEST_ENVIRONMENT_CACHE.read().unwrap();
     match read_lock.get(key) {
-        Some(snapshot) => Some(snapshot.revive()),
+        Some(cached) => {
+            let tick = CACHE_TICK.fetch_add(1, Ordering::Relaxed);
+            cached.last_used.store(tick, Ordering::Relaxed);
+            Some(cached.snapshot.revive())
+        }
         None => None,
     }
 }
 
+// Writes to TEST_ENVIRONMENT_CACHE, evicting the least recently used environments
+// if the cache grows beyond `cache::set_max_environments`
+fn write_cache_test_environment(key: EnvironmentCacheKey, snapshot: TestEnvironmentSnapshot) {
+    let cached = CachedEnvironment {
+        snapshot,
+        last_used: AtomicU64::new(CACHE_TICK.fetch_add(1, Ordering::Relaxed)),
+    };
+    write_cache(&TEST_ENVIRONMENT_CACHE, key, cached);
+
+    let mut write_lock = TEST_ENVIRONMENT_CACHE.write().unwrap();
+    evict_least_recently_used(&mut write_lock, max_environments());
+}
+
+fn evict_least_recently_used<K: Hash + Eq + Clone>(
+    cache: &mut HashMap<K, CachedEnvironment>,
+    max_environments: usize,
+) {
+    while cache.len() > max_environments {
+        let least_recently_used = cache
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used.load(Ordering::Relaxed))
+            .map(|(key, _)| key.clone())
+            .unwrap();
+        cache.remove(&least_recently_used);
+    }
+}
+
+pub(crate) fn cached_entry_counts() -> (usize, usize) {
+    (
+        TEST_ENVIRONMENT_CACHE.read().unwrap().len(),
+        PACKAGE_CACHE.read().unwrap().len(),
+    )
+}
+
 fn write_cache<K: Hash + Eq + Clone, V>(cache: &RwLock<HashMap<K, V>>, key: K, value: V) {
+    if !cache_enabled() {
+        return;
+    }
     let mut write_lock = cache.write().unwrap();
     write_lock.entry(key).or_insert(value);
 }
 
-// OPTIMIZE: can be optimized in the future by checking whether a test_environment is being generated,
-// even if it was found not to exist, avoiding concurrent generation of the same environment,
-// and then discarding one of the copies after the first one is written.
-// Two possible approaches:
-// 1) Creating two static RwLock Hashmap variables, one for PACKAGE_CACHE and the other for TEST_ENVIRONMENT_CACHE
-//    That would state, for each possible cache entry, whether it is being generated or not
-// 2) Instead of the cache variables holding the final object, they would be wrapped in a
-//    new CachedObject struct, that has as fields and Option<T> and a bool "generation"
-//    that would be set to true when a thread starts to generate that object
+pub(crate) fn clear_memory_caches() {
+    TEST_ENVIRONMENT_CACHE.write().unwrap().clear();
+    PACKAGE_CACHE.write().unwrap().clear();
+}
+
+// Lock ensuring only one thread generates the cache entry of `key`, while the others wait
+// and then use the cached entry instead of generating the same entry concurrently.
+// `None` if caching is disabled, since nothing could be shared anyways.
+fn generation_lock<K: Hash + Eq + Clone>(
+    locks: &'static GenerationLocks<K>,
+    key: &K,
+) -> Option<GenerationLock<K>> {
+    if !cache_enabled() {
+        return None;
+    }
+    let mut locks_guard = locks.lock().unwrap_or_else(PoisonError::into_inner);
+    Some(GenerationLock {
+        locks,
+        key: key.clone(),
+        lock: locks_guard.entry(key.clone()).or_default().clone(),
+    })
+}
+
+// Removes its entry from `locks` when dropped by the last thread using it,
+// so the lock maps don't grow with every generated entry
+struct GenerationLock<K: Hash + Eq + 'static> {
+    locks: &'static GenerationLocks<K>,
+    key: K,
+    lock: Arc<Mutex<()>>,
+}
+
+impl<K: Hash + Eq + 'static> Drop for GenerationLock<K> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap_or_else(PoisonError::into_inner);
+        // Only the map and this handle reference the lock, no thread is waiting for it
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+// A panicking generation leaves the entry uncached, so the next waiting thread simply retries
+fn lock_generation<K: Hash + Eq>(generation_lock: &GenerationLock<K>) -> MutexGuard<'_, ()> {
+    generation_lock
+        .lock
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Hook customizing the underlying LedgerSimulatorBuilder, see `TestEnvironmentBuilder::configure_simulator`
+///
+/// Only plain function pointers (or non-capturing closures) are supported, since they are
+/// part of the environment cache key and need to be comparable.
+#[derive(Clone, Copy)]
+pub struct SimulatorHook(pub fn(SimulatorBuilder) -> SimulatorBuilder);
+
+// Compare by function address: two identical hooks with different addresses only cause a cache miss
+impl PartialEq for SimulatorHook {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 as usize == other.0 as usize
+    }
+}
+
+impl Eq for SimulatorHook {}
+
+impl Hash for SimulatorHook {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0 as usize).hash(state);
+    }
+}
+
+impl std::fmt::Debug for SimulatorHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SimulatorHook({:#x})", self.0 as usize)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FungibleConfig {
+    pub symbol: String,
+    pub supply: Decimal,
+    pub divisibility: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NonFungibleConfig {
+    pub symbol: String,
+    /// Number of NFTs minted with integer ids starting at 1
+    pub count: u64,
+}
+
+/// Amount of a resource (by symbol) transferred from the main account to an additional account
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BalanceConfig {
+    pub account_index: usize,
+    pub symbol: String,
+    pub amount: Decimal,
+}
+
+/// Options for compiling packages, see `TestEnvironmentBuilder::compiler_options`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompilerOptions {
+    /// Cargo features enabled for every package, e.g. feature gated test hooks
+    pub features: Vec<String>,
+    /// Builds with the debug instead of the release profile
+    pub debug: bool,
+    /// Environment variables set for the compilation
+    pub env_vars: BTreeMap<String, String>,
+    /// Instruments the code for coverage reports, see `TestEnvironmentBuilder::coverage`
+    pub coverage: bool,
+}
+
+/// Owner role of a published package, see `TestEnvironmentBuilder::package_owner_role`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageOwnerRole(pub OwnerRole);
+
+// OwnerRole is not hashable, so its SBOR encoding is hashed instead
+impl Hash for PackageOwnerRole {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        scrypto_encode(&self.0).unwrap().hash(state);
+    }
+}
+
+/// Initial metadata of a published package, see `TestEnvironmentBuilder::package_metadata`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageMetadata {
+    pub entries: BTreeMap<String, MetadataValue>,
+    /// Sets `dapp_definition` to the environment's dapp definition account
+    pub link_dapp_definition: bool,
+}
+
+impl Hash for PackageMetadata {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        scrypto_encode(&self.entries).unwrap().hash(state);
+        self.link_dapp_definition.hash(state);
+    }
+}
+
+fn collect_coverage_files(dir: &Path, coverage_files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() {
+            collect_coverage_files(&path, coverage_files);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "profraw")
+        {
+            coverage_files.push(path);
+        }
+    }
+}
+
+/// Package address derived from `package_name` only, identical across test runs
+pub fn deterministic_package_address(package_name: &str) -> PackageAddress {
+    let mut node_id = [0u8; NodeId::LENGTH];
+    node_id[0] = EntityType::GlobalPackage as u8;
+    node_id[1..].copy_from_slice(&hash(package_name.as_bytes()).0[..NodeId::LENGTH - 1]);
+    PackageAddress::new_or_panic(node_id)
+}
+
+/// XRD royalty charged for calling `function_name` (function or method) of a published package
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PackageRoyalty {
+    pub package_name: String,
+    pub blueprint_name: String,
+    pub function_name: String,
+    pub xrd: Decimal,
+}
+
+/// Describes how a TestEnvironment is set up and serves as its cache key
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TestEnvironmentConfig {
+    pub packages: BTreeMap<String, PathBuf>,
+    /// Royalties attached to the package definitions before publishing
+    pub package_royalties: Vec<PackageRoyalty>,
+    /// Owner roles of packages, `OwnerRole::Updatable(rule!(require(admin_badge)))` if not set
+    pub package_owner_roles: BTreeMap<String, PackageOwnerRole>,
+    pub package_metadata: BTreeMap<String, PackageMetadata>,
+    pub compiler_options: CompilerOptions,
+    /// Directory the coverage data of executed blueprints is written to, see `COVERAGE_DIRECTORY`
+    pub coverage_dir: Option<PathBuf>,
+    /// Cargo workspace containing the packages, compiled at once instead of package by package
+    pub workspace: Option<PathBuf>,
+    /// Packages published at a preallocated address instead of a freshly derived one
+    pub package_preallocated_addresses: BTreeMap<String, PackageAddress>,
+    pub simulator_hook: Option<SimulatorHook>,
+    pub fungibles: Vec<FungibleConfig>,
+    pub non_fungibles: Vec<NonFungibleConfig>,
+    pub additional_accounts: usize,
+    pub balances: Vec<BalanceConfig>,
+    pub ed25519_accounts: bool,
+    /// Creates the edge case resources (D0, D6, recallable, String/Bytes/RUID NFTs and the
+    /// MAXSUPPLY, NODIV and ATTO tokens), see `TestEnvironmentBuilder::extra_resources`
+    pub extra_resources: bool,
+    /// Number of NFTs minted into each of the default J/K collections (3 if not set)
+    pub nft_collection_size: Option<usize>,
+    pub badges: Vec<String>,
+    pub kernel_trace: bool,
+    pub skip_receipt_substate_check: bool,
+    /// Protocol version the ledger is bootstrapped at (latest if not set)
+    pub protocol_version: Option<ProtocolVersion>,
+    pub genesis: GenesisConfig,
+    /// Wall-clock length of an epoch for `TestEnvironment::advance_time` (5 minutes if not set)
+    pub epoch_duration: Option<Duration>,
+    /// Keys are derived from a counter, a non-zero seed derives a different set of keys
+    pub key_seed: u64,
+    /// Publishes packages at addresses derived from their names, see `TestEnvironmentBuilder::deterministic`
+    pub deterministic: bool,
+}
+
+/// Ledger state at genesis, before the baseline snapshot is taken
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GenesisConfig {
+    pub epoch: u64,
+    pub initial_time_ms: i64,
+    /// Epochs change after exactly this many rounds
+    pub rounds_per_epoch: u64,
+    pub num_unstake_epochs: u64,
+    pub total_emission_xrd_per_epoch: Decimal,
+    /// XRD allocated to (e.g. preallocated) accounts at genesis
+    pub xrd_balances: Vec<(ComponentAddress, Decimal)>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        let consensus_config = CustomGenesis::default_consensus_manager_config();
+        Self {
+            epoch: 1,
+            initial_time_ms: 0,
+            rounds_per_epoch: consensus_config.epoch_change_condition.max_round_count,
+            num_unstake_epochs: consensus_config.num_unstake_epochs,
+            total_emission_xrd_per_epoch: consensus_config.total_emission_xrd_per_epoch,
+            xrd_balances: vec![],
+        }
+    }
+}
+
+impl GenesisConfig {
+    fn custom_genesis(&self) -> CustomGenesis {
+        let mut consensus_config = CustomGenesis::default_consensus_manager_config();
+        consensus_config.epoch_change_condition.min_round_count = self.rounds_per_epoch;
+        consensus_config.epoch_change_condition.max_round_count = self.rounds_per_epoch;
+        consensus_config.num_unstake_epochs = self.num_unstake_epochs;
+        consensus_config.total_emission_xrd_per_epoch = self.total_emission_xrd_per_epoch;
+
+        let mut custom_genesis = CustomGenesis::default(Epoch::of(self.epoch), consensus_config);
+        custom_genesis.initial_time_ms = self.initial_time_ms;
+        if !self.xrd_balances.is_empty() {
+            custom_genesis
+                .genesis_data_chunks
+                .push(GenesisDataChunk::XrdBalances(self.xrd_balances.clone()));
+        }
+        custom_genesis
+    }
+}
+
+/// Protocol versions supported by the simulator of this Scrypto version
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    Babylon,
+    Anemone,
+    Bottlenose,
+}
+
+impl ProtocolVersion {
+    fn protocol_updates(&self) -> ProtocolUpdates {
+        match self {
+            ProtocolVersion::Babylon => ProtocolUpdates::none(),
+            ProtocolVersion::Anemone => ProtocolUpdates::up_to_anemone(),
+            ProtocolVersion::Bottlenose => ProtocolUpdates::up_to_bottlenose(),
+        }
+    }
+
+    /// Protocol update enacting this version (`None` for the genesis version Babylon)
+    pub fn protocol_update(&self) -> Option<ProtocolUpdate> {
+        match self {
+            ProtocolVersion::Babylon => None,
+            ProtocolVersion::Anemone => Some(ProtocolUpdate::Anemone),
+            ProtocolVersion::Bottlenose => Some(ProtocolUpdate::Bottlenose),
+        }
+    }
+}
+
+impl TestEnvironmentConfig {
+    /// Config of the same environment but without any packages published
+    fn without_packages(&self) -> Self {
+        Self {
+            packages: BTreeMap::new(),
+            package_royalties: vec![],
+            package_owner_roles: BTreeMap::new(),
+            package_metadata: BTreeMap::new(),
+            package_preallocated_addresses: BTreeMap::new(),
+            workspace: None,
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn simulator_builder(&self) -> SimulatorBuilder {
+        let simulator_builder =
+            LedgerSimulatorBuilder::new().with_custom_genesis(self.genesis.custom_genesis());
+        let simulator_builder = match self.kernel_trace {
+            true => simulator_builder.with_kernel_trace(),
+            false => simulator_builder.without_kernel_trace(),
+        };
+        let simulator_builder = match self.skip_receipt_substate_check {
+            true => simulator_builder.without_receipt_substate_check(),
+            false => simulator_builder,
+        };
+        let simulator_builder = match self.protocol_version {
+            Some(protocol_version) => {
+                simulator_builder.with_custom_protocol_updates(protocol_version.protocol_updates())
+            }
+            None => simulator_builder,
+        };
+        match self.simulator_hook {
+            Some(hook) => (hook.0)(simulator_builder),
+            None => simulator_builder,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TestEnvironmentBuilder {
+    config: TestEnvironmentConfig,
+}
+
+impl TestEnvironmentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn package<T: AsRef<Path>>(mut self, package_name: &str, package_dir: T) -> Self {
+        self.config
+            .packages
+            .insert(package_name.to_string(), package_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn packages<T: AsRef<Path>>(mut self, packages: HashMap<&str, T>) -> Self {
+        for (package_name, package_dir) in packages {
+            self = self.package(package_name, package_dir);
+        }
+        self
+    }
+
+    /// Publishes the package `package_name` with `owner_role` instead of the admin badge
+    /// as updatable owner, e.g. `OwnerRole::None` for tests of immutable packages
+    pub fn package_owner_role(mut self, package_name: &str, owner_role: OwnerRole) -> Self {
+        self.config
+            .package_owner_roles
+            .insert(package_name.to_string(), PackageOwnerRole(owner_role));
+        self
+    }
+
+    /// Publishes the package `package_name` with the metadata entry `key`, e.g. `name` or `description`
+    pub fn package_metadata<V: ToMetadataEntry>(
+        mut self,
+        package_name: &str,
+        key: &str,
+        value: V,
+    ) -> Self {
+        if let Some(value) = value.to_metadata_entry() {
+            self.config
+                .package_metadata
+                .entry(package_name.to_string())
+                .or_default()
+                .entries
+                .insert(key.to_string(), value);
+        }
+        self
+    }
+
+    /// Publishes the package `package_name` with its `dapp_definition` metadata pointing to
+    /// the environment's dapp definition account
+    pub fn link_package_to_dapp_definition(mut self, package_name: &str) -> Self {
+        self.config
+            .package_metadata
+            .entry(package_name.to_string())
+            .or_default()
+            .link_dapp_definition = true;
+        self
+    }
+
+    /// Compiles the packages with `options` instead of the defaults of `LedgerSimulator::compile`
+    /// (not applied to workspace builds, see `workspace`)
+    pub fn compiler_options(mut self, options: CompilerOptions) -> Self {
+        self.config.compiler_options = options;
+        self
+    }
+
+    /// Compiles the packages with coverage instrumentation. The engine writes the coverage data
+    /// of every execution into the directory in `COVERAGE_DIRECTORY` (one directory per
+    /// blueprint), which has to be set for the test run like `scrypto coverage` does, e.g.
+    /// `COVERAGE_DIRECTORY=target/coverage cargo +nightly test --features coverage`.
+    /// Requires a nightly toolchain with `llvm-tools`, reports are generated from the `.profraw`
+    /// files via `llvm-profdata merge` and `llvm-cov report`.
+    #[cfg(feature = "coverage")]
+    pub fn coverage(mut self) -> Self {
+        self.config.compiler_options.coverage = true;
+        self
+    }
+
+    /// Compiles all packages missing in the package cache with a single build of the Cargo
+    /// workspace at `workspace_dir`, instead of compiling each package on its own.
+    /// The results are handed over via the package cache, so caching must not be disabled.
+    pub fn workspace<T: AsRef<Path>>(mut self, workspace_dir: T) -> Self {
+        self.config.workspace = Some(workspace_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Publishes the package `package_name` at `address`, e.g. `deterministic_package_address(name)`,
+    /// for blueprints with hardcoded references to other packages.
+    /// The owner role and metadata configured for the package are applied as usual.
+    pub fn package_at_address(mut self, package_name: &str, address: PackageAddress) -> Self {
+        self.config
+            .package_preallocated_addresses
+            .insert(package_name.to_string(), address);
+        self
+    }
+
+    /// Charges `xrd` royalty for every call of `function_name` of `blueprint_name` in the
+    /// package `package_name`. All other functions of the blueprint stay free.
+    pub fn package_royalty(
+        mut self,
+        package_name: &str,
+        blueprint_name: &str,
+        function_name: &str,
+        xrd: Decimal,
+    ) -> Self {
+        self.config.package_royalties.push(PackageRoyalty {
+            package_name: package_name.to_string(),
+            blueprint_name: blueprint_name.to_string(),
+            function_name: function_name.to_string(),
+            xrd,
+        });
+        self
+    }
+
+    /// Customizes the underlying LedgerSimulatorBuilder, e.g. custom protocol updates or
+    /// receipt substate checks, after the defaults of this crate have been applied:
+    /// ```ignore
+    /// TestEnvironmentBuilder::new()
+    ///     .configure_simulator(|builder| builder.with_kernel_trace())
+    ///     .build();
+    /// ```
+    /// The hook is applied again whenever the environment is revived from a snapshot.
+    pub fn configure_simulator(mut self, hook: fn(SimulatorBuilder) -> SimulatorBuilder) -> Self {
+        self.config.simulator_hook = Some(SimulatorHook(hook));
+        self
+    }
+
+    /// Prints the kernel trace of every executed transaction (off by default)
+    pub fn with_kernel_trace(mut self) -> Self {
+        self.config.kernel_trace = true;
+        self
+    }
+
+    /// Skips the simulator's consistency check of receipt substate changes,
+    /// speeding up tests executing many or large transactions
+    pub fn without_receipt_substate_check(mut self) -> Self {
+        self.config.skip_receipt_substate_check = true;
+        self
+    }
+
+    /// Creates the edge case resources during setup: the D0 and D6 tokens, the recallable token,
+    /// the String/Bytes/RUID NFT collections and the tokens registered under `MAX_SUPPLY_SYMBOL`,
+    /// `ZERO_DIVISIBILITY_SYMBOL` and `SINGLE_ATTO_SYMBOL`. Without it their fields are `None`.
+    pub fn extra_resources(mut self) -> Self {
+        self.config.extra_resources = true;
+        self
+    }
+
+    /// Customizes the genesis (initial epoch, consensus config, XRD allocations)
+    pub fn genesis(mut self, genesis: GenesisConfig) -> Self {
+        self.config.genesis = genesis;
+        self
+    }
+
+    /// Wall-clock length of an epoch used by `TestEnvironment::advance_time`
+    pub fn epoch_duration(mut self, epoch_duration: Duration) -> Self {
+        self.config.epoch_duration = Some(epoch_duration);
+        self
+    }
+
+    /// Account keys (and addresses) are always reproducible, derived from a counter.
+    /// Seeding derives the keys from the seed and the counter instead, yielding a different
+    /// but still reproducible set of keys for every seed.
+    pub fn key_seed(mut self, seed: u64) -> Self {
+        self.config.key_seed = seed;
+        self
+    }
+
+    /// Keeps all addresses identical across runs and machines: keys only depend on `key_seed`,
+    /// packages are published in name order, and at `deterministic_package_address(name)`
+    /// (unless set via `package_at_address`), since compiler output differs between toolchains.
+    /// Upgraded packages are published at `deterministic_package_address("{name}@{version}")`.
+    pub fn deterministic(mut self) -> Self {
+        self.config.deterministic = true;
+        self
+    }
+
+    /// Bootstraps the ledger at an older protocol version, e.g. the one currently live on mainnet
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.config.protocol_version = Some(protocol_version);
+        self
+    }
+
+    /// Creates an additional fungible resource registered under `symbol`.
+    /// The whole supply is deposited into the main account.
+    pub fn fungible(mut self, symbol: &str, supply: Decimal, divisibility: u8) -> Self {
+        self.config.fungibles.push(FungibleConfig {
+            symbol: symbol.to_string(),
+            supply,
+            divisibility,
+        });
+        self
+    }
+
+    /// Creates an additional non fungible resource registered under `symbol` with
+    /// `count` NFTs (integer ids 1 to count) deposited into the main account
+    pub fn non_fungible(mut self, symbol: &str, count: u64) -> Self {
+        self.config.non_fungibles.push(NonFungibleConfig {
+            symbol: symbol.to_string(),
+            count,
+        });
+        self
+    }
+
+    /// Number of accounts created in addition to the main account, see `TestEnvironment::accounts`
+    pub fn additional_accounts(mut self, count: usize) -> Self {
+        self.config.additional_accounts = count;
+        self
+    }
+
+    /// Creates all additional accounts with Ed25519 instead of Secp256k1 keys.
+    /// The main account keeps its Secp256k1 key, see `TestEnvironment::public_key`.
+    pub fn ed25519_accounts(mut self) -> Self {
+        self.config.ed25519_accounts = true;
+        self
+    }
+
+    /// Number of integer id NFTs (1 to size) minted into each of the default J/K collections,
+    /// e.g. for stress tests depositing and withdrawing many NFTs
+    pub fn nft_collection_size(mut self, size: usize) -> Self {
+        self.config.nft_collection_size = Some(size);
+        self
+    }
+
+    /// Creates an additional badge (supply 1, indivisible) in the main account besides the
+    /// admin badge, e.g. `moderator_badge`, see `TestEnvironment::badge`
+    pub fn badge(mut self, name: &str) -> Self {
+        self.config.badges.push(name.to_string());
+        self
+    }
+
+    /// Transfers `amount` of the resource registered under `symbol` from the main account
+    /// to the additional account with index `account_index`
+    pub fn balance(mut self, account_index: usize, symbol: &str, amount: Decimal) -> Self {
+        self.config.balances.push(BalanceConfig {
+            account_index,
+            symbol: symbol.to_string(),
+            amount,
+        });
+        self
+    }
+
+    pub fn build(self) -> TestEnvironment {
+        self.try_build().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `build`, but returns an error if a package fails to compile, a balance is configured
+    /// for an account or resource that isn't created, a royalty for a missing blueprint, or
+    /// the disk cache can't be accessed
+    pub fn try_build(mut self) -> Result<TestEnvironment, TestEnvError> {
+        if let Some(balance) = self
+            .config
+            .balances
+            .iter()
+            .find(|balance| balance.account_index >= self.config.additional_accounts)
+        {
+            return Err(TestEnvError::SetupFailed(format!(
+                "Balance of {:?} configured for account {}, but only {} additional accounts are created",
+                balance.symbol, balance.account_index, self.config.additional_accounts
+            )));
+        }
+        if self.config.compiler_options.coverage {
+            // The engine has no setting for the coverage directory, it only reads the variable
+            let coverage_dir = std::env::var_os(COVERAGE_DIRECTORY).ok_or_else(|| {
+                TestEnvError::SetupFailed(format!(
+                    "Coverage requires {} to be set for the test run",
+                    COVERAGE_DIRECTORY
+                ))
+            })?;
+            self.config.coverage_dir = Some(PathBuf::from(coverage_dir));
+        }
+        TestEnvironment::from_config(self.config)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, ScryptoSbor)]
+pub struct TestAccount {
+    /// Key of the account, Secp256k1 or Ed25519
+    pub key: PublicKey,
+    // Private keys are not cloneable, so only their bytes are kept to support snapshots
+    private_key_bytes: Vec<u8>,
+    pub address: ComponentAddress,
+}
+
+impl TestAccount {
+    fn new(private_key: PrivateKey, address: ComponentAddress) -> Self {
+        let private_key_bytes = match &private_key {
+            PrivateKey::Secp256k1(private_key) => private_key.to_bytes(),
+            PrivateKey::Ed25519(private_key) => private_key.to_bytes(),
+        };
+        Self {
+            key: private_key.public_key(),
+            private_key_bytes,
+            address,
+        }
+    }
+
+    /// Allocated account for Secp256k1 keys, virtual account for Ed25519 keys
+    fn create(
+        test_runner: &mut LedgerSimulator<NoExtension, InMemorySubstateDatabase>,
+        private_key: PrivateKey,
+    ) -> Self {
+        let address = match private_key.public_key() {
+            PublicKey::Secp256k1(public_key) => {
+                let owner_rule = rule!(require(NonFungibleGlobalId::from_public_key(&public_key)));
+                test_runner.new_account_advanced(OwnerRole::Fixed(owner_rule))
+            }
+            PublicKey::Ed25519(public_key) => {
+                ComponentAddress::virtual_account_from_public_key(&public_key)
+            }
+        };
+        Self::new(private_key, address)
+    }
+
+    /// Secp256k1 key of the account, panics for Ed25519 accounts (see `key`)
+    pub fn public_key(&self) -> Secp256k1PublicKey {
+        match self.key {
+            PublicKey::Secp256k1(public_key) => public_key,
+            PublicKey::Ed25519(_) => panic!("{:?} is an Ed25519 account", self.address),
+        }
+    }
+
+    pub fn private_key(&self) -> PrivateKey {
+        match self.key {
+            PublicKey::Secp256k1(_) => PrivateKey::Secp256k1(
+                Secp256k1PrivateKey::from_bytes(&self.private_key_bytes).unwrap(),
+            ),
+            PublicKey::Ed25519(_) => {
+                PrivateKey::Ed25519(Ed25519PrivateKey::from_bytes(&self.private_key_bytes).unwrap())
+            }
+        }
+    }
+
+    /// Virtual signature badge of the account's key, used as initial proof when executing
+    pub fn global_id(&self) -> NonFungibleGlobalId {
+        NonFungibleGlobalId::from_public_key(&self.key)
+    }
+}
+
+/// Hands out the keys of the environment, reproducible for the same seed
+#[derive(Clone, Debug, PartialEq, Eq, ScryptoSbor)]
+pub(crate) struct KeyGenerator {
+    seed: u64,
+    next_index: u64,
+}
+
+impl KeyGenerator {
+    fn new(seed: u64) -> Self {
+        // Same keys as the simulator hands out without a seed, which counts up from 1
+        Self {
+            seed,
+            next_index: 1,
+        }
+    }
+
+    // Unseeded keys are the index itself (like `Secp256k1PrivateKey::from_u64`),
+    // seeded keys a hash of seed and index, so any seed takes constant time
+    fn next_key_bytes(&mut self) -> [u8; 32] {
+        let index = self.next_index;
+        self.next_index += 1;
+        match self.seed {
+            0 => {
+                let mut key_bytes = [0u8; 32];
+                key_bytes[24..].copy_from_slice(&index.to_be_bytes());
+                key_bytes
+            }
+            seed => hash(scrypto_encode(&(seed, index)).unwrap()).0,
+        }
+    }
+
+    fn next_key(&mut self, ed25519: bool) -> PrivateKey {
+        match ed25519 {
+            true => {
+                PrivateKey::Ed25519(Ed25519PrivateKey::from_bytes(&self.next_key_bytes()).unwrap())
+            }
+            false => PrivateKey::Secp256k1(self.next_secp256k1_key()),
+        }
+    }
+
+    fn next_secp256k1_key(&mut self) -> Secp256k1PrivateKey {
+        Secp256k1PrivateKey::from_bytes(&self.next_key_bytes()).unwrap()
+    }
+}
+
+/// Component locking the fee for transactions run by `TestHelperExecution::execute`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeePayer {
+    /// The account executing the transaction (main account unless switched via `set_active_account`)
+    #[default]
+    ActiveAccount,
+    /// Another account created by the environment, which additionally signs the transaction
+    Account(ComponentAddress),
+    /// Any component providing a `lock_fee(amount: Decimal)` method, e.g. the faucet
+    Component(ComponentAddress),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeConfig {
+    pub payer: FeePayer,
+    pub amount: Decimal,
+    /// Tip on top of the execution and finalization costs. Transactions are notarized (with an
+    /// epoch range starting at the current epoch) if set, since test transactions never tip.
+    pub tip_percentage: u16,
+}
+
+impl Default for FeeConfig {
+    /// Same as `lock_standard_test_fee`
+    fn default() -> Self {
+        Self {
+            payer: FeePayer::ActiveAccount,
+            amount: dec!(5000),
+            tip_percentage: 0,
+        }
+    }
+}
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TestAddress {
     A,
     B,
@@ -92,6 +949,10 @@ pub enum TestAddress {
     Y,
     U,
     V,
+    J,
+    K,
+    D0,
+    D6,
 }
 
 pub struct TestEnvironment {
@@ -99,52 +960,140 @@ pub struct TestEnvironment {
     pub manifest_builder: ManifestBuilder,
 
     pub package_addresses: HashMap<String, PackageAddress>,
+    /// Addresses of packages replaced by `upgrade_package`, oldest first
+    pub previous_package_addresses: HashMap<String, Vec<PackageAddress>>,
+    /// Definitions of the currently published packages, see `blueprint_functions`
+    pub(crate) package_definitions: HashMap<String, PackageDefinition>,
     pub public_key: Secp256k1PublicKey,
     pub account: ComponentAddress,
     pub dapp_definition: ComponentAddress,
+    pub accounts: Vec<TestAccount>,
+    pub accounts_by_label: HashMap<String, TestAccount>,
+    pub identities_by_label: HashMap<String, ComponentAddress>,
+    // Keys of the main account, needed for notarized transactions
+    main_account: TestAccount,
+    // Keys of the dapp definition account, needed to configure its metadata
+    dapp_definition_account: TestAccount,
+    key_generator: KeyGenerator,
 
     pub admin_badge_address: ResourceAddress,
+    pub badges: HashMap<String, ResourceAddress>,
     pub a_address: ResourceAddress,
     pub b_address: ResourceAddress,
     pub x_address: ResourceAddress,
     pub y_address: ResourceAddress,
-    pub u_address: ResourceAddress,
-    pub v_address: ResourceAddress,
-    pub j_nft_address: ResourceAddress,
-    pub k_nft_address: ResourceAddress,
+    // U, V, J and K are only created on first access, see `u_address()` etc.
+    u_address: Option<ResourceAddress>,
+    v_address: Option<ResourceAddress>,
+    // Only created with `TestEnvironmentBuilder::extra_resources`, like the NFT collections below
+    pub d0_address: Option<ResourceAddress>,
+    pub d6_address: Option<ResourceAddress>,
+    pub recallable_address: Option<ResourceAddress>,
+    j_nft_address: Option<ResourceAddress>,
+    k_nft_address: Option<ResourceAddress>,
+    pub string_nft_address: Option<ResourceAddress>,
+    pub bytes_nft_address: Option<ResourceAddress>,
+    pub ruid_nft_address: Option<ResourceAddress>,
+    pub resources_by_symbol: HashMap<String, ResourceAddress>,
 
     pub instruction_counter: usize,
     instruction_ids_by_label: HashMap<String, Vec<usize>>,
+    /// Whether `execute` previews the manifest before executing it (needed for output buckets)
+    pub preview_enabled: bool,
+    /// Flags used for the preview, e.g. to mimic the wallet by using free credit
+    pub preview_flags: PreviewFlags,
+    active_account_label: Option<String>,
+    /// Who locks how much fee for transactions run by `execute`
+    pub fee_config: FeeConfig,
+    history: Vec<ExecutedTransaction>,
+    // Owners of the vaults which paid fees so far, `None` for vaults of no environment account
+    fee_vault_owners: HashMap<NodeId, Option<ComponentAddress>>,
+    // Labels shared by all receipts, rebuilt once the environment registered new addresses
+    address_labels_cache: Option<Arc<Vec<(String, GlobalAddress)>>>,
+
+    pub setup_timings: SetupTimings,
+    config: TestEnvironmentConfig,
 }
 
 impl TestEnvironment {
     pub fn new<T: AsRef<Path> + Ord>(packages: HashMap<&str, T>) -> Self {
-        let packages: HashMap<&str, PathBuf> = packages
-            .iter()
-            .map(|(&package_name, package_dir)| (package_name, package_dir.as_ref().to_path_buf()))
-            .into_iter()
-            .collect();
+        Self::try_new(packages).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `new`, but returns an error instead of panicking if a package directory
+    /// is missing, a package fails to compile or the setup fails otherwise
+    pub fn try_new<T: AsRef<Path> + Ord>(packages: HashMap<&str, T>) -> Result<Self, TestEnvError> {
+        if let Some(package_dir) = packages.values().find(|dir| !dir.as_ref().is_dir()) {
+            return Err(TestEnvError::PackageDirNotFound(
+                package_dir.as_ref().to_path_buf(),
+            ));
+        }
+        TestEnvironmentBuilder::new().packages(packages).try_build()
+    }
+
+    fn from_config(config: TestEnvironmentConfig) -> Result<Self, TestEnvError> {
+        let revival_start = Instant::now();
+        let cache_key = (config.clone(), packages_fingerprint(&config));
+        if let Some(test_environment_) = get_cache_test_environment(&cache_key) {
+            return Ok(test_environment_.cache_hit(&config, revival_start));
+        }
 
-        let package_dirs: BTreeSet<PathBuf> = packages.values().cloned().collect();
-        let test_environment_cached = get_cache_test_environment(&package_dirs);
+        // Wait for other threads generating the same environment and use theirs
+        let environment_lock = generation_lock(&ENVIRONMENT_GENERATION_LOCKS, &cache_key);
+        let _environment_guard = environment_lock.as_ref().map(lock_generation);
+        if let Some(test_environment_) = get_cache_test_environment(&cache_key) {
+            return Ok(test_environment_.cache_hit(&config, revival_start));
+        }
 
-        if let Some(test_environment_) = test_environment_cached {
-            return test_environment_;
+        // Other test binaries may have baked this environment already
+        let disk_cache_path = disk_cache_path(&config);
+        let _disk_cache_lock = disk_cache_path
+            .as_deref()
+            .map(DiskCacheLock::acquire)
+            .transpose()?;
+        if let Some(snapshot) = disk_cache_path
+            .as_deref()
+            .and_then(|path| load_from_disk(path, &config))
+        {
+            let test_environment_ = snapshot.revive();
+            write_cache_test_environment(cache_key, snapshot);
+            return Ok(test_environment_.cache_hit(&config, revival_start));
         }
 
-        let mut test_environment_new =
-            get_cache_test_environment(&BTreeSet::new()).unwrap_or_else(|| {
-                let test_environment_empty_ = TestEnvironment::generate_new_test_environment();
-                write_cache(
-                    &TEST_ENVIRONMENT_CACHE,
-                    BTreeSet::new(), // Cache empty (packageless) environment
+        let empty_config = config.without_packages();
+        let revival_start = Instant::now();
+        let empty_cache_key = (empty_config.clone(), packages_fingerprint(&empty_config));
+        let empty_environment_lock = match config.packages.is_empty() {
+            true => None, // Same key as above, already locked
+            false => generation_lock(&ENVIRONMENT_GENERATION_LOCKS, &empty_cache_key),
+        };
+        let _empty_environment_guard = empty_environment_lock.as_ref().map(lock_generation);
+        let mut test_environment_new = match get_cache_test_environment(&empty_cache_key) {
+            Some(mut test_environment_empty_) => {
+                test_environment_empty_.setup_timings.revival = revival_start.elapsed();
+                test_environment_empty_
+            }
+            None => {
+                let generation_start = Instant::now();
+                let mut test_environment_empty_ =
+                    TestEnvironment::generate_new_test_environment(&empty_config)?;
+                write_cache_test_environment(
+                    empty_cache_key, // Cache empty (packageless) environment
                     test_environment_empty_.create_snapshot(),
                 );
+                test_environment_empty_.setup_timings.generation = generation_start.elapsed();
                 test_environment_empty_
-            });
+            }
+        };
+        test_environment_new.setup_timings.cache_misses = 1;
+        record_environment_lookup(false);
 
-        if packages.is_empty() {
-            return test_environment_new;
+        if config.packages.is_empty() {
+            record_setup_timings(&config, &test_environment_new.setup_timings);
+            if let Some(path) = &disk_cache_path {
+                save_to_disk(path, &test_environment_new.create_snapshot())?;
+            }
+            return Ok(test_environment_new);
         }
 
         // Leaving package publishing for last, means that there will be nothing
@@ -153,13 +1102,31 @@ impl TestEnvironment {
         // and just publish packages on top of it, with the fields of the TestEnvironment
         // (account/tokens/etc) remaining valid
 
-        test_environment_new.compile_and_publish_packages(packages);
-        write_cache(
-            &TEST_ENVIRONMENT_CACHE,
-            package_dirs, // Cache TestEnvironment with new packages
-            test_environment_new.create_snapshot(),
+        let packages: HashMap<&str, PathBuf> = config
+            .packages
+            .iter()
+            .map(|(package_name, package_dir)| (package_name.as_str(), package_dir.clone()))
+            .collect();
+        test_environment_new.config = config.clone();
+        test_environment_new.try_compile_and_publish_packages(packages)?;
+        record_setup_timings(&config, &test_environment_new.setup_timings);
+        let snapshot = test_environment_new.create_snapshot();
+        if let Some(path) = &disk_cache_path {
+            save_to_disk(path, &snapshot)?;
+        }
+        write_cache_test_environment(
+            cache_key, // Cache TestEnvironment with new packages
+            snapshot,
         );
-        test_environment_new
+        Ok(test_environment_new)
+    }
+
+    fn cache_hit(mut self, config: &TestEnvironmentConfig, revival_start: Instant) -> Self {
+        self.setup_timings.revival = revival_start.elapsed();
+        self.setup_timings.cache_hits = 1;
+        record_environment_lookup(true);
+        record_setup_timings(config, &self.setup_timings);
+        self
     }
 
     /// Retrieves a TestEnvironment from the snapshot
@@ -167,21 +1134,26 @@ impl TestEnvironment {
     /// - MenifestBuilder
     /// - instruction_counter
     /// - instruction_ids_by_label
+    /// - preview_enabled
+    /// - preview_flags
+    /// - active account
+    /// - fee_config
+    /// - history
     pub fn from_snapshot(snapshot: TestEnvironmentSnapshot) -> Self {
         snapshot.revive()
     }
 
-    fn generate_new_test_environment() -> TestEnvironment {
-        let mut test_runner = LedgerSimulatorBuilder::new()
-            .with_custom_genesis(CustomGenesis::default(
-                Epoch::of(1),
-                CustomGenesis::default_consensus_manager_config(),
-            ))
-            .without_kernel_trace()
-            .build();
+    fn generate_new_test_environment(
+        config: &TestEnvironmentConfig,
+    ) -> Result<TestEnvironment, TestEnvError> {
+        let mut test_runner = config.simulator_builder().build();
+        let mut key_generator = KeyGenerator::new(config.key_seed);
 
-        let (public_key, _private_key, account) = test_runner.new_allocated_account();
-        let (_, _, dapp_definition) = test_runner.new_allocated_account();
+        let main_account = TestAccount::create(&mut test_runner, key_generator.next_key(false));
+        let (public_key, account) = (main_account.public_key(), main_account.address);
+        let dapp_definition_account =
+            TestAccount::create(&mut test_runner, key_generator.next_key(false));
+        let dapp_definition = dapp_definition_account.address;
 
         let manifest_builder = ManifestBuilder::new().lock_standard_test_fee(account);
 
@@ -189,10 +1161,29 @@ impl TestEnvironment {
 
         let admin_badge_address =
             test_runner.create_fungible_resource(dec!(1), DIVISIBILITY_NONE, account);
-        let a_address = test_runner.create_fungible_resource_advanced(
+        let badges: HashMap<String, ResourceAddress> = config
+            .badges
+            .iter()
+            .map(|name| {
+                let badge_address = test_runner.create_fungible_resource_advanced(
+                    dec!(1),
+                    DIVISIBILITY_NONE,
+                    account,
+                    metadata! {
+                        init {
+                            "name" => name.clone(), locked;
+                        }
+                    },
+                );
+                (name.clone(), badge_address)
+            })
+            .collect();
+        // A, B, U and V can be minted and burned with the admin badge to top up balances mid-test
+        let a_address = test_runner.create_fungible_resource_with_roles(
             MAX_SUPPLY,
             DIVISIBILITY_MAXIMUM,
             account,
+            admin_badge_roles(admin_badge_address),
             metadata! {
                 init {
                     "name" => "Test token A".to_owned(), locked;
@@ -200,10 +1191,11 @@ impl TestEnvironment {
                 }
             },
         );
-        let b_address = test_runner.create_fungible_resource_advanced(
+        let b_address = test_runner.create_fungible_resource_with_roles(
             MAX_SUPPLY,
             DIVISIBILITY_MAXIMUM,
             account,
+            admin_badge_roles(admin_badge_address),
             metadata! {
                 init {
                     "name" => "Test token B".to_owned(), locked;
@@ -213,36 +1205,135 @@ impl TestEnvironment {
         );
         let (x_address, y_address) = sort_addresses(a_address, b_address);
 
-        let u_address =
-            test_runner.create_fungible_resource(dec!(1000000000), DIVISIBILITY_MAXIMUM, account);
-        let v_address =
-            test_runner.create_fungible_resource(dec!(10000000), DIVISIBILITY_MAXIMUM, account);
-        let j_nft_address = test_runner.create_non_fungible_resource(account);
-        let k_nft_address = test_runner.create_non_fungible_resource(account);
+        // U, V and the J/K NFT collections are created on first access, see `u_address()` etc.
+        let mut resources_by_symbol: HashMap<String, ResourceAddress> = vec![
+            ("A".to_string(), a_address),
+            ("B".to_string(), b_address),
+            ("X".to_string(), x_address),
+            ("Y".to_string(), y_address),
+        ]
+        .into_iter()
+        .collect();
+        let extra_resources = config.extra_resources.then(|| {
+            ExtraResources::create(
+                &mut test_runner,
+                account,
+                admin_badge_address,
+                &mut resources_by_symbol,
+            )
+        });
+
+        for fungible in &config.fungibles {
+            let resource_address = test_runner.create_fungible_resource_advanced(
+                fungible.supply,
+                fungible.divisibility,
+                account,
+                metadata! {
+                    init {
+                        "name" => format!("Test token {}", fungible.symbol), locked;
+                        "symbol" => fungible.symbol.clone(), locked;
+                    }
+                },
+            );
+            resources_by_symbol.insert(fungible.symbol.clone(), resource_address);
+        }
+        for non_fungible in &config.non_fungibles {
+            let resource_address = test_runner.create_non_fungible_resource_with_metadata(
+                non_fungible.count,
+                account,
+                metadata! {
+                    init {
+                        "name" => format!("Test NFT {}", non_fungible.symbol), locked;
+                        "symbol" => non_fungible.symbol.clone(), locked;
+                    }
+                },
+            );
+            resources_by_symbol.insert(non_fungible.symbol.clone(), resource_address);
+        }
+
+        let accounts: Vec<TestAccount> = (0..config.additional_accounts)
+            .map(|_| {
+                let private_key = key_generator.next_key(config.ed25519_accounts);
+                TestAccount::create(&mut test_runner, private_key)
+            })
+            .collect();
 
-        let test_environment = Self {
+        let mut test_environment = Self {
             test_runner,
             manifest_builder,
             package_addresses,
+            previous_package_addresses: HashMap::new(),
+            package_definitions: HashMap::new(),
             public_key,
             account,
             dapp_definition,
+            accounts,
+            accounts_by_label: HashMap::new(),
+            identities_by_label: HashMap::new(),
+            main_account,
+            dapp_definition_account,
+            key_generator,
 
             admin_badge_address,
+            badges,
             a_address,
             b_address,
             x_address,
             y_address,
-            u_address,
-            v_address,
-            j_nft_address,
-            k_nft_address,
+            u_address: None,
+            v_address: None,
+            d0_address: extra_resources.as_ref().map(|extra| extra.d0_address),
+            d6_address: extra_resources.as_ref().map(|extra| extra.d6_address),
+            recallable_address: extra_resources
+                .as_ref()
+                .map(|extra| extra.recallable_address),
+            j_nft_address: None,
+            k_nft_address: None,
+            string_nft_address: extra_resources
+                .as_ref()
+                .map(|extra| extra.string_nft_address),
+            bytes_nft_address: extra_resources
+                .as_ref()
+                .map(|extra| extra.bytes_nft_address),
+            ruid_nft_address: extra_resources.as_ref().map(|extra| extra.ruid_nft_address),
+            resources_by_symbol,
 
             instruction_counter: INSTRUCTION_COUNTER_INIT,
             instruction_ids_by_label: HashMap::new(),
+            preview_enabled: true,
+            preview_flags: PreviewFlags::default(),
+            active_account_label: None,
+            fee_config: FeeConfig::default(),
+            history: vec![],
+            fee_vault_owners: HashMap::new(),
+            address_labels_cache: None,
+
+            setup_timings: SetupTimings::default(),
+            config: config.clone(),
         };
 
-        test_environment
+        for balance in &config.balances {
+            let resource_address = test_environment.try_resource_by_symbol(&balance.symbol)?;
+            let manifest = ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .withdraw_from_account(test_environment.account, resource_address, balance.amount)
+                .try_deposit_entire_worktop_or_abort(
+                    test_environment.accounts[balance.account_index].address,
+                    None,
+                )
+                .build();
+            test_environment
+                .test_runner
+                .execute_manifest(
+                    manifest,
+                    vec![NonFungibleGlobalId::from_public_key(
+                        &test_environment.public_key,
+                    )],
+                )
+                .expect_commit_success();
+        }
+
+        Ok(test_environment)
     }
 
     /// Compiles and Publishes Packages
@@ -252,28 +1343,419 @@ impl TestEnvironment {
     /// since the first results in caching of clean environment states + respective packages,
     /// speeding up future calls
     pub fn compile_and_publish_packages(&mut self, packages: HashMap<&str, PathBuf>) {
-        let package_addresses: HashMap<String, PackageAddress> = packages
+        self.try_compile_and_publish_packages(packages)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_compile_and_publish_packages(
+        &mut self,
+        packages: HashMap<&str, PathBuf>,
+    ) -> Result<(), TestEnvError> {
+        // Publishing in a stable order keeps package addresses identical across runs
+        let packages: BTreeMap<&str, PathBuf> = packages.into_iter().collect();
+        if let Some(workspace_dir) = self.config.workspace.clone() {
+            self.compile_workspace(&workspace_dir, packages.values().cloned().collect())?;
+        }
+        let mut compiled_packages =
+            self.compile_packages_in_parallel(packages.values().cloned().collect())?;
+        for (package_name, package_dir) in packages {
+            let compiled_package = match compiled_packages.remove(&package_dir) {
+                Some(compiled_package) => compiled_package,
+                None => self.try_compile_package(&package_dir)?,
+            };
+            self.try_publish_compiled_package(package_name, compiled_package)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles the `package_dirs` missing in the package cache concurrently, in batches of
+    /// at most `MAX_PARALLEL_COMPILATIONS`. A single missing package is left to `compile_package`.
+    fn compile_packages_in_parallel(
+        &mut self,
+        package_dirs: Vec<PathBuf>,
+    ) -> Result<HashMap<PathBuf, CompiledPackage>, TestEnvError> {
+        let uncached: Vec<(PathBuf, PackageCacheKey)> = package_dirs
             .into_iter()
-            .map(|(package_name, package_dir)| {
-                let cache_result: Option<CompiledPackage> = get_cache(&PACKAGE_CACHE, &package_dir);
-                let compiled_package = match cache_result {
-                    Some(compiled_package) => compiled_package,
-                    None => {
-                        let compiled_package = self.test_runner.compile(&package_dir);
-                        write_cache(&PACKAGE_CACHE, package_dir, compiled_package.clone());
-                        compiled_package
-                    }
-                };
-                let package_address = self.test_runner.publish_package(
-                    compiled_package,
-                    BTreeMap::new(),
-                    OwnerRole::Updatable(rule!(require(self.admin_badge_address))),
-                );
-                (package_name.to_string(), package_address)
+            .map(|package_dir| {
+                let cache_key = self.package_cache_key(&package_dir);
+                (package_dir, cache_key)
+            })
+            .filter(|(_, cache_key)| get_cache(&PACKAGE_CACHE, cache_key).is_none())
+            .collect();
+        if uncached.len() < 2 {
+            return Ok(HashMap::new());
+        }
+        let parallelism = std::thread::available_parallelism()
+            .map_or(1, |parallelism| parallelism.get())
+            .min(MAX_PARALLEL_COMPILATIONS);
+        let compiler_options = &self.config.compiler_options;
+        let compilation_start = Instant::now();
+        let mut compiled_packages = HashMap::new();
+        for batch in uncached.chunks(parallelism) {
+            let compiled_batch: Result<Vec<(PathBuf, CompiledPackage)>, TestEnvError> =
+                std::thread::scope(|scope| {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|(package_dir, cache_key)| {
+                            scope.spawn(move || {
+                                let compilation_lock =
+                                    generation_lock(&PACKAGE_COMPILATION_LOCKS, cache_key);
+                                let _compilation_guard =
+                                    compilation_lock.as_ref().map(lock_generation);
+                                let cache_result = get_cache(&PACKAGE_CACHE, cache_key);
+                                record_package_lookup(cache_result.is_some());
+                                let compiled_package = match cache_result {
+                                    Some(compiled_package) => compiled_package,
+                                    None => {
+                                        let compiled_package =
+                                            compile(package_dir, compiler_options)?;
+                                        write_cache(
+                                            &PACKAGE_CACHE,
+                                            cache_key.clone(),
+                                            compiled_package.clone(),
+                                        );
+                                        compiled_package
+                                    }
+                                };
+                                Ok::<_, TestEnvError>((package_dir.clone(), compiled_package))
+                            })
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| {
+                            handle
+                                .join()
+                                .unwrap_or_else(|error| std::panic::resume_unwind(error))
+                        })
+                        .collect()
+                });
+            compiled_packages.extend(compiled_batch?);
+        }
+        self.setup_timings.compilation += compilation_start.elapsed();
+        Ok(compiled_packages)
+    }
+
+    /// Coverage data files written so far by blueprints compiled with `TestEnvironmentBuilder::coverage`
+    pub fn coverage_files(&self) -> Vec<PathBuf> {
+        let mut coverage_files = vec![];
+        if let Some(coverage_dir) = &self.config.coverage_dir {
+            collect_coverage_files(coverage_dir, &mut coverage_files);
+        }
+        coverage_files.sort();
+        coverage_files
+    }
+
+    /// Fills the package cache for all `package_dirs` not cached yet with one workspace build
+    fn compile_workspace(
+        &mut self,
+        workspace_dir: &Path,
+        package_dirs: Vec<PathBuf>,
+    ) -> Result<(), TestEnvError> {
+        let uncached_package_dirs: Vec<PathBuf> = package_dirs
+            .into_iter()
+            .filter(|package_dir| {
+                let cache_key = self.package_cache_key(package_dir);
+                get_cache(&PACKAGE_CACHE, &cache_key).is_none()
             })
             .collect();
+        if uncached_package_dirs.is_empty() {
+            return Ok(());
+        }
+        let compilation_start = Instant::now();
+        for (package_dir, compiled_package) in
+            compile_workspace(workspace_dir, &uncached_package_dirs)?
+        {
+            let cache_key = self.package_cache_key(&package_dir);
+            write_cache(&PACKAGE_CACHE, cache_key, compiled_package);
+        }
+        self.setup_timings.compilation += compilation_start.elapsed();
+        Ok(())
+    }
+
+    // Packages compiled with different options are cached separately
+    fn package_cache_key(&self, package_dir: &Path) -> PackageCacheKey {
+        let mut hasher = DefaultHasher::new();
+        package_fingerprint(package_dir).hash(&mut hasher);
+        self.config.compiler_options.hash(&mut hasher);
+        (package_dir.to_path_buf(), hasher.finish())
+    }
+
+    fn compile_package(&mut self, package_dir: &Path) -> CompiledPackage {
+        self.try_compile_package(package_dir)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_compile_package(&mut self, package_dir: &Path) -> Result<CompiledPackage, TestEnvError> {
+        let cache_key = self.package_cache_key(package_dir);
+        let compilation_lock = generation_lock(&PACKAGE_COMPILATION_LOCKS, &cache_key);
+        let _compilation_guard = compilation_lock.as_ref().map(lock_generation);
+        let cache_result: Option<CompiledPackage> = get_cache(&PACKAGE_CACHE, &cache_key);
+        record_package_lookup(cache_result.is_some());
+        match cache_result {
+            Some(compiled_package) => Ok(compiled_package),
+            None => {
+                let compilation_start = Instant::now();
+                let compiled_package = compile(package_dir, &self.config.compiler_options)?;
+                self.setup_timings.compilation += compilation_start.elapsed();
+                write_cache(&PACKAGE_CACHE, cache_key, compiled_package.clone());
+                Ok(compiled_package)
+            }
+        }
+    }
+
+    /// Publishes the code in `new_package_dir` as new version of the package `package_name`
+    /// (with the owner role, metadata and royalties configured for it). `package_address(name)`
+    /// returns the new address afterwards, the replaced ones are kept in `previous_package_addresses`.
+    /// The new version always gets a new address, also with `package_at_address` or in
+    /// deterministic mode, see `TestEnvironmentBuilder::deterministic`.
+    pub fn upgrade_package<T: AsRef<Path>>(
+        &mut self,
+        package_name: &str,
+        new_package_dir: T,
+    ) -> PackageAddress {
+        self.upgrade_package_with_migration(package_name, new_package_dir, |_, _, _| {})
+    }
+
+    /// Same as `upgrade_package` but calls `migration` with the old and the new package address,
+    /// e.g. to move state from components of the old package to ones of the new package:
+    /// ```ignore
+    /// test_environment.upgrade_package_with_migration("pool", "../pool_v2", |env, _, new_package| {
+    ///     env.manifest_builder = ...; // call_function(new_package, "Pool", "migrate", ...)
+    ///     env.execute_expect_success(false);
+    /// });
+    /// ```
+    pub fn upgrade_package_with_migration<T, F>(
+        &mut self,
+        package_name: &str,
+        new_package_dir: T,
+        migration: F,
+    ) -> PackageAddress
+    where
+        T: AsRef<Path>,
+        F: FnOnce(&mut Self, PackageAddress, PackageAddress),
+    {
+        let old_package_address = self.package_address(package_name);
+        let compiled_package = self.compile_package(new_package_dir.as_ref());
+        let new_package_address = self.publish_compiled_package(package_name, compiled_package);
+        self.previous_package_addresses
+            .entry(package_name.to_string())
+            .or_default()
+            .push(old_package_address);
+        migration(self, old_package_address, new_package_address);
+        new_package_address
+    }
+
+    /// Publishes a package compiled beforehand (e.g. by `scrypto build` in CI or a released
+    /// artifact) from its `.wasm` and `.rpd` files and registers it under `package_name`.
+    /// The royalties, metadata and owner role configured for `package_name` are applied.
+    pub fn publish_package_from_files<W: AsRef<Path>, R: AsRef<Path>>(
+        &mut self,
+        package_name: &str,
+        wasm_path: W,
+        rpd_path: R,
+    ) -> PackageAddress {
+        let code = std::fs::read(wasm_path.as_ref())
+            .unwrap_or_else(|_| panic!("Failed to read wasm {:?}", wasm_path.as_ref()));
+        let rpd = std::fs::read(rpd_path.as_ref()).unwrap_or_else(|_| {
+            panic!("Failed to read package definition {:?}", rpd_path.as_ref())
+        });
+        let definition: PackageDefinition = manifest_decode(&rpd)
+            .unwrap_or_else(|_| panic!("Invalid package definition {:?}", rpd_path.as_ref()));
+        self.publish_package_bytes(package_name, code, definition)
+    }
+
+    /// Publishes a package from in-memory code and definition and registers it under
+    /// `package_name`, e.g. for fixtures embedded via `include_bytes!` or mutated wasm
+    pub fn publish_package_bytes(
+        &mut self,
+        package_name: &str,
+        code: Vec<u8>,
+        definition: PackageDefinition,
+    ) -> PackageAddress {
+        self.publish_compiled_package(package_name, (code, definition))
+    }
+
+    fn publish_compiled_package(
+        &mut self,
+        package_name: &str,
+        compiled_package: CompiledPackage,
+    ) -> PackageAddress {
+        self.try_publish_compiled_package(package_name, compiled_package)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_publish_compiled_package(
+        &mut self,
+        package_name: &str,
+        compiled_package: CompiledPackage,
+    ) -> Result<PackageAddress, TestEnvError> {
+        let compiled_package = self.with_package_royalties(package_name, compiled_package)?;
+        let publishing_start = Instant::now();
+        let owner_role = match self.config.package_owner_roles.get(package_name) {
+            Some(PackageOwnerRole(owner_role)) => owner_role.clone(),
+            None => OwnerRole::Updatable(rule!(require(self.admin_badge_address))),
+        };
+        let metadata = self.package_metadata(package_name);
+        let definition = compiled_package.1.clone();
+        // The configured address is taken by the first version, upgrades need a new one
+        let is_upgrade = self.package_addresses.contains_key(package_name);
+        let preallocated_address = match (self.config.deterministic, is_upgrade) {
+            (true, true) => {
+                let version = self
+                    .previous_package_addresses
+                    .get(package_name)
+                    .map_or(0, Vec::len)
+                    + 1;
+                Some(deterministic_package_address(&format!(
+                    "{}@{}",
+                    package_name, version
+                )))
+            }
+            (true, false) => Some(
+                self.config
+                    .package_preallocated_addresses
+                    .get(package_name)
+                    .copied()
+                    .unwrap_or_else(|| deterministic_package_address(package_name)),
+            ),
+            (false, true) => None,
+            (false, false) => self
+                .config
+                .package_preallocated_addresses
+                .get(package_name)
+                .copied(),
+        };
+        let receipt = match preallocated_address {
+            Some(address) => {
+                self.publish_package_at_address(compiled_package, address, metadata, owner_role)
+            }
+            None => {
+                let (code, definition) = compiled_package;
+                let manifest = ManifestBuilder::new()
+                    .lock_fee_from_faucet()
+                    .publish_package_advanced(None, code, definition, metadata, owner_role)
+                    .build();
+                self.test_runner.execute_manifest(manifest, vec![])
+            }
+        };
+        if !receipt.is_commit_success() {
+            return Err(TestEnvError::SetupFailed(format!(
+                "Failed to publish package {:?}: {:?}",
+                package_name, receipt.result
+            )));
+        }
+        let package_address = match preallocated_address {
+            Some(address) => address,
+            None => receipt.expect_commit_success().new_package_addresses()[0],
+        };
+        self.setup_timings.publishing += publishing_start.elapsed();
+        self.package_addresses
+            .insert(package_name.to_string(), package_address);
+        self.package_definitions
+            .insert(package_name.to_string(), definition);
+        self.address_labels_cache = None;
+        Ok(package_address)
+    }
+
+    /// Like `LedgerSimulator::publish_package_at_address`, which always publishes without
+    /// metadata and with an `AllowAll` owner, but with the configured metadata and owner role
+    fn publish_package_at_address(
+        &mut self,
+        (code, definition): CompiledPackage,
+        address: PackageAddress,
+        metadata: BTreeMap<String, MetadataValue>,
+        owner_role: OwnerRole,
+    ) -> TransactionReceipt {
+        let code_hash = hash(&code);
+        let nonce = self.test_runner.next_transaction_nonce();
+        let executable = SystemTransactionV1 {
+            instructions: InstructionsV1(vec![InstructionV1::CallFunction {
+                package_address: DynamicPackageAddress::Static(PACKAGE_PACKAGE),
+                blueprint_name: PACKAGE_BLUEPRINT.to_string(),
+                function_name: PACKAGE_PUBLISH_WASM_ADVANCED_IDENT.to_string(),
+                args: to_manifest_value_and_unwrap!(&PackagePublishWasmAdvancedManifestInput {
+                    code: ManifestBlobRef(code_hash.0),
+                    definition,
+                    metadata: metadata.into(),
+                    package_address: Some(ManifestAddressReservation(0)),
+                    owner_role,
+                }),
+            }]),
+            blobs: BlobsV1 {
+                blobs: vec![BlobV1(code)],
+            },
+            hash_for_execution: hash(format!("Test environment package publish: {}", nonce)),
+            pre_allocated_addresses: vec![PreAllocatedAddress {
+                blueprint_id: BlueprintId::new(&PACKAGE_PACKAGE, PACKAGE_BLUEPRINT),
+                address: address.into(),
+            }],
+        }
+        .prepare()
+        .expect("Package publish transaction is preparable")
+        .get_executable(btreeset!(AuthAddresses::system_role()));
+        self.test_runner.execute_transaction(
+            executable,
+            ExecutionConfig::for_system_transaction(NetworkDefinition::simulator()),
+        )
+    }
 
-        self.package_addresses.extend(package_addresses);
+    fn package_metadata(&self, package_name: &str) -> BTreeMap<String, MetadataValue> {
+        let package_metadata = self
+            .config
+            .package_metadata
+            .get(package_name)
+            .cloned()
+            .unwrap_or_default();
+        let mut metadata = package_metadata.entries;
+        if package_metadata.link_dapp_definition {
+            metadata.insert(
+                "dapp_definition".to_string(),
+                MetadataValue::GlobalAddress(self.dapp_definition.into()),
+            );
+        }
+        metadata
+    }
+
+    /// Enables the royalties configured for `package_name`, see `TestEnvironmentBuilder::package_royalty`
+    fn with_package_royalties(
+        &self,
+        package_name: &str,
+        compiled_package: CompiledPackage,
+    ) -> Result<CompiledPackage, TestEnvError> {
+        let (code, mut definition) = compiled_package;
+        for royalty in self
+            .config
+            .package_royalties
+            .iter()
+            .filter(|royalty| royalty.package_name == package_name)
+        {
+            let blueprint = definition
+                .blueprints
+                .get_mut(&royalty.blueprint_name)
+                .ok_or_else(|| TestEnvError::BlueprintNotFound {
+                    package_name: package_name.to_string(),
+                    blueprint_name: royalty.blueprint_name.clone(),
+                })?;
+            // Enabled royalty configs have to cover every function of the blueprint
+            if let PackageRoyaltyConfig::Disabled = blueprint.royalty_config {
+                let free_functions = blueprint
+                    .schema
+                    .functions
+                    .functions
+                    .keys()
+                    .map(|function_name| (function_name.clone(), RoyaltyAmount::Free))
+                    .collect();
+                blueprint.royalty_config = PackageRoyaltyConfig::Enabled(free_functions);
+            }
+            if let PackageRoyaltyConfig::Enabled(amounts) = &mut blueprint.royalty_config {
+                amounts.insert(
+                    royalty.function_name.clone(),
+                    RoyaltyAmount::Xrd(royalty.xrd),
+                );
+            }
+        }
+        Ok((code, definition))
     }
 
     pub fn new_instruction(
@@ -289,187 +1771,1578 @@ impl TestEnvironment {
         self.instruction_counter += instruction_count;
     }
 
-    pub fn package_address(&self, package_name: &str) -> PackageAddress {
-        *self
-            .package_addresses
-            .get(package_name)
-            .expect(format!("Package {:?} not found", package_name).as_str())
+    /// Adds the instructions of `add` to the manifest and tracks the last one under `label`,
+    /// deriving the counts passed to `new_instruction` from the builder:
+    /// ```ignore
+    /// let x_bucket = env.name("x_bucket");
+    /// env.add_instructions("swap", |builder| {
+    ///     builder
+    ///         .withdraw_from_account(account, x_address, dec!(1))
+    ///         .take_from_worktop(x_address, dec!(1), &x_bucket)
+    ///         .with_name_lookup(|builder, lookup| {
+    ///             builder.call_method(pool_address, "swap", manifest_args!(lookup.bucket(&x_bucket)))
+    ///         })
+    /// });
+    /// ```
+    pub fn add_instructions<F>(&mut self, label: &str, add: F) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder) -> ManifestBuilder,
+    {
+        let manifest_builder = mem::replace(&mut self.manifest_builder, ManifestBuilder::new());
+        let instruction_count_before = manifest_builder.instructions().len();
+        self.manifest_builder = add(manifest_builder);
+        let instruction_count =
+            self.manifest_builder.instructions().len() - instruction_count_before;
+        assert!(
+            instruction_count > 0,
+            "No instructions added for label {:?}",
+            label
+        );
+        self.new_instruction(label, instruction_count, instruction_count - 1);
+        self
     }
 
-    /// Creates and retrieves snapshot of the TestEnvironment
-    /// IMPORTANT: The states of the following fields are dropped:
-    /// - MenifestBuilder
-    /// - instruction_counter
-    /// - instruction_ids_by_label
-    pub fn create_snapshot(&self) -> TestEnvironmentSnapshot {
-        TestEnvironmentSnapshot::from(self)
+    /// Injects a freshly minted bucket of `amount` of `resource` straight into the call added
+    /// by `call`, which is tracked under `label`. `call` has to add exactly one instruction.
+    ///
+    /// The resource has to be mintable with the admin badge (A, B, U, V), XRD is taken from the
+    /// faucet instead. No account balance is touched, so no withdraw instructions are needed:
+    /// ```ignore
+    /// env.call_with_bucket("swap", env.x_address, dec!(1), |builder, x_bucket| {
+    ///     builder.call_method(pool_address, "swap", manifest_args!(x_bucket))
+    /// });
+    /// ```
+    /// The admin badge proof is created from the main account, which therefore has to sign.
+    pub fn call_with_bucket<F>(
+        &mut self,
+        label: &str,
+        resource: ResourceAddress,
+        amount: Decimal,
+        call: F,
+    ) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder, ManifestBucket) -> ManifestBuilder,
+    {
+        let bucket_name = self.name("injected_bucket");
+        let admin_proof_name = self.name("admin_proof");
+        let (account, admin_badge_address) = (self.account, self.admin_badge_address);
+        self.add_instructions(label, |builder| {
+            let builder = match resource == XRD {
+                true => builder.get_free_xrd_from_faucet(),
+                false => builder
+                    .create_proof_from_account_of_amount(account, admin_badge_address, dec!(1))
+                    .mint_fungible(resource, amount)
+                    .pop_from_auth_zone(&admin_proof_name)
+                    .drop_proof(&admin_proof_name),
+            };
+            builder
+                .take_from_worktop(resource, amount, &bucket_name)
+                .with_name_lookup(|builder, lookup| call(builder, lookup.bucket(&bucket_name)))
+        })
     }
-}
 
-/// NOTE: This should only be used for single clones,
-/// since it clones by taking a snapshot and then recovering from it.
-/// For the creation of many clones, it is advised to manually snapshot
-/// and then creating as many TestEnvironments as needed from
-/// that snapshot
-impl Clone for TestEnvironment {
-    fn clone(&self) -> Self {
-        self.create_snapshot().revive()
+    /// Same as `call_with_bucket` but withdraws the bucket from the main account,
+    /// e.g. for pool units or stake units which can not be minted
+    pub fn call_with_account_bucket<F>(
+        &mut self,
+        label: &str,
+        resource: ResourceAddress,
+        amount: Decimal,
+        call: F,
+    ) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder, ManifestBucket) -> ManifestBuilder,
+    {
+        let bucket_name = self.name("injected_bucket");
+        let account = self.account;
+        self.add_instructions(label, |builder| {
+            builder
+                .withdraw_from_account(account, resource, amount)
+                .take_from_worktop(resource, amount, &bucket_name)
+                .with_name_lookup(|builder, lookup| call(builder, lookup.bucket(&bucket_name)))
+        })
     }
-}
 
-pub struct TestEnvironmentSnapshot {
-    pub test_runner_snapshot: LedgerSimulatorSnapshot,
+    /// Same as `call_with_bucket` but injects a bucket with the given non fungibles
+    pub fn call_with_non_fungible_bucket<F>(
+        &mut self,
+        label: &str,
+        resource: ResourceAddress,
+        ids: IndexSet<NonFungibleLocalId>,
+        call: F,
+    ) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder, ManifestBucket) -> ManifestBuilder,
+    {
+        let bucket_name = self.name("injected_bucket");
+        let account = self.account;
+        self.add_instructions(label, |builder| {
+            builder
+                .withdraw_non_fungibles_from_account(account, resource, ids.clone())
+                .take_non_fungibles_from_worktop(resource, ids, &bucket_name)
+                .with_name_lookup(|builder, lookup| call(builder, lookup.bucket(&bucket_name)))
+        })
+    }
 
-    pub package_addresses: HashMap<String, PackageAddress>,
-    pub public_key: Secp256k1PublicKey,
-    pub account: ComponentAddress,
-    pub dapp_definition: ComponentAddress,
+    /// Same as `call_with_bucket` but injects a proof of `amount` of `resource`
+    pub fn call_with_proof<F>(
+        &mut self,
+        label: &str,
+        resource: ResourceAddress,
+        amount: Decimal,
+        call: F,
+    ) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder, ManifestProof) -> ManifestBuilder,
+    {
+        let proof_name = self.name("injected_proof");
+        let account = self.account;
+        self.add_instructions(label, |builder| {
+            builder
+                .create_proof_from_account_of_amount(account, resource, amount)
+                .pop_from_auth_zone(&proof_name)
+                .with_name_lookup(|builder, lookup| call(builder, lookup.proof(&proof_name)))
+        })
+    }
 
-    pub admin_badge_address: ResourceAddress,
-    pub a_address: ResourceAddress,
-    pub b_address: ResourceAddress,
-    pub x_address: ResourceAddress,
-    pub y_address: ResourceAddress,
-    pub u_address: ResourceAddress,
-    pub v_address: ResourceAddress,
-    pub j_nft_address: ResourceAddress,
-    pub k_nft_address: ResourceAddress,
-}
+    /// Puts a proof of the admin badge into the auth zone for the following instructions,
+    /// e.g. to call methods of components or packages owned by the admin badge
+    pub fn present_admin_badge(&mut self) -> &mut Self {
+        let manifest_builder = mem::replace(&mut self.manifest_builder, ManifestBuilder::new());
+        self.manifest_builder = manifest_builder.create_proof_from_account_of_amount(
+            self.account,
+            self.admin_badge_address,
+            dec!(1),
+        );
+        self.instruction_counter += 1;
+        self
+    }
 
-impl TestEnvironmentSnapshot {
-    /// Creates snapshot of the TestEnvironment
-    /// IMPORTANT: The states of the following fields are dropped:
-    /// - MenifestBuilder
-    /// - instruction_counter
-    /// - instruction_ids_by_label
-    pub fn from(test_environment: &TestEnvironment) -> TestEnvironmentSnapshot {
-        Self {
-            test_runner_snapshot: test_environment.test_runner.create_snapshot(),
-            package_addresses: test_environment.package_addresses.clone(),
-            public_key: test_environment.public_key.clone(),
-            account: test_environment.account.clone(),
-            dapp_definition: test_environment.dapp_definition.clone(),
-            admin_badge_address: test_environment.admin_badge_address.clone(),
-            a_address: test_environment.a_address.clone(),
-            b_address: test_environment.b_address.clone(),
-            x_address: test_environment.x_address.clone(),
-            y_address: test_environment.y_address.clone(),
-            u_address: test_environment.u_address.clone(),
-            v_address: test_environment.v_address.clone(),
-            j_nft_address: test_environment.j_nft_address.clone(),
-            k_nft_address: test_environment.k_nft_address.clone(),
-        }
+    /// Reserves a global address for a component of `blueprint_name` in `package_address`,
+    /// tracking the instruction under the label `name`. Following instructions of the same
+    /// manifest can instantiate the component at the reservation `lookup.address_reservation(name)`
+    /// and already refer to its address by `name`:
+    /// ```ignore
+    /// test_environment.allocate_global_address(package_address, "Pool", "pool");
+    /// test_environment.add_instructions("instantiate", |builder| {
+    ///     builder
+    ///         .with_name_lookup(|builder, lookup| {
+    ///             let reservation = lookup.address_reservation("pool");
+    ///             builder.call_function(package_address, "Pool", "instantiate", manifest_args!(reservation))
+    ///         })
+    ///         .call_method("pool", "deposit", manifest_args!())
+    /// });
+    /// ```
+    pub fn allocate_global_address(
+        &mut self,
+        package_address: PackageAddress,
+        blueprint_name: &str,
+        name: &str,
+    ) -> &mut Self {
+        self.add_instructions(name, |builder| {
+            builder.allocate_global_address(package_address, blueprint_name, name, name)
+        })
     }
 
-    /// Retrieves a TestEnvironment from the snapshot
-    /// IMPORTANT: The states of the following fields are not recovered:
-    /// - MenifestBuilder
-    /// - instruction_counter
-    /// - instruction_ids_by_label
-    pub fn revive(&self) -> TestEnvironment {
-        TestEnvironment {
-            test_runner: LedgerSimulatorBuilder::new()
-                .with_custom_genesis(CustomGenesis::default(
-                    Epoch::of(1),
-                    CustomGenesis::default_consensus_manager_config(),
-                ))
-                .without_kernel_trace()
-                .build_from_snapshot(self.test_runner_snapshot.clone()),
-            manifest_builder: ManifestBuilder::new().lock_standard_test_fee(self.account),
+    /// Creates a new allocated account registered under `label`, e.g. to simulate
+    /// different users like liquidity providers and traders
+    pub fn new_account(&mut self, label: &str) -> ComponentAddress {
+        let private_key = self.key_generator.next_key(false);
+        let test_account = TestAccount::create(&mut self.test_runner, private_key);
+        let address = test_account.address;
+        self.accounts_by_label
+            .insert(label.to_string(), test_account);
+        self.address_labels_cache = None;
+        address
+    }
 
-            package_addresses: self.package_addresses.clone(),
-            public_key: self.public_key.clone(),
-            account: self.account.clone(),
-            dapp_definition: self.dapp_definition.clone(),
+    /// New key derived like the account keys (see `TestEnvironmentBuilder::key_seed`),
+    /// e.g. for validators or access controller roles
+    pub fn new_key_pair(&mut self) -> (Secp256k1PublicKey, Secp256k1PrivateKey) {
+        let private_key = self.key_generator.next_secp256k1_key();
+        (private_key.public_key(), private_key)
+    }
 
-            admin_badge_address: self.admin_badge_address.clone(),
-            a_address: self.a_address.clone(),
-            b_address: self.b_address.clone(),
-            x_address: self.x_address.clone(),
-            y_address: self.y_address.clone(),
-            u_address: self.u_address.clone(),
-            v_address: self.v_address.clone(),
-            j_nft_address: self.j_nft_address.clone(),
-            k_nft_address: self.k_nft_address.clone(),
+    /// Same as `new_account` but backed by an Ed25519 key, for blueprints whose
+    /// access rules differentiate between key types
+    pub fn new_account_ed25519(&mut self, label: &str) -> ComponentAddress {
+        let private_key = self.key_generator.next_key(true);
+        let test_account = TestAccount::create(&mut self.test_runner, private_key);
+        let address = test_account.address;
+        self.accounts_by_label
+            .insert(label.to_string(), test_account);
+        self.address_labels_cache = None;
+        address
+    }
 
-            instruction_counter: INSTRUCTION_COUNTER_INIT,
-            instruction_ids_by_label: HashMap::new(),
-        }
+    pub fn account(&self, label: &str) -> ComponentAddress {
+        self.test_account(label).address
     }
-}
 
-pub trait TestHelperExecution {
-    fn env(&mut self) -> &mut TestEnvironment;
+    pub fn test_account(&self, label: &str) -> &TestAccount {
+        self.accounts_by_label
+            .get(label)
+            .unwrap_or_else(|| panic!("Account {:?} not found", label))
+    }
 
-    fn execute(&mut self, verbose: bool) -> Receipt {
-        let account_component = self.env().account;
-        let public_key = self.env().public_key;
-        let manifest_builder =
-            mem::replace(&mut self.env().manifest_builder, ManifestBuilder::new());
-        let manifest = manifest_builder.deposit_batch(account_component).build();
-        let preview_receipt = self.env().test_runner.preview_manifest(
-            manifest.clone(),
-            vec![public_key.clone().into()],
-            0,
-            PreviewFlags::default(),
-        );
-        let execution_receipt = self.env().test_runner.execute_manifest(
-            manifest.clone(),
-            vec![NonFungibleGlobalId::from_public_key(&public_key)],
-        );
-        if verbose {
-            println!("{:?}", execution_receipt);
-        }
-        let instruction_mapping = self.env().instruction_ids_by_label.clone();
-        self.reset_instructions();
-        let manifest_builder =
-            mem::replace(&mut self.env().manifest_builder, ManifestBuilder::new());
-        self.env().manifest_builder = manifest_builder.lock_standard_test_fee(self.env().account);
-        Receipt {
-            execution_receipt,
-            preview_receipt,
-            instruction_ids_by_label: instruction_mapping,
-        }
+    /// Creates a new identity registered under `label`, with its owner badge deposited
+    /// into the main account, e.g. for dApp verification flows
+    pub fn new_identity(&mut self, label: &str) -> ComponentAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_identity()
+            .try_deposit_entire_worktop_or_abort(self.account, None)
+            .build();
+        let identity = self
+            .test_runner
+            .execute_manifest(manifest, vec![])
+            .expect_commit_success()
+            .new_component_addresses()[0];
+        self.identities_by_label.insert(label.to_string(), identity);
+        self.address_labels_cache = None;
+        identity
     }
 
-    fn execute_expect_success(&mut self, verbose: bool) -> Receipt {
-        let receipt = self.execute(verbose);
-        receipt.execution_receipt.expect_commit_success();
-        receipt
+    pub fn identity(&self, label: &str) -> ComponentAddress {
+        *self
+            .identities_by_label
+            .get(label)
+            .unwrap_or_else(|| panic!("Identity {:?} not found", label))
     }
 
-    fn execute_expect_failure(&mut self, verbose: bool) -> Receipt {
-        let receipt = self.execute(verbose);
-        receipt.execution_receipt.expect_commit_failure();
-        receipt
+    /// Owner badge of an identity, held by the main account for identities created by `new_identity`
+    pub fn identity_owner_badge(&self, identity: ComponentAddress) -> NonFungibleGlobalId {
+        NonFungibleGlobalId::new(
+            IDENTITY_OWNER_BADGE,
+            NonFungibleLocalId::bytes(identity.as_node_id().0).unwrap(),
+        )
     }
 
-    fn execute_expect_rejection(&mut self, verbose: bool) -> Receipt {
-        let receipt = self.execute(verbose);
-        receipt.execution_receipt.expect_rejection();
-        receipt
+    /// Creates U, V, J and K on first access. D0 and D6 require
+    /// `TestEnvironmentBuilder::extra_resources`.
+    pub fn resource(&mut self, test_address: TestAddress) -> ResourceAddress {
+        match test_address {
+            TestAddress::A => self.a_address,
+            TestAddress::B => self.b_address,
+            TestAddress::X => self.x_address,
+            TestAddress::Y => self.y_address,
+            TestAddress::U => self.u_address(),
+            TestAddress::V => self.v_address(),
+            TestAddress::D0 => self
+                .d0_address
+                .expect("D0 requires TestEnvironmentBuilder::extra_resources"),
+            TestAddress::D6 => self
+                .d6_address
+                .expect("D6 requires TestEnvironmentBuilder::extra_resources"),
+            TestAddress::J => self.j_nft_address(),
+            TestAddress::K => self.k_nft_address(),
+        }
     }
 
-    fn name(&mut self, name: &str) -> String {
-        format!("{}_{}", name, self.env().instruction_counter)
+    /// Token U (supply 1000000000), mintable and burnable with the admin badge.
+    /// Created on first access and registered under "U", see `resource_by_symbol`.
+    pub fn u_address(&mut self) -> ResourceAddress {
+        if self.u_address.is_none() {
+            self.u_address = Some(self.create_admin_fungible("U", dec!(1000000000)));
+        }
+        self.u_address.unwrap()
     }
 
-    fn reset_instructions(&mut self) {
-        self.env().instruction_ids_by_label = HashMap::new();
-        self.env().instruction_counter = INSTRUCTION_COUNTER_INIT;
+    /// Token V (supply 10000000), mintable and burnable with the admin badge.
+    /// Created on first access and registered under "V", see `resource_by_symbol`.
+    pub fn v_address(&mut self) -> ResourceAddress {
+        if self.v_address.is_none() {
+            self.v_address = Some(self.create_admin_fungible("V", dec!(10000000)));
+        }
+        self.v_address.unwrap()
     }
-}
 
-pub struct Receipt {
-    pub execution_receipt: TransactionReceipt,
-    pub preview_receipt: TransactionReceipt,
-    pub instruction_ids_by_label: HashMap<String, Vec<usize>>,
-}
+    /// NFT collection J, see `TestEnvironmentBuilder::nft_collection_size`.
+    /// Created on first access.
+    pub fn j_nft_address(&mut self) -> ResourceAddress {
+        if self.j_nft_address.is_none() {
+            self.j_nft_address = Some(self.create_nft_collection());
+        }
+        self.j_nft_address.unwrap()
+    }
 
-impl Receipt {
-    pub fn output_buckets(&self, instruction_label: &str) -> Vec<Vec<ResourceSpecifier>> {
-        self.preview_receipt
-            .output_buckets(self.instruction_ids(instruction_label))
+    /// NFT collection K, see `TestEnvironmentBuilder::nft_collection_size`.
+    /// Created on first access.
+    pub fn k_nft_address(&mut self) -> ResourceAddress {
+        if self.k_nft_address.is_none() {
+            self.k_nft_address = Some(self.create_nft_collection());
+        }
+        self.k_nft_address.unwrap()
+    }
+
+    fn create_admin_fungible(&mut self, symbol: &str, supply: Decimal) -> ResourceAddress {
+        let resource_address = self.test_runner.create_fungible_resource_with_roles(
+            supply,
+            DIVISIBILITY_MAXIMUM,
+            self.account,
+            admin_badge_roles(self.admin_badge_address),
+            ModuleConfig::default(),
+        );
+        self.resources_by_symbol
+            .insert(symbol.to_string(), resource_address);
+        self.address_labels_cache = None;
+        resource_address
+    }
+
+    fn create_nft_collection(&mut self) -> ResourceAddress {
+        self.test_runner.create_non_fungible_resource_advanced(
+            NonFungibleResourceRoles::default(),
+            self.account,
+            self.config.nft_collection_size.unwrap_or(3),
+        )
+    }
+
+    // Like `resource_by_symbol`, but also creates U and V if not accessed yet
+    fn try_resource_by_symbol(&mut self, symbol: &str) -> Result<ResourceAddress, TestEnvError> {
+        match symbol {
+            "U" => Ok(self.u_address()),
+            "V" => Ok(self.v_address()),
+            _ => self
+                .resources_by_symbol
+                .get(symbol)
+                .copied()
+                .ok_or_else(|| TestEnvError::ResourceNotFound(symbol.to_string())),
+        }
+    }
+
+    /// Balance of the main account
+    pub fn balance(&mut self, test_address: TestAddress) -> Decimal {
+        let resource_address = self.resource(test_address);
+        self.test_runner
+            .get_component_balance(self.account, resource_address)
+    }
+
+    /// Creates a fungible resource with its whole supply deposited into the main account and
+    /// registers it under `symbol`, see `resource_by_symbol`. The symbol is only used as
+    /// registry key, so it also has to be part of `metadata` if the resource should carry it.
+    pub fn create_resource(
+        &mut self,
+        symbol: &str,
+        supply: Decimal,
+        divisibility: u8,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let resource_address = self.test_runner.create_fungible_resource_advanced(
+            supply,
+            divisibility,
+            self.account,
+            metadata,
+        );
+        self.resources_by_symbol
+            .insert(symbol.to_string(), resource_address);
+        resource_address
+    }
+
+    /// Mints `amount` of a resource mintable with the admin badge (A, B, U, V) into `account`
+    pub fn mint_to_account(
+        &mut self,
+        resource: ResourceAddress,
+        amount: Decimal,
+        account: ComponentAddress,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .mint_fungible(resource, amount)
+            .try_deposit_entire_worktop_or_abort(account, None)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Burns `amount` of a resource burnable with the admin badge (A, B, U, V) from `account`,
+    /// which has to be one of the accounts created by the environment
+    pub fn burn_from_account(
+        &mut self,
+        resource: ResourceAddress,
+        amount: Decimal,
+        account: ComponentAddress,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .withdraw_from_account(account, resource, amount)
+            .burn_all_from_worktop(resource)
+            .build();
+        let initial_proofs = self.signatures(&[self.account, account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Recalls `amount` from a vault of the recallable resource into the main account
+    pub fn recall_from_vault(&mut self, vault_id: NodeId, amount: Decimal) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .recall(InternalAddress::new_or_panic(vault_id.0), amount)
+            .try_deposit_entire_worktop_or_abort(self.account, None)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Freezes withdraws, deposits and burns of a vault of the recallable resource
+    pub fn freeze_vault(&mut self, vault_id: NodeId) {
+        let vault_address = InternalAddress::new_or_panic(vault_id.0);
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .freeze_withdraw(vault_address)
+            .freeze_deposit(vault_address)
+            .freeze_burn(vault_address)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    pub fn unfreeze_vault(&mut self, vault_id: NodeId) {
+        let vault_address = InternalAddress::new_or_panic(vault_id.0);
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .unfreeze_withdraw(vault_address)
+            .unfreeze_deposit(vault_address)
+            .unfreeze_burn(vault_address)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Funds `account` with exactly `amount` XRD by calling the faucet as often as needed.
+    /// Fees are paid by the faucet, surplus XRD of the last call is deposited into a sink account.
+    pub fn fund_account_xrd(&mut self, account: ComponentAddress, amount: Decimal) {
+        let sink_account = faucet_sink_account();
+        let mut remaining = amount;
+        while remaining > Decimal::ZERO {
+            let funding = remaining.min(FAUCET_FREE_XRD);
+            let manifest = ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .get_free_xrd_from_faucet()
+                .take_from_worktop(XRD, funding, "funding")
+                .try_deposit_or_abort(account, None, "funding")
+                .try_deposit_entire_worktop_or_abort(sink_account, None)
+                .build();
+            self.test_runner
+                .execute_manifest(manifest, vec![])
+                .expect_commit_success();
+            remaining -= funding;
+        }
+    }
+
+    /// Transfers `amount` of `resource` between two accounts created by the environment
+    pub fn transfer(
+        &mut self,
+        from: ComponentAddress,
+        to: ComponentAddress,
+        resource: ResourceAddress,
+        amount: Decimal,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(from, resource, amount)
+            .try_deposit_entire_worktop_or_abort(to, None)
+            .build();
+        let initial_proofs = self.signatures(&[from]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Transfers the given NFTs between two accounts created by the environment
+    pub fn transfer_non_fungibles(
+        &mut self,
+        from: ComponentAddress,
+        to: ComponentAddress,
+        resource: ResourceAddress,
+        ids: IndexSet<NonFungibleLocalId>,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_non_fungibles_from_account(from, resource, ids)
+            .try_deposit_entire_worktop_or_abort(to, None)
+            .build();
+        let initial_proofs = self.signatures(&[from]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Public key of an account created by the environment (main, dapp definition, additional
+    /// or labeled account)
+    pub fn public_key_of(&self, account: ComponentAddress) -> Option<PublicKey> {
+        if account == self.account {
+            return Some(self.public_key.into());
+        }
+        std::iter::once(&self.dapp_definition_account)
+            .chain(self.accounts.iter())
+            .chain(self.accounts_by_label.values())
+            .find(|test_account| test_account.address == account)
+            .map(|test_account| test_account.key)
+    }
+
+    fn private_key_of(&self, public_key: &PublicKey) -> PrivateKey {
+        std::iter::once(&self.main_account)
+            .chain(self.accounts.iter())
+            .chain(self.accounts_by_label.values())
+            .find(|test_account| &test_account.key == public_key)
+            .unwrap_or_else(|| panic!("Key {:?} not created by environment", public_key))
+            .private_key()
+    }
+
+    /// Signature proofs of the owner keys of the given environment accounts
+    pub(crate) fn signatures(&self, accounts: &[ComponentAddress]) -> Vec<NonFungibleGlobalId> {
+        accounts
+            .iter()
+            .map(|&account| {
+                let public_key = self
+                    .public_key_of(account)
+                    .unwrap_or_else(|| panic!("Account {:?} not created by environment", account));
+                NonFungibleGlobalId::from_public_key(&public_key)
+            })
+            .collect()
+    }
+
+    // Main account, unlabeled and labeled accounts created by the environment
+    fn environment_accounts(&self) -> Vec<ComponentAddress> {
+        std::iter::once(self.account)
+            .chain(
+                self.accounts
+                    .iter()
+                    .map(|test_account| test_account.address),
+            )
+            .chain(
+                self.accounts_by_label
+                    .values()
+                    .map(|test_account| test_account.address),
+            )
+            .collect()
+    }
+
+    /// XRD paid for fees per account created by the environment. The owners of the paying
+    /// vaults are looked up once and cached, as the owner of a vault never changes.
+    fn account_fees(&mut self, receipt: &TransactionReceipt) -> HashMap<ComponentAddress, Decimal> {
+        let mut fees = HashMap::new();
+        let TransactionResult::Commit(commit_result) = &receipt.result else {
+            return fees;
+        };
+        for (vault_id, fee) in &commit_result.fee_source.paying_vaults {
+            if !self.fee_vault_owners.contains_key(vault_id) {
+                let owner = self.environment_accounts().into_iter().find(|&account| {
+                    self.test_runner
+                        .get_component_vaults(account, XRD)
+                        .contains(vault_id)
+                });
+                self.fee_vault_owners.insert(*vault_id, owner);
+            }
+            if let Some(owner) = self.fee_vault_owners[vault_id] {
+                *fees.entry(owner).or_default() += *fee;
+            }
+        }
+        fees
+    }
+
+    // Labels of `address_labels`, only rebuilt after new addresses were registered
+    fn shared_address_labels(&mut self) -> Arc<Vec<(String, GlobalAddress)>> {
+        let address_labels = match &self.address_labels_cache {
+            Some(address_labels) => address_labels.clone(),
+            None => Arc::new(self.address_labels()),
+        };
+        self.address_labels_cache = Some(address_labels.clone());
+        address_labels
+    }
+
+    /// Adds an executed transaction to the history and wraps its receipt, which shares the
+    /// transaction receipt with the history entry
+    fn record_transaction(
+        &mut self,
+        manifest: TransactionManifestV1,
+        notarized_transaction: Option<RawNotarizedTransaction>,
+        execution_receipt: TransactionReceipt,
+        preview: Option<TransactionReceipt>,
+        instruction_ids_by_label: HashMap<String, Vec<usize>>,
+    ) -> Receipt {
+        let execution_receipt = Arc::new(execution_receipt);
+        self.history.push(ExecutedTransaction {
+            manifest,
+            receipt: execution_receipt.clone(),
+            notarized_transaction,
+        });
+        Receipt {
+            fees: self.account_fees(&execution_receipt),
+            execution_receipt,
+            preview,
+            instruction_ids_by_label,
+            balance_changes: OnceCell::new(),
+            address_labels: self.shared_address_labels(),
+            transaction_index: self.history.len() - 1,
+        }
+    }
+
+    /// Creates a non fungible resource with one NFT per item (integer ids starting at 1)
+    /// deposited into the main account, so blueprints reading the NFT data can be tested
+    pub fn create_non_fungible_resource_with_data<T: NonFungibleData + ManifestEncode>(
+        &mut self,
+        items: Vec<T>,
+    ) -> ResourceAddress {
+        let entries: Vec<(NonFungibleLocalId, T)> = items
+            .into_iter()
+            .zip(1..)
+            .map(|(item, id)| (NonFungibleLocalId::integer(id), item))
+            .collect();
+        self.test_runner.create_non_fungible_resource_with_entries(
+            NonFungibleIdType::Integer,
+            entries,
+            self.account,
+            ModuleConfig::default(),
+        )
+    }
+
+    /// Decoded data of the NFT `id`, reflecting all updates of its mutable fields so far
+    pub fn non_fungible_data<T: NonFungibleData>(
+        &self,
+        resource: ResourceAddress,
+        id: NonFungibleLocalId,
+    ) -> T {
+        self.test_runner.get_non_fungible_data::<T>(resource, id)
+    }
+
+    pub fn badge(&self, name: &str) -> ResourceAddress {
+        *self
+            .badges
+            .get(name)
+            .unwrap_or_else(|| panic!("Badge {:?} not found", name))
+    }
+
+    /// U and V are only registered after their first access, see `u_address`
+    pub fn resource_by_symbol(&self, symbol: &str) -> ResourceAddress {
+        *self
+            .resources_by_symbol
+            .get(symbol)
+            .unwrap_or_else(|| panic!("Resource {:?} not found", symbol))
+    }
+
+    pub fn package_address(&self, package_name: &str) -> PackageAddress {
+        self.try_package_address(package_name)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_package_address(&self, package_name: &str) -> Result<PackageAddress, TestEnvError> {
+        self.package_addresses
+            .get(package_name)
+            .copied()
+            .ok_or_else(|| TestEnvError::PackageNotFound(package_name.to_string()))
+    }
+
+    /// Creates and retrieves snapshot of the TestEnvironment
+    /// IMPORTANT: The states of the following fields are dropped:
+    /// - MenifestBuilder
+    /// - instruction_counter
+    /// - instruction_ids_by_label
+    /// - preview_enabled
+    /// - preview_flags
+    /// - active account
+    /// - fee_config
+    /// - history
+    pub fn create_snapshot(&self) -> TestEnvironmentSnapshot {
+        TestEnvironmentSnapshot::from(self)
+    }
+
+    // Revives `snapshot` in place, keeping the execution settings of this environment
+    // (fee config, preview and active account) which are not part of snapshots
+    fn revive_with_settings(&mut self, snapshot: &TestEnvironmentSnapshot) {
+        let mut test_environment = snapshot.revive();
+        test_environment.preview_enabled = self.preview_enabled;
+        test_environment.preview_flags = self.preview_flags.clone();
+        test_environment.active_account_label = self.active_account_label.clone();
+        test_environment.fee_config = self.fee_config.clone();
+        *self = test_environment;
+    }
+
+    pub fn config(&self) -> &TestEnvironmentConfig {
+        &self.config
+    }
+
+    /// Applies a protocol update to the current ledger state, so that state created under
+    /// the previous protocol version can be verified to still work after the update.
+    ///
+    /// The simulator has to be built without that update, e.g.:
+    /// ```ignore
+    /// let mut test_environment = TestEnvironmentBuilder::new()
+    ///     .protocol_version(ProtocolVersion::Anemone)
+    ///     .build();
+    /// // publish and exercise components
+    /// test_environment.apply_protocol_update(ProtocolUpdate::Bottlenose);
+    /// // verify the components still behave the same
+    /// ```
+    pub fn apply_protocol_update(&mut self, protocol_update: ProtocolUpdate) {
+        let network = NetworkDefinition::simulator();
+        let state_updates =
+            protocol_update.generate_state_updates(self.test_runner.substate_db(), &network);
+        for state_update in state_updates {
+            let database_updates = state_update.create_database_updates::<SpreadPrefixKeyMapper>();
+            self.test_runner.substate_db_mut().commit(&database_updates);
+        }
+    }
+
+    /// Protocol version of the current ledger state, detected by the native packages
+    /// introduced with each protocol update
+    pub fn ledger_protocol_version(&self) -> ProtocolVersion {
+        let package_exists = |package_address: PackageAddress| {
+            self.test_runner
+                .substate_db()
+                .get_mapped::<SpreadPrefixKeyMapper, TypeInfoSubstate>(
+                    package_address.as_node_id(),
+                    TYPE_INFO_FIELD_PARTITION,
+                    &TypeInfoField::TypeInfo.into(),
+                )
+                .is_some()
+        };
+        if package_exists(LOCKER_PACKAGE) {
+            ProtocolVersion::Bottlenose
+        } else if package_exists(CRYPTO_UTILS_PACKAGE) {
+            ProtocolVersion::Anemone
+        } else {
+            ProtocolVersion::Babylon
+        }
+    }
+}
+
+/// NOTE: This should only be used for single clones,
+/// since it clones by taking a snapshot and then recovering from it.
+/// For the creation of many clones, it is advised to manually snapshot
+/// and then creating as many TestEnvironments as needed from
+/// that snapshot
+impl Clone for TestEnvironment {
+    fn clone(&self) -> Self {
+        self.create_snapshot().revive()
+    }
+}
+
+pub struct TestEnvironmentSnapshot {
+    pub test_runner_snapshot: LedgerSimulatorSnapshot,
+
+    pub package_addresses: HashMap<String, PackageAddress>,
+    pub previous_package_addresses: HashMap<String, Vec<PackageAddress>>,
+    pub package_definitions: HashMap<String, PackageDefinition>,
+    pub public_key: Secp256k1PublicKey,
+    pub account: ComponentAddress,
+    pub dapp_definition: ComponentAddress,
+    pub accounts: Vec<TestAccount>,
+    pub accounts_by_label: HashMap<String, TestAccount>,
+    pub identities_by_label: HashMap<String, ComponentAddress>,
+    pub main_account: TestAccount,
+    pub dapp_definition_account: TestAccount,
+    pub(crate) key_generator: KeyGenerator,
+
+    pub admin_badge_address: ResourceAddress,
+    pub badges: HashMap<String, ResourceAddress>,
+    pub a_address: ResourceAddress,
+    pub b_address: ResourceAddress,
+    pub x_address: ResourceAddress,
+    pub y_address: ResourceAddress,
+    /// `None` if not accessed before taking the snapshot
+    pub u_address: Option<ResourceAddress>,
+    pub v_address: Option<ResourceAddress>,
+    pub d0_address: Option<ResourceAddress>,
+    pub d6_address: Option<ResourceAddress>,
+    pub recallable_address: Option<ResourceAddress>,
+    pub j_nft_address: Option<ResourceAddress>,
+    pub k_nft_address: Option<ResourceAddress>,
+    pub string_nft_address: Option<ResourceAddress>,
+    pub bytes_nft_address: Option<ResourceAddress>,
+    pub ruid_nft_address: Option<ResourceAddress>,
+    pub resources_by_symbol: HashMap<String, ResourceAddress>,
+
+    pub config: TestEnvironmentConfig,
+}
+
+impl TestEnvironmentSnapshot {
+    /// Creates snapshot of the TestEnvironment
+    /// IMPORTANT: The states of the following fields are dropped:
+    /// - MenifestBuilder
+    /// - instruction_counter
+    /// - instruction_ids_by_label
+    /// - preview_enabled
+    /// - preview_flags
+    /// - active account
+    /// - fee_config
+    /// - history
+    pub fn from(test_environment: &TestEnvironment) -> TestEnvironmentSnapshot {
+        Self {
+            test_runner_snapshot: test_environment.test_runner.create_snapshot(),
+            package_addresses: test_environment.package_addresses.clone(),
+            previous_package_addresses: test_environment.previous_package_addresses.clone(),
+            package_definitions: test_environment.package_definitions.clone(),
+            public_key: test_environment.public_key.clone(),
+            account: test_environment.account.clone(),
+            dapp_definition: test_environment.dapp_definition.clone(),
+            accounts: test_environment.accounts.clone(),
+            accounts_by_label: test_environment.accounts_by_label.clone(),
+            identities_by_label: test_environment.identities_by_label.clone(),
+            main_account: test_environment.main_account.clone(),
+            dapp_definition_account: test_environment.dapp_definition_account.clone(),
+            key_generator: test_environment.key_generator.clone(),
+            admin_badge_address: test_environment.admin_badge_address.clone(),
+            badges: test_environment.badges.clone(),
+            a_address: test_environment.a_address.clone(),
+            b_address: test_environment.b_address.clone(),
+            x_address: test_environment.x_address.clone(),
+            y_address: test_environment.y_address.clone(),
+            u_address: test_environment.u_address.clone(),
+            v_address: test_environment.v_address.clone(),
+            d0_address: test_environment.d0_address.clone(),
+            d6_address: test_environment.d6_address.clone(),
+            recallable_address: test_environment.recallable_address.clone(),
+            j_nft_address: test_environment.j_nft_address.clone(),
+            k_nft_address: test_environment.k_nft_address.clone(),
+            string_nft_address: test_environment.string_nft_address.clone(),
+            bytes_nft_address: test_environment.bytes_nft_address.clone(),
+            ruid_nft_address: test_environment.ruid_nft_address.clone(),
+            resources_by_symbol: test_environment.resources_by_symbol.clone(),
+
+            config: test_environment.config.clone(),
+        }
+    }
+
+    /// Retrieves a TestEnvironment from the snapshot
+    /// IMPORTANT: The states of the following fields are not recovered:
+    /// - MenifestBuilder
+    /// - instruction_counter
+    /// - instruction_ids_by_label
+    /// - preview_enabled
+    /// - preview_flags
+    /// - active account
+    /// - fee_config
+    /// - history
+    pub fn revive(&self) -> TestEnvironment {
+        TestEnvironment {
+            test_runner: self
+                .config
+                .simulator_builder()
+                .build_from_snapshot(self.test_runner_snapshot.clone()),
+            manifest_builder: ManifestBuilder::new().lock_standard_test_fee(self.account),
+
+            package_addresses: self.package_addresses.clone(),
+            previous_package_addresses: self.previous_package_addresses.clone(),
+            package_definitions: self.package_definitions.clone(),
+            public_key: self.public_key.clone(),
+            account: self.account.clone(),
+            dapp_definition: self.dapp_definition.clone(),
+            accounts: self.accounts.clone(),
+            accounts_by_label: self.accounts_by_label.clone(),
+            identities_by_label: self.identities_by_label.clone(),
+            main_account: self.main_account.clone(),
+            dapp_definition_account: self.dapp_definition_account.clone(),
+            key_generator: self.key_generator.clone(),
+
+            admin_badge_address: self.admin_badge_address.clone(),
+            badges: self.badges.clone(),
+            a_address: self.a_address.clone(),
+            b_address: self.b_address.clone(),
+            x_address: self.x_address.clone(),
+            y_address: self.y_address.clone(),
+            u_address: self.u_address.clone(),
+            v_address: self.v_address.clone(),
+            d0_address: self.d0_address.clone(),
+            d6_address: self.d6_address.clone(),
+            recallable_address: self.recallable_address.clone(),
+            j_nft_address: self.j_nft_address.clone(),
+            k_nft_address: self.k_nft_address.clone(),
+            string_nft_address: self.string_nft_address.clone(),
+            bytes_nft_address: self.bytes_nft_address.clone(),
+            ruid_nft_address: self.ruid_nft_address.clone(),
+            resources_by_symbol: self.resources_by_symbol.clone(),
+
+            instruction_counter: INSTRUCTION_COUNTER_INIT,
+            instruction_ids_by_label: HashMap::new(),
+            preview_enabled: true,
+            preview_flags: PreviewFlags::default(),
+            active_account_label: None,
+            fee_config: FeeConfig::default(),
+            history: vec![],
+            fee_vault_owners: HashMap::new(),
+            address_labels_cache: None,
+
+            setup_timings: SetupTimings::default(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl TestEnvironment {
+    /// Sets the account paying the fees, signing and receiving the remaining resources for
+    /// all following executions (can be switched at any time, even with pending instructions)
+    pub fn set_active_account(&mut self, label: &str) {
+        self.test_account(label);
+        self.active_account_label = Some(label.to_string());
+    }
+
+    /// Switches back to the main account
+    pub fn reset_active_account(&mut self) {
+        self.active_account_label = None;
+    }
+
+    fn active_account(&self) -> (ComponentAddress, PublicKey) {
+        match &self.active_account_label {
+            Some(label) => {
+                let test_account = self.test_account(label);
+                (test_account.address, test_account.key)
+            }
+            None => (self.account, self.public_key.into()),
+        }
+    }
+
+    fn fee_payer(&self, active_account: ComponentAddress) -> ComponentAddress {
+        match self.fee_config.payer {
+            FeePayer::ActiveAccount => active_account,
+            FeePayer::Account(account) | FeePayer::Component(account) => account,
+        }
+    }
+
+    /// Public keys which have to sign when executing as `active_account`
+    fn signer_public_keys(&self, active_account: ComponentAddress) -> Vec<PublicKey> {
+        let mut signers = vec![active_account];
+        if let FeePayer::Account(account) = self.fee_config.payer {
+            if account != active_account {
+                signers.push(account);
+            }
+        }
+        signers
+            .into_iter()
+            .map(|account| {
+                self.public_key_of(account)
+                    .unwrap_or_else(|| panic!("Account {:?} not created by environment", account))
+            })
+            .collect()
+    }
+
+    /// Builds the pending instructions with a final deposit to `account`. The placeholder fee lock
+    /// always added as first instruction is replaced by the one configured in `fee_config`.
+    fn build_manifest(&mut self, account: ComponentAddress) -> TransactionManifestV1 {
+        let manifest_builder = mem::replace(&mut self.manifest_builder, ManifestBuilder::new());
+        let mut manifest = manifest_builder.deposit_batch(account).build();
+        manifest.instructions[0] = ManifestBuilder::new()
+            .call_method(
+                self.fee_payer(account),
+                "lock_fee",
+                manifest_args!(self.fee_config.amount),
+            )
+            .build()
+            .instructions
+            .remove(0);
+        manifest
+    }
+
+    fn execute_manifest_builder(
+        &mut self,
+        verbose: bool,
+        preview_flags: Option<PreviewFlags>,
+        epoch_range: Option<(Epoch, Epoch)>,
+    ) -> Receipt {
+        let (account_component, _) = self.active_account();
+        let signer_public_keys = self.signer_public_keys(account_component);
+        let manifest = self.build_manifest(account_component);
+        let tip_percentage = self.fee_config.tip_percentage;
+        let epoch_range = match epoch_range {
+            None if tip_percentage > 0 => {
+                let current_epoch = self.test_runner.get_current_epoch();
+                Some((current_epoch, current_epoch.after(TIP_EPOCH_RANGE).unwrap()))
+            }
+            _ => epoch_range,
+        };
+        let preview_receipt = preview_flags.map(|preview_flags| {
+            self.test_runner.preview_manifest(
+                manifest.clone(),
+                signer_public_keys.clone(),
+                tip_percentage,
+                preview_flags,
+            )
+        });
+        let (execution_receipt, notarized_transaction) = match epoch_range {
+            Some((start_epoch_inclusive, end_epoch_exclusive)) => {
+                let notarized_transaction = self.notarize(
+                    manifest.clone(),
+                    &signer_public_keys,
+                    start_epoch_inclusive,
+                    end_epoch_exclusive,
+                );
+                let execution_receipt = self
+                    .test_runner
+                    .execute_notarized_transaction(&notarized_transaction);
+                (execution_receipt, Some(notarized_transaction))
+            }
+            None => {
+                let execution_receipt = self.test_runner.execute_manifest(
+                    manifest.clone(),
+                    signer_public_keys
+                        .iter()
+                        .map(NonFungibleGlobalId::from_public_key)
+                        .collect::<Vec<_>>(),
+                );
+                (execution_receipt, None)
+            }
+        };
+        let instruction_mapping = self.instruction_ids_by_label.clone();
+        self.reset_instructions();
+        let manifest_builder = mem::replace(&mut self.manifest_builder, ManifestBuilder::new());
+        self.manifest_builder = manifest_builder.lock_standard_test_fee(self.account);
+        let receipt = self.record_transaction(
+            manifest,
+            notarized_transaction,
+            execution_receipt,
+            preview_receipt,
+            instruction_mapping,
+        );
+        if verbose {
+            println!("{}", receipt.summary());
+        }
+        receipt
+    }
+
+    /// Builds `manifest` into a fully notarized transaction (notarized by the first signer),
+    /// which goes through the same validation as transactions submitted to a node
+    fn notarize(
+        &mut self,
+        manifest: TransactionManifestV1,
+        signer_public_keys: &[PublicKey],
+        start_epoch_inclusive: Epoch,
+        end_epoch_exclusive: Epoch,
+    ) -> RawNotarizedTransaction {
+        let private_keys: Vec<PrivateKey> = signer_public_keys
+            .iter()
+            .map(|public_key| self.private_key_of(public_key))
+            .collect();
+        let notary = &private_keys[0];
+        let mut transaction_builder = TransactionBuilder::new()
+            .header(TransactionHeaderV1 {
+                network_id: NetworkDefinition::simulator().id,
+                start_epoch_inclusive,
+                end_epoch_exclusive,
+                nonce: self.test_runner.next_transaction_nonce(),
+                notary_public_key: notary.public_key(),
+                notary_is_signatory: true,
+                tip_percentage: self.fee_config.tip_percentage,
+            })
+            .manifest(manifest);
+        for private_key in &private_keys[1..] {
+            transaction_builder = transaction_builder.sign(private_key);
+        }
+        transaction_builder
+            .notarize(notary)
+            .build()
+            .to_raw()
+            .unwrap()
+    }
+
+    /// Submits the notarized transaction at `transaction_index` of the history again, with the
+    /// exact same intent. Replay protection is expected to reject it as duplicate:
+    /// ```ignore
+    /// helper.execute_with_epoch_range(epoch, epoch.after(10).unwrap(), true);
+    /// let last = test_environment.transaction_count() - 1;
+    /// test_environment.resubmit(last).execution_receipt.expect_rejection();
+    /// ```
+    pub fn resubmit(&mut self, transaction_index: usize) -> Receipt {
+        let executed_transaction = self.history[transaction_index].clone();
+        let notarized_transaction = executed_transaction.notarized_transaction.expect(
+            "Only notarized transactions can be resubmitted, see `execute_with_epoch_range`",
+        );
+        let execution_receipt = self
+            .test_runner
+            .execute_notarized_transaction(&notarized_transaction);
+        self.record_transaction(
+            executed_transaction.manifest,
+            Some(notarized_transaction),
+            execution_receipt,
+            None,
+            HashMap::new(),
+        )
+    }
+
+    /// Executes pre-built manifests one after another, signed by the active account (and the
+    /// fee paying account if configured). Manifests have to lock their fees themselves.
+    pub fn execute_all(&mut self, manifests: Vec<TransactionManifestV1>) -> Vec<Receipt> {
+        let (account_component, _) = self.active_account();
+        let initial_proofs: Vec<NonFungibleGlobalId> = self
+            .signer_public_keys(account_component)
+            .iter()
+            .map(NonFungibleGlobalId::from_public_key)
+            .collect();
+        manifests
+            .into_iter()
+            .map(|manifest| {
+                let execution_receipt = self
+                    .test_runner
+                    .execute_manifest(manifest.clone(), initial_proofs.clone());
+                self.record_transaction(manifest, None, execution_receipt, None, HashMap::new())
+            })
+            .collect()
+    }
+
+    /// All transactions executed via `execute` or `execute_all` so far, in order
+    pub fn history(&self) -> &[ExecutedTransaction] {
+        &self.history
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.history.len()
+    }
+
+    /// XRD paid for all transactions in the history
+    pub fn total_fees_paid_xrd(&self) -> Decimal {
+        self.history
+            .iter()
+            .map(|transaction| transaction.receipt.fee_summary.total_cost())
+            .fold(Decimal::ZERO, |total, fee| total + fee)
+    }
+
+    /// Writes the currently built manifest as transaction manifest text (.rtm) to `path`,
+    /// e.g. to replay a failing test case with resim. The built manifest is written as is
+    /// (without the final deposit and fee lock added on execution), if nothing is built
+    /// besides the fee lock the last executed manifest is written instead.
+    pub fn dump_manifest<P: AsRef<Path>>(&self, path: P) {
+        let pending_instructions = self.manifest_builder.instructions();
+        let instructions: &[InstructionV1] =
+            match pending_instructions.len() > INSTRUCTION_COUNTER_INIT {
+                true => pending_instructions,
+                false => {
+                    &self
+                        .history
+                        .last()
+                        .expect("No manifest built or executed yet")
+                        .manifest
+                        .instructions
+                }
+            };
+        let manifest_text = decompile(instructions, &NetworkDefinition::simulator())
+            .expect("Failed to decompile manifest");
+        std::fs::write(path.as_ref(), manifest_text)
+            .unwrap_or_else(|_| panic!("Failed to write manifest to {:?}", path.as_ref()));
+    }
+
+    /// Stable labels of the addresses known to the environment: `account`, `dapp_definition`,
+    /// `account:<label>`, `identity:<label>`, resource symbols (`A`, `X`, ..., `XRD`) and `package:<name>`
+    pub fn address_labels(&self) -> Vec<(String, GlobalAddress)> {
+        let mut addresses: Vec<(String, GlobalAddress)> = vec![
+            ("account".to_string(), self.account.into()),
+            ("dapp_definition".to_string(), self.dapp_definition.into()),
+            ("XRD".to_string(), XRD.into()),
+        ];
+        addresses.extend(self.accounts_by_label.iter().map(|(label, test_account)| {
+            (format!("account:{}", label), test_account.address.into())
+        }));
+        addresses.extend(
+            self.identities_by_label
+                .iter()
+                .map(|(label, identity)| (format!("identity:{}", label), (*identity).into())),
+        );
+        addresses.extend(
+            self.resources_by_symbol
+                .iter()
+                .map(|(symbol, resource)| (symbol.clone(), (*resource).into())),
+        );
+        addresses.extend(
+            self.package_addresses
+                .iter()
+                .map(|(name, package)| (format!("package:{}", name), (*package).into())),
+        );
+        addresses
+    }
+
+    /// Compiles the transaction manifest file at `path` and executes it via `execute_all`.
+    /// Placeholders of the form `${name}` are replaced by the given `substitutions` first,
+    /// then by the environment's addresses: `${account}`, `${dapp_definition}`, labeled accounts
+    /// as `${account:label}`, resources by symbol as `${A}`, `${X}`, ... and packages as `${package:name}`.
+    pub fn execute_manifest_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        substitutions: HashMap<&str, String>,
+    ) -> Receipt {
+        let mut manifest_text = std::fs::read_to_string(path.as_ref())
+            .unwrap_or_else(|_| panic!("Failed to read manifest {:?}", path.as_ref()));
+        for (name, value) in substitutions {
+            manifest_text = manifest_text.replace(&format!("${{{}}}", name), &value);
+        }
+        let encoder = AddressBech32Encoder::for_simulator();
+        for (name, address) in self.address_labels() {
+            manifest_text = manifest_text.replace(
+                &format!("${{{}}}", name),
+                &encoder.encode(address.as_node_id().as_bytes()).unwrap(),
+            );
+        }
+        let manifest = compile(
+            &manifest_text,
+            &NetworkDefinition::simulator(),
+            BlobProvider::new(),
+        )
+        .unwrap_or_else(|_| panic!("Failed to compile manifest {:?}", path.as_ref()));
+        self.execute_all(vec![manifest]).remove(0)
+    }
+
+    pub fn failed_transactions(&self) -> Vec<&ExecutedTransaction> {
+        self.history
+            .iter()
+            .filter(|transaction| !transaction.is_success())
+            .collect()
+    }
+
+    fn preview_manifest_builder(&mut self, verbose: bool) -> TransactionReceipt {
+        let (account_component, _) = self.active_account();
+        let manifest = self.build_manifest(account_component);
+        let mut preview_flags = self.preview_flags.clone();
+        preview_flags.assume_all_signature_proofs = true;
+        let mut preview_receipt = self.test_runner.preview_manifest(
+            manifest,
+            vec![],
+            self.fee_config.tip_percentage,
+            preview_flags,
+        );
+        if verbose {
+            // Same summary as for executions, the preview is not part of the history though
+            let receipt = Receipt {
+                execution_receipt: Arc::new(preview_receipt),
+                preview: None,
+                instruction_ids_by_label: self.instruction_ids_by_label.clone(),
+                fees: HashMap::new(),
+                balance_changes: OnceCell::new(),
+                address_labels: self.shared_address_labels(),
+                transaction_index: self.history.len(),
+            };
+            println!("{}", receipt.summary());
+            preview_receipt =
+                Arc::into_inner(receipt.execution_receipt).expect("Preview receipt is not shared");
+        }
+        self.reset_instructions();
+        self.manifest_builder = ManifestBuilder::new().lock_standard_test_fee(self.account);
+        preview_receipt
+    }
+}
+
+pub trait TestHelperExecution {
+    /// Environment the helper builds into, either owned (`&mut self.env`) or
+    /// shared with other helpers (`self.env.env()` of a `TestEnvironmentHandle`)
+    fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_;
+
+    /// Prefix of the names and labels of this helper, to keep them apart from the ones of
+    /// other helpers building into the same manifest (e.g. `"pool"` and `"router"`)
+    fn namespace(&self) -> Option<&str> {
+        None
+    }
+
+    /// Previews (unless disabled via `TestEnvironment::preview_enabled`) and executes the built manifest
+    fn execute(&mut self, verbose: bool) -> Receipt {
+        let mut env = self.env();
+        let preview_flags = match env.preview_enabled {
+            true => Some(env.preview_flags.clone()),
+            false => None,
+        };
+        env.execute_manifest_builder(verbose, preview_flags, None)
+    }
+
+    /// Like `execute`, but previews with the given flags instead of `TestEnvironment::preview_flags`
+    fn execute_with_preview_flags(
+        &mut self,
+        preview_flags: PreviewFlags,
+        verbose: bool,
+    ) -> Receipt {
+        self.env()
+            .execute_manifest_builder(verbose, Some(preview_flags), None)
+    }
+
+    /// Executes the built manifest without the preview run, roughly halving the runtime.
+    /// `Receipt::output_buckets` is not available for the returned receipt.
+    fn execute_without_preview(&mut self, verbose: bool) -> Receipt {
+        self.env().execute_manifest_builder(verbose, None, None)
+    }
+
+    /// Executes the built manifest as notarized transaction whose intent is only valid from
+    /// `start_epoch_inclusive` until before `end_epoch_exclusive` (without preview), e.g.:
+    /// ```ignore
+    /// let receipt = helper.execute_with_epoch_range(Epoch::of(1), Epoch::of(10), true);
+    /// receipt.execution_receipt.expect_rejection();
+    /// ```
+    fn execute_with_epoch_range(
+        &mut self,
+        start_epoch_inclusive: Epoch,
+        end_epoch_exclusive: Epoch,
+        verbose: bool,
+    ) -> Receipt {
+        self.env().execute_manifest_builder(
+            verbose,
+            None,
+            Some((start_epoch_inclusive, end_epoch_exclusive)),
+        )
+    }
+
+    /// Only previews the built manifest without signatures (assuming all signature proofs),
+    /// leaving the ledger state untouched
+    fn preview(&mut self, verbose: bool) -> TransactionReceipt {
+        self.env().preview_manifest_builder(verbose)
+    }
+
+    /// Executes the built manifest with the labeled account as active account
+    fn execute_as(&mut self, account_label: &str, verbose: bool) -> Receipt {
+        let active_account_label = self.env().active_account_label.clone();
+        self.env().set_active_account(account_label);
+        let receipt = self.execute(verbose);
+        self.env().active_account_label = active_account_label;
+        receipt
+    }
+
+    /// Like `execute`, but returns an error if the transaction failed or was rejected
+    fn try_execute(&mut self, verbose: bool) -> Result<Receipt, TestEnvError> {
+        let receipt = self.execute(verbose);
+        let transaction_index = receipt.transaction_index;
+        match &receipt.execution_receipt.result {
+            TransactionResult::Commit(commit) => match &commit.outcome {
+                TransactionOutcome::Success(_) => Ok(receipt),
+                TransactionOutcome::Failure(error) => Err(TestEnvError::TransactionFailed {
+                    transaction_index,
+                    message: format!("{:?}", error),
+                }),
+            },
+            TransactionResult::Reject(reject) => Err(TestEnvError::TransactionRejected {
+                transaction_index,
+                message: format!("{:?}", reject.reason),
+            }),
+            TransactionResult::Abort(abort) => Err(TestEnvError::TransactionRejected {
+                transaction_index,
+                message: format!("{:?}", abort.reason),
+            }),
+        }
+    }
+
+    fn execute_expect_success(&mut self, verbose: bool) -> Receipt {
+        self.try_execute(verbose)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn execute_expect_failure(&mut self, verbose: bool) -> Receipt {
+        let receipt = self.execute(verbose);
+        receipt.execution_receipt.expect_commit_failure();
+        receipt
+    }
+
+    /// Expects a commit failure whose error message contains `message`
+    fn execute_expect_failure_containing(&mut self, message: &str, verbose: bool) -> Receipt {
+        let receipt = self.execute_expect_failure(verbose);
+        let error = receipt.failure_message();
+        assert!(
+            error.contains(message),
+            "Expected failure containing {:?}, got: {}",
+            message,
+            error
+        );
+        receipt
+    }
+
+    /// Expects a commit failure whose error message matches the regular expression `pattern`
+    fn execute_expect_failure_matching(&mut self, pattern: &str, verbose: bool) -> Receipt {
+        let regex = Regex::new(pattern).unwrap_or_else(|_| panic!("Invalid regex {:?}", pattern));
+        let receipt = self.execute_expect_failure(verbose);
+        let error = receipt.failure_message();
+        assert!(
+            regex.is_match(&error),
+            "Expected failure matching {:?}, got: {}",
+            pattern,
+            error
+        );
+        receipt
+    }
+
+    /// Expects a commit failure with a runtime error accepted by `matches`,
+    /// e.g. `|error| matches!(error, RuntimeError::ApplicationError(_))`
+    fn execute_expect_specific_failure<F>(&mut self, matches: F, verbose: bool) -> Receipt
+    where
+        Self: Sized,
+        F: Fn(&RuntimeError) -> bool,
+    {
+        let receipt = self.execute(verbose);
+        receipt.execution_receipt.expect_specific_failure(matches);
+        receipt
+    }
+
+    /// Expects a commit failure caused by the auth module rejecting a call
+    fn execute_expect_auth_failure(&mut self, verbose: bool) -> Receipt
+    where
+        Self: Sized,
+    {
+        self.execute_expect_specific_failure(
+            |error| {
+                matches!(
+                    error,
+                    RuntimeError::SystemModuleError(SystemModuleError::AuthError(
+                        AuthError::Unauthorized(_)
+                    ))
+                )
+            },
+            verbose,
+        )
+    }
+
+    fn execute_expect_rejection(&mut self, verbose: bool) -> Receipt {
+        let receipt = self.execute(verbose);
+        receipt.execution_receipt.expect_rejection();
+        receipt
+    }
+
+    fn name(&mut self, name: &str) -> String {
+        let instruction_counter = self.env().instruction_counter;
+        match self.namespace() {
+            Some(namespace) => format!("{}_{}_{}", namespace, name, instruction_counter),
+            None => format!("{}_{}", name, instruction_counter),
+        }
+    }
+
+    /// Instruction label in the namespace of this helper, e.g. `"pool::swap"`,
+    /// under which instructions registered by the helper are queried from the receipt
+    fn label(&self, label: &str) -> String {
+        match self.namespace() {
+            Some(namespace) => format!("{}::{}", namespace, label),
+            None => label.to_string(),
+        }
+    }
+
+    /// `TestEnvironment::new_instruction` with `label` in the namespace of this helper
+    fn new_instruction(
+        &mut self,
+        label: &str,
+        instruction_count: usize,
+        label_instruction_id: usize,
+    ) {
+        let label = self.label(label);
+        self.env()
+            .new_instruction(&label, instruction_count, label_instruction_id);
+    }
+
+    /// `TestEnvironment::add_instructions` with `label` in the namespace of this helper
+    fn add_instructions<F>(&mut self, label: &str, add: F) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder) -> ManifestBuilder,
+    {
+        let label = self.label(label);
+        self.env().add_instructions(&label, add);
+        self
+    }
+
+    fn reset_instructions(&mut self) {
+        self.env().instruction_ids_by_label = HashMap::new();
+        self.env().instruction_counter = INSTRUCTION_COUNTER_INIT;
+    }
+
+    /// Runs `flow` `iterations` times, each time against a fresh revive of the current state
+    /// (with the current fee config, preview settings and active account), and reports the
+    /// cost unit and wall-clock distributions of the returned receipts.
+    /// Pending instructions and the history are put aside while iterating, afterwards the
+    /// ledger is reset to the state before benchmarking and both are restored.
+    fn bench<F>(&mut self, mut flow: F, iterations: usize) -> BenchReport
+    where
+        Self: Sized,
+        F: FnMut(&mut Self) -> Receipt,
+    {
+        let mut env = self.env();
+        let snapshot = env.create_snapshot();
+        let manifest_builder = mem::replace(&mut env.manifest_builder, ManifestBuilder::new());
+        let instruction_counter = env.instruction_counter;
+        let instruction_ids_by_label = mem::take(&mut env.instruction_ids_by_label);
+        let history = mem::take(&mut env.history);
+        drop(env);
+        let mut report = BenchReport::default();
+        for _ in 0..iterations {
+            self.env().revive_with_settings(&snapshot);
+            let start = Instant::now();
+            let receipt = flow(self);
+            report.durations.push(start.elapsed());
+            report.cost_units.push(receipt.total_cost_units());
+        }
+        let mut env = self.env();
+        env.revive_with_settings(&snapshot);
+        env.manifest_builder = manifest_builder;
+        env.instruction_counter = instruction_counter;
+        env.instruction_ids_by_label = instruction_ids_by_label;
+        env.history = history;
+        report
+    }
+}
+
+impl TestHelperExecution for TestEnvironment {
+    fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {
+        self
+    }
+}
+
+pub struct Receipt {
+    /// Shared with the entry of `TestEnvironment::history`
+    pub execution_receipt: Arc<TransactionReceipt>,
+    preview: Option<TransactionReceipt>,
+    pub instruction_ids_by_label: HashMap<String, Vec<usize>>,
+    // Fees paid by environment accounts, the resource deltas are only computed on first access
+    fees: HashMap<ComponentAddress, Decimal>,
+    balance_changes: OnceCell<BalanceChanges>,
+    pub(crate) address_labels: Arc<Vec<(String, GlobalAddress)>>,
+    /// Position in `TestEnvironment::history`
+    pub transaction_index: usize,
+}
+
+impl Receipt {
+    /// Receipt of the preview run, panics if the preview was skipped (see `try_preview_receipt`)
+    pub fn preview_receipt(&self) -> &TransactionReceipt {
+        self.try_preview_receipt()
+            .expect("No preview receipt, the preview was skipped for this execution")
+    }
+
+    /// Receipt of the preview run, `None` if the preview was skipped
+    pub fn try_preview_receipt(&self) -> Option<&TransactionReceipt> {
+        self.preview.as_ref()
+    }
+
+    /// Per account, per resource deltas computed from the deposit and withdraw events of the
+    /// accounts, with the fees paid by the accounts created by the environment separated out
+    pub fn balance_changes(&self) -> &BalanceChanges {
+        self.balance_changes
+            .get_or_init(|| BalanceChanges::from_events(&self.events(), self.fees.clone()))
+    }
+
+    /// Total XRD paid for the transaction, including tips, storage and royalties
+    pub fn fee_paid_xrd(&self) -> Decimal {
+        self.execution_receipt.fee_summary.total_cost()
+    }
+
+    /// Cost units consumed by execution and finalization
+    pub fn total_cost_units(&self) -> u32 {
+        let fee_summary = &self.execution_receipt.fee_summary;
+        fee_summary.total_execution_cost_units_consumed
+            + fee_summary.total_finalization_cost_units_consumed
+    }
+
+    pub fn fee_breakdown(&self) -> FeeBreakdown {
+        let fee_summary = &self.execution_receipt.fee_summary;
+        FeeBreakdown {
+            execution: fee_summary.total_execution_cost_in_xrd,
+            finalization: fee_summary.total_finalization_cost_in_xrd,
+            storage: fee_summary.total_storage_cost_in_xrd,
+            royalties: fee_summary.total_royalty_cost_in_xrd,
+            tipping: fee_summary.total_tipping_cost_in_xrd,
+        }
+    }
+
+    pub fn output_buckets(&self, instruction_label: &str) -> Vec<Vec<ResourceSpecifier>> {
+        self.try_preview_receipt()
+            .expect("Output buckets require a preview, which was skipped for this execution")
+            .output_buckets(self.instruction_ids(instruction_label))
     }
 
     pub fn outputs<T>(&self, instruction_label: &str) -> Vec<T>
@@ -480,152 +3353,2519 @@ impl Receipt {
             .outputs(self.instruction_ids(instruction_label))
     }
 
-    fn instruction_ids(&self, instruction_label: &str) -> Vec<usize> {
-        self.instruction_ids_by_label
-            .get(&instruction_label.to_string())
-            .expect(&format!("Can't find instruction '{}'", instruction_label))
-            .clone()
-    }
+    /// Execution cost units consumed by each instruction registered under `instruction_label`
+    /// including its nested calls, `None` if the receipt doesn't carry the detailed cost breakdown.
+    /// See `TransactionReceiptOutputBuckets::cost_by_instruction` for the limits of the attribution.
+    pub fn cost_by_label(&self, instruction_label: &str) -> Option<Vec<u64>> {
+        let cost_by_instruction = self.execution_receipt.cost_by_instruction()?;
+        Some(
+            self.instruction_ids(instruction_label)
+                .iter()
+                .map(|id| cost_by_instruction.get(*id).copied().unwrap_or_default())
+                .collect(),
+        )
+    }
+
+    /// Log entries (`info!`, `warn!`, ...) emitted by the application layer, in order
+    pub fn logs(&self) -> Vec<(Level, String)> {
+        match &self.execution_receipt.result {
+            TransactionResult::Commit(commit_result) => commit_result.application_logs.clone(),
+            _ => vec![],
+        }
+    }
+
+    pub fn assert_log_contains(&self, level: Level, message: &str) {
+        let logs = self.logs();
+        assert!(
+            logs.iter().any(
+                |(log_level, log_message)| *log_level == level && log_message.contains(message)
+            ),
+            "No {:?} log containing {:?} found in {:?}",
+            level,
+            message,
+            logs
+        );
+    }
+
+    /// Debug representation of the runtime error of a failed commit
+    pub fn failure_message(&self) -> String {
+        match &self.execution_receipt.expect_commit_failure().outcome {
+            TransactionOutcome::Failure(error) => format!("{:?}", error),
+            TransactionOutcome::Success(_) => panic!("Transaction did not fail"),
+        }
+    }
+
+    fn instruction_ids(&self, instruction_label: &str) -> Vec<usize> {
+        self.instruction_ids_by_label
+            .get(&instruction_label.to_string())
+            .expect(&format!("Can't find instruction '{}'", instruction_label))
+            .clone()
+    }
+}
+
+/// Entry of the `TestEnvironment` transaction history
+#[derive(Clone, Debug)]
+pub struct ExecutedTransaction {
+    pub manifest: TransactionManifestV1,
+    pub receipt: Arc<TransactionReceipt>,
+    /// Set for transactions executed as notarized transaction, see `TestEnvironment::resubmit`
+    pub notarized_transaction: Option<RawNotarizedTransaction>,
+}
+
+impl ExecutedTransaction {
+    pub fn is_success(&self) -> bool {
+        matches!(
+            &self.receipt.result,
+            TransactionResult::Commit(commit_result)
+                if matches!(commit_result.outcome, TransactionOutcome::Success(_))
+        )
+    }
+}
+
+/// XRD paid for a transaction, split by fee component
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeBreakdown {
+    pub execution: Decimal,
+    pub finalization: Decimal,
+    pub storage: Decimal,
+    pub royalties: Decimal,
+    pub tipping: Decimal,
+}
+
+impl FeeBreakdown {
+    pub fn total(&self) -> Decimal {
+        self.execution + self.finalization + self.storage + self.royalties + self.tipping
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BalanceChanges {
+    /// Resource deltas per account, not including the XRD paid for fees
+    pub resources: HashMap<ComponentAddress, HashMap<ResourceAddress, BalanceChange>>,
+    /// XRD paid for fees per account
+    pub fees: HashMap<ComponentAddress, Decimal>,
+}
+
+impl BalanceChanges {
+    pub fn fungible(&self, account: ComponentAddress, resource: ResourceAddress) -> Decimal {
+        match self.change(account, resource) {
+            Some(BalanceChange::Fungible(delta)) => *delta,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// Ids added to and removed from the account
+    pub fn non_fungible(
+        &self,
+        account: ComponentAddress,
+        resource: ResourceAddress,
+    ) -> (BTreeSet<NonFungibleLocalId>, BTreeSet<NonFungibleLocalId>) {
+        match self.change(account, resource) {
+            Some(BalanceChange::NonFungible { added, removed }) => (added.clone(), removed.clone()),
+            _ => (BTreeSet::new(), BTreeSet::new()),
+        }
+    }
+
+    pub fn fee(&self, account: ComponentAddress) -> Decimal {
+        self.fees.get(&account).copied().unwrap_or_default()
+    }
+
+    fn change(
+        &self,
+        account: ComponentAddress,
+        resource: ResourceAddress,
+    ) -> Option<&BalanceChange> {
+        self.resources.get(&account)?.get(&resource)
+    }
+
+    // Fees are locked directly from the account vaults and therefore not part of the
+    // deposit and withdraw events
+    fn from_events(events: &[RecordedEvent], fees: HashMap<ComponentAddress, Decimal>) -> Self {
+        let mut balance_changes = BalanceChanges {
+            resources: HashMap::new(),
+            fees,
+        };
+        for event in events {
+            let Emitter::Method(node_id, ModuleId::Main) = &event.emitter else {
+                continue;
+            };
+            if !node_id
+                .entity_type()
+                .is_some_and(|entity_type| entity_type.is_global_account())
+            {
+                continue;
+            }
+            let account = ComponentAddress::new_or_panic(node_id.0);
+            if event.is::<AccountDepositEvent>() {
+                match event.decode::<AccountDepositEvent>() {
+                    AccountDepositEvent::Fungible(resource, amount) => {
+                        balance_changes.add(account, resource, BalanceChange::Fungible(amount))
+                    }
+                    AccountDepositEvent::NonFungible(resource, ids) => balance_changes.add(
+                        account,
+                        resource,
+                        BalanceChange::NonFungible {
+                            added: ids.into_iter().collect(),
+                            removed: BTreeSet::new(),
+                        },
+                    ),
+                }
+            } else if event.is::<AccountWithdrawEvent>() {
+                match event.decode::<AccountWithdrawEvent>() {
+                    AccountWithdrawEvent::Fungible(resource, amount) => {
+                        balance_changes.add(account, resource, BalanceChange::Fungible(-amount))
+                    }
+                    AccountWithdrawEvent::NonFungible(resource, ids) => balance_changes.add(
+                        account,
+                        resource,
+                        BalanceChange::NonFungible {
+                            added: BTreeSet::new(),
+                            removed: ids.into_iter().collect(),
+                        },
+                    ),
+                }
+            }
+        }
+        balance_changes.resources.retain(|_, changes| {
+            changes.retain(|_, change| match change {
+                BalanceChange::Fungible(delta) => !delta.is_zero(),
+                BalanceChange::NonFungible { added, removed } => {
+                    !added.is_empty() || !removed.is_empty()
+                }
+            });
+            !changes.is_empty()
+        });
+        balance_changes
+    }
+
+    fn add(&mut self, account: ComponentAddress, resource: ResourceAddress, change: BalanceChange) {
+        let account_changes = self.resources.entry(account).or_default();
+        match (account_changes.get_mut(&resource), change) {
+            (Some(BalanceChange::Fungible(total)), BalanceChange::Fungible(delta)) => {
+                *total += delta
+            }
+            (
+                Some(BalanceChange::NonFungible { added, removed }),
+                BalanceChange::NonFungible {
+                    added: more_added,
+                    removed: more_removed,
+                },
+            ) => {
+                // Ids withdrawn and deposited again within the transaction cancel out
+                for id in more_added {
+                    if !removed.remove(&id) {
+                        added.insert(id);
+                    }
+                }
+                for id in more_removed {
+                    if !added.remove(&id) {
+                        removed.insert(id);
+                    }
+                }
+            }
+            (_, change) => {
+                account_changes.insert(resource, change);
+            }
+        }
+    }
+}
+
+pub trait TransactionReceiptOutputBuckets {
+    fn output_buckets(&self, instruction_ids: Vec<usize>) -> Vec<Vec<ResourceSpecifier>>;
+    fn cost_by_instruction(&self) -> Option<Vec<u64>>;
+    fn outputs<T>(&self, instruction_ids: Vec<usize>) -> Vec<T>
+    where
+        T: ScryptoDecode;
+}
+
+impl TransactionReceiptOutputBuckets for TransactionReceipt {
+    /// Attributes the detailed execution cost breakdown to the manifest instructions.
+    ///
+    /// This is an approximation: the breakdown carries no instruction indices, so every
+    /// invocation directly below the transaction processor is taken as the next instruction.
+    /// Nested calls are attributed to the instruction invoking them, but instructions without
+    /// an invocation of their own (e.g. `DROP_PROOF`) shift the attribution of all following
+    /// instructions, and invocations of the transaction processor itself (e.g. draining the
+    /// worktop) add trailing entries. Costs outside of any invocation (e.g. manifest decoding)
+    /// are not attributed. `None` if the receipt doesn't carry the detailed cost breakdown.
+    fn cost_by_instruction(&self) -> Option<Vec<u64>> {
+        let breakdown = &self
+            .debug_information
+            .as_ref()?
+            .detailed_execution_cost_breakdown;
+        let invocation_depths = breakdown.iter().filter_map(|entry| match entry.item {
+            ExecutionCostBreakdownItem::Invocation { .. } => Some(entry.depth),
+            _ => None,
+        });
+        let root_depth = match invocation_depths.clone().min() {
+            Some(depth) => depth,
+            None => return Some(vec![]),
+        };
+        // Skip the transaction processor invocation wrapping all instructions, if present
+        let instruction_depth = match invocation_depths.filter(|&d| d == root_depth).count() {
+            1 => root_depth + 1,
+            _ => root_depth,
+        };
+        let mut costs: Vec<u64> = vec![];
+        let mut inside_instruction = false;
+        for entry in breakdown {
+            match &entry.item {
+                ExecutionCostBreakdownItem::Invocation { .. }
+                    if entry.depth == instruction_depth =>
+                {
+                    costs.push(0);
+                    inside_instruction = true;
+                }
+                ExecutionCostBreakdownItem::InvocationComplete
+                    if entry.depth == instruction_depth =>
+                {
+                    inside_instruction = false;
+                }
+                ExecutionCostBreakdownItem::Execution { cost_units, .. } if inside_instruction => {
+                    *costs.last_mut().unwrap() += cost_units;
+                }
+                _ => {}
+            }
+        }
+        Some(costs)
+    }
+
+    fn output_buckets(&self, instruction_ids: Vec<usize>) -> Vec<Vec<ResourceSpecifier>> {
+        match &self.expect_commit_success().execution_trace {
+            None => vec![],
+            Some(execution_trace) => {
+                let worktop_changes = execution_trace.worktop_changes();
+                instruction_ids
+                    .iter()
+                    .filter_map(|id| {
+                        let instruction_worktop_changes = worktop_changes.get(id).unwrap();
+                        Some(
+                            instruction_worktop_changes
+                                .iter()
+                                .filter_map(|change| match change {
+                                    WorktopChange::Put(resource_specifier) => {
+                                        Some(resource_specifier.clone())
+                                    }
+                                    _ => None,
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn outputs<T>(&self, instruction_ids: Vec<usize>) -> Vec<T>
+    where
+        T: ScryptoDecode,
+    {
+        instruction_ids
+            .iter()
+            .filter_map(|id| Some(self.expect_commit_success().output(*id)))
+            .collect()
+    }
+}
+
+pub trait GetResourceAddress {
+    fn address(&self) -> ResourceAddress;
+}
+
+impl GetResourceAddress for ResourceSpecifier {
+    fn address(&self) -> ResourceAddress {
+        match self {
+            ResourceSpecifier::Amount(address, _) => *address,
+            ResourceSpecifier::Ids(address, _) => *address,
+        }
+    }
+}
+
+pub fn sort_addresses(
+    a_address: ResourceAddress,
+    b_address: ResourceAddress,
+) -> (ResourceAddress, ResourceAddress) {
+    if a_address < b_address {
+        (a_address, b_address)
+    } else {
+        (b_address, a_address)
+    }
+}
+
+pub trait CreateFungibleResourceAdvanced {
+    fn create_fungible_resource_advanced(
+        &mut self,
+        amount: Decimal,
+        divisibility: u8,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+
+    fn create_fungible_resource_with_roles(
+        &mut self,
+        amount: Decimal,
+        divisibility: u8,
+        account: ComponentAddress,
+        roles: FungibleResourceRoles,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+}
+
+impl CreateFungibleResourceAdvanced for LedgerSimulator<NoExtension, InMemorySubstateDatabase> {
+    fn create_fungible_resource_advanced(
+        &mut self,
+        amount: Decimal,
+        divisibility: u8,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        self.create_fungible_resource_with_roles(
+            amount,
+            divisibility,
+            account,
+            FungibleResourceRoles::default(),
+            metadata,
+        )
+    }
+
+    fn create_fungible_resource_with_roles(
+        &mut self,
+        amount: Decimal,
+        divisibility: u8,
+        account: ComponentAddress,
+        roles: FungibleResourceRoles,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_fungible_resource(
+                OwnerRole::None,
+                true,
+                divisibility,
+                roles,
+                metadata,
+                Some(amount),
+            )
+            .try_deposit_entire_worktop_or_abort(account, None)
+            .build();
+        let receipt = self.execute_manifest(manifest, vec![]);
+        receipt.expect_commit(true).new_resource_addresses()[0]
+    }
+}
+
+/// Account receiving surplus XRD of `fund_account_xrd`, not used by anything else
+fn faucet_sink_account() -> ComponentAddress {
+    ComponentAddress::virtual_account_from_public_key(
+        &Secp256k1PrivateKey::from_u64(FAUCET_SINK_KEY)
+            .unwrap()
+            .public_key(),
+    )
+}
+
+fn admin_badge_roles(admin_badge_address: ResourceAddress) -> FungibleResourceRoles {
+    FungibleResourceRoles {
+        mint_roles: mint_roles! {
+            minter => rule!(require(admin_badge_address));
+            minter_updater => rule!(deny_all);
+        },
+        burn_roles: burn_roles! {
+            burner => rule!(require(admin_badge_address));
+            burner_updater => rule!(deny_all);
+        },
+        ..Default::default()
+    }
+}
+
+// Edge case resources, only created with `TestEnvironmentBuilder::extra_resources`
+struct ExtraResources {
+    d0_address: ResourceAddress,
+    d6_address: ResourceAddress,
+    recallable_address: ResourceAddress,
+    string_nft_address: ResourceAddress,
+    bytes_nft_address: ResourceAddress,
+    ruid_nft_address: ResourceAddress,
+}
+
+impl ExtraResources {
+    fn create(
+        test_runner: &mut LedgerSimulator<NoExtension, InMemorySubstateDatabase>,
+        account: ComponentAddress,
+        admin_badge_address: ResourceAddress,
+        resources_by_symbol: &mut HashMap<String, ResourceAddress>,
+    ) -> Self {
+        // Coarse grained tokens, since rounding behaviour differs significantly for them
+        let d6_address = test_runner.create_fungible_resource(dec!(1000000000), 6, account);
+        // Recallable and freezable with the admin badge, for components holding user funds
+        let recallable_address = test_runner.create_fungible_resource_with_roles(
+            dec!(1000000000),
+            DIVISIBILITY_MAXIMUM,
+            account,
+            FungibleResourceRoles {
+                recall_roles: recall_roles! {
+                    recaller => rule!(require(admin_badge_address));
+                    recaller_updater => rule!(deny_all);
+                },
+                freeze_roles: freeze_roles! {
+                    freezer => rule!(require(admin_badge_address));
+                    freezer_updater => rule!(deny_all);
+                },
+                ..Default::default()
+            },
+            ModuleConfig::default(),
+        );
+        // NFT collections covering the remaining local id types
+        let string_nft_address = test_runner.create_non_fungible_resource_with_ids(
+            NonFungibleIdType::String,
+            (1..=3)
+                .map(|id| NonFungibleLocalId::string(format!("nft_{}", id)).unwrap())
+                .collect(),
+            account,
+            ModuleConfig::default(),
+        );
+        let bytes_nft_address = test_runner.create_non_fungible_resource_with_ids(
+            NonFungibleIdType::Bytes,
+            (1..=3u8)
+                .map(|id| NonFungibleLocalId::bytes(vec![id]).unwrap())
+                .collect(),
+            account,
+            ModuleConfig::default(),
+        );
+        let ruid_nft_address = test_runner.create_ruid_non_fungible_resource_with_metadata(
+            3,
+            account,
+            ModuleConfig::default(),
+        );
+
+        // Edge case resources for overflow and rounding tests
+        let max_supply_address = test_runner.create_fungible_resource_advanced(
+            MAX_SUPPLY,
+            DIVISIBILITY_MAXIMUM,
+            account,
+            metadata! {
+                init {
+                    "name" => "Max supply token".to_owned(), locked;
+                    "symbol" => MAX_SUPPLY_SYMBOL.to_owned(), locked;
+                }
+            },
+        );
+        let zero_divisibility_address = test_runner.create_fungible_resource_advanced(
+            dec!(1000000000),
+            DIVISIBILITY_NONE,
+            account,
+            metadata! {
+                init {
+                    "name" => "Zero divisibility token".to_owned(), locked;
+                    "symbol" => ZERO_DIVISIBILITY_SYMBOL.to_owned(), locked;
+                }
+            },
+        );
+        let single_atto_address = test_runner.create_fungible_resource_advanced(
+            Decimal(I192::from_digits([1, 0, 0])),
+            DIVISIBILITY_MAXIMUM,
+            account,
+            metadata! {
+                init {
+                    "name" => "Single atto token".to_owned(), locked;
+                    "symbol" => SINGLE_ATTO_SYMBOL.to_owned(), locked;
+                }
+            },
+        );
+        resources_by_symbol.extend([
+            (MAX_SUPPLY_SYMBOL.to_string(), max_supply_address),
+            (
+                ZERO_DIVISIBILITY_SYMBOL.to_string(),
+                zero_divisibility_address,
+            ),
+            (SINGLE_ATTO_SYMBOL.to_string(), single_atto_address),
+        ]);
+
+        Self {
+            d0_address: zero_divisibility_address,
+            d6_address,
+            recallable_address,
+            string_nft_address,
+            bytes_nft_address,
+            ruid_nft_address,
+        }
+    }
+}
+
+#[derive(ScryptoSbor, ManifestSbor, NonFungibleData)]
+pub struct TestNonFungibleData {}
+
+pub trait CreateNonFungibleResourceWithMetadata {
+    fn create_non_fungible_resource_with_metadata(
+        &mut self,
+        count: u64,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+
+    /// All `ids` have to be of type `id_type`, which can't be RUID (RUIDs are generated by the engine)
+    fn create_non_fungible_resource_with_ids(
+        &mut self,
+        id_type: NonFungibleIdType,
+        ids: Vec<NonFungibleLocalId>,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+
+    fn create_ruid_non_fungible_resource_with_metadata(
+        &mut self,
+        count: u64,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+
+    fn create_non_fungible_resource_with_entries<T: NonFungibleData + ManifestEncode>(
+        &mut self,
+        id_type: NonFungibleIdType,
+        entries: Vec<(NonFungibleLocalId, T)>,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress;
+}
+
+impl CreateNonFungibleResourceWithMetadata
+    for LedgerSimulator<NoExtension, InMemorySubstateDatabase>
+{
+    fn create_non_fungible_resource_with_metadata(
+        &mut self,
+        count: u64,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let ids = (1..=count).map(NonFungibleLocalId::integer).collect();
+        self.create_non_fungible_resource_with_ids(
+            NonFungibleIdType::Integer,
+            ids,
+            account,
+            metadata,
+        )
+    }
+
+    fn create_non_fungible_resource_with_ids(
+        &mut self,
+        id_type: NonFungibleIdType,
+        ids: Vec<NonFungibleLocalId>,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let entries: Vec<(NonFungibleLocalId, TestNonFungibleData)> = ids
+            .into_iter()
+            .map(|id| (id, TestNonFungibleData {}))
+            .collect();
+        self.create_non_fungible_resource_with_entries(id_type, entries, account, metadata)
+    }
+
+    fn create_non_fungible_resource_with_entries<T: NonFungibleData + ManifestEncode>(
+        &mut self,
+        id_type: NonFungibleIdType,
+        entries: Vec<(NonFungibleLocalId, T)>,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_non_fungible_resource(
+                OwnerRole::None,
+                id_type,
+                true,
+                NonFungibleResourceRoles::default(),
+                metadata,
+                Some(entries),
+            )
+            .try_deposit_entire_worktop_or_abort(account, None)
+            .build();
+        let receipt = self.execute_manifest(manifest, vec![]);
+        receipt.expect_commit(true).new_resource_addresses()[0]
+    }
+
+    fn create_ruid_non_fungible_resource_with_metadata(
+        &mut self,
+        count: u64,
+        account: ComponentAddress,
+        metadata: ModuleConfig<MetadataInit>,
+    ) -> ResourceAddress {
+        let entries: Vec<TestNonFungibleData> =
+            (0..count).map(|_| TestNonFungibleData {}).collect();
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_ruid_non_fungible_resource(
+                OwnerRole::None,
+                true,
+                metadata,
+                NonFungibleResourceRoles::default(),
+                Some(entries),
+            )
+            .try_deposit_entire_worktop_or_abort(account, None)
+            .build();
+        let receipt = self.execute_manifest(manifest, vec![]);
+        receipt.expect_commit(true).new_resource_addresses()[0]
+    }
+}
+
+#[test]
+fn test_nft_id() {
+    assert_eq!(nft_id!(3), NonFungibleLocalId::Integer((3).into()))
+}
+
+#[test]
+fn test_nft_ids() {
+    assert_eq!(
+        nft_ids!(1, 3, 2),
+        IndexSet::from([
+            NonFungibleLocalId::Integer((1).into()),
+            NonFungibleLocalId::Integer((2).into()),
+            NonFungibleLocalId::Integer((3).into()),
+        ])
+    )
+}
+
+#[test]
+fn test_test_environment_snapshot() {
+    let mut test_environment = TestEnvironmentBuilder::new().extra_resources().build();
+    test_environment.u_address();
+    test_environment.k_nft_address();
+    let test_environment_new = TestEnvironmentSnapshot::from(&test_environment).revive();
+
+    assert!(test_environment.package_addresses == test_environment_new.package_addresses);
+    assert!(
+        test_environment.previous_package_addresses
+            == test_environment_new.previous_package_addresses
+    );
+    assert!(test_environment.package_definitions == test_environment_new.package_definitions);
+    assert!(test_environment.public_key == test_environment_new.public_key);
+    assert!(test_environment.account == test_environment_new.account);
+    assert!(test_environment.dapp_definition == test_environment_new.dapp_definition);
+    assert!(test_environment.accounts == test_environment_new.accounts);
+    assert!(test_environment.accounts_by_label == test_environment_new.accounts_by_label);
+    assert!(test_environment.identities_by_label == test_environment_new.identities_by_label);
+    assert!(test_environment.key_generator == test_environment_new.key_generator);
+    assert!(test_environment.admin_badge_address == test_environment_new.admin_badge_address);
+    assert!(test_environment.badges == test_environment_new.badges);
+    assert!(test_environment.a_address == test_environment_new.a_address);
+    assert!(test_environment.b_address == test_environment_new.b_address);
+    assert!(test_environment.x_address == test_environment_new.x_address);
+    assert!(test_environment.y_address == test_environment_new.y_address);
+    assert!(test_environment.u_address == test_environment_new.u_address);
+    assert!(test_environment.v_address == test_environment_new.v_address);
+    assert!(test_environment.d0_address == test_environment_new.d0_address);
+    assert!(test_environment.d6_address == test_environment_new.d6_address);
+    assert!(test_environment.recallable_address == test_environment_new.recallable_address);
+    assert!(test_environment.j_nft_address == test_environment_new.j_nft_address);
+    assert!(test_environment.k_nft_address == test_environment_new.k_nft_address);
+    assert!(test_environment.string_nft_address == test_environment_new.string_nft_address);
+    assert!(test_environment.bytes_nft_address == test_environment_new.bytes_nft_address);
+    assert!(test_environment.ruid_nft_address == test_environment_new.ruid_nft_address);
+    assert!(test_environment.resources_by_symbol == test_environment_new.resources_by_symbol);
+}
+
+#[test]
+fn test_bench() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let report = test_environment.bench(|env| env.execute_expect_success(false), 3);
+
+    assert_eq!(report.iterations(), 3);
+    assert!(report.is_cost_deterministic());
+}
+
+#[test]
+fn test_bench_keeps_settings() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    test_environment.execute_expect_success(false);
+    test_environment.set_active_account("trader");
+    test_environment.preview_enabled = false;
+    test_environment.fee_config.tip_percentage = 10;
+    test_environment.add_instructions("deposit", |builder| builder.deposit_entire_worktop(trader));
+
+    let report = test_environment.bench(
+        |env| {
+            assert_eq!(env.active_account_label.as_deref(), Some("trader"));
+            assert!(!env.preview_enabled);
+            assert_eq!(env.fee_config.tip_percentage, 10);
+            assert!(env.instruction_ids_by_label.is_empty());
+            assert_eq!(env.transaction_count(), 0);
+            env.execute_expect_success(false)
+        },
+        2,
+    );
+
+    assert_eq!(report.iterations(), 2);
+    assert_eq!(
+        test_environment.active_account_label.as_deref(),
+        Some("trader")
+    );
+    assert_eq!(test_environment.fee_config.tip_percentage, 10);
+    assert_eq!(test_environment.transaction_count(), 1);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(receipt.outputs::<()>("deposit"), vec![()]);
+}
+
+#[test]
+fn test_configure_simulator() {
+    let test_environment = TestEnvironmentBuilder::new()
+        .configure_simulator(|builder| builder.with_kernel_trace())
+        .build();
+
+    assert!(test_environment.config().simulator_hook.is_some());
+    assert!(test_environment.clone().config() == test_environment.config());
+}
+
+#[test]
+fn test_call_with_bucket() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let x_address = test_environment.x_address;
+    let x_balance = test_environment.balance(TestAddress::X);
+    test_environment.call_with_bucket("deposit", x_address, dec!(1), |builder, x_bucket| {
+        builder.call_method(account, "deposit", manifest_args!(x_bucket))
+    });
+    let receipt = test_environment.execute_expect_success(false);
+
+    assert_eq!(receipt.outputs::<()>("deposit"), vec![()]);
+    // Minted instead of withdrawn, so the account ends up with one more X
+    assert_eq!(
+        test_environment.balance(TestAddress::X),
+        x_balance + dec!(1)
+    );
+}
+
+#[test]
+fn test_call_with_account_bucket() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let x_address = test_environment.x_address;
+    let x_balance = test_environment.balance(TestAddress::X);
+    test_environment.call_with_account_bucket(
+        "deposit",
+        x_address,
+        dec!(1),
+        |builder, x_bucket| {
+            builder.call_method(
+                trader,
+                "try_deposit_or_abort",
+                manifest_args!(x_bucket, None::<ResourceOrNonFungible>),
+            )
+        },
+    );
+    test_environment.execute_expect_success(false);
+
+    assert_eq!(
+        test_environment.balance(TestAddress::X),
+        x_balance - dec!(1)
+    );
+}
+
+#[test]
+fn test_add_instructions() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let x_address = test_environment.x_address;
+    let x_bucket = test_environment.name("x_bucket");
+
+    test_environment.add_instructions("deposit", |builder| {
+        builder
+            .withdraw_from_account(account, x_address, dec!(1))
+            .take_from_worktop(x_address, dec!(1), &x_bucket)
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(account, "deposit", manifest_args!(lookup.bucket(&x_bucket)))
+            })
+    });
+
+    assert_eq!(
+        test_environment.instruction_counter,
+        INSTRUCTION_COUNTER_INIT + 3
+    );
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(receipt.outputs::<()>("deposit"), vec![()]);
+}
+
+#[test]
+fn test_builder_resources_and_accounts() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .fungible("USDT", dec!(1000000), 6)
+        .non_fungible("LP", 10)
+        .additional_accounts(2)
+        .balance(1, "USDT", dec!(100))
+        .build();
+    let usdt_address = test_environment.resource_by_symbol("USDT");
+    let lp_address = test_environment.resource_by_symbol("LP");
+    let account = test_environment.account;
+    let trader = test_environment.accounts[1].address;
+
+    assert_eq!(test_environment.accounts.len(), 2);
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, usdt_address),
+        dec!(100)
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, usdt_address),
+        dec!(999900)
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, lp_address),
+        dec!(10)
+    );
+}
+
+#[test]
+fn test_builder_invalid_account_index() {
+    let result = TestEnvironmentBuilder::new()
+        .additional_accounts(1)
+        .balance(1, "A", dec!(100))
+        .try_build();
+
+    assert!(matches!(result, Err(TestEnvError::SetupFailed(_))));
+}
+
+#[test]
+fn test_builder_unknown_balance_symbol() {
+    let result = TestEnvironmentBuilder::new()
+        .additional_accounts(1)
+        .balance(0, "UNKNOWN", dec!(100))
+        .try_build();
+
+    assert_eq!(
+        result.err(),
+        Some(TestEnvError::ResourceNotFound("UNKNOWN".to_string()))
+    );
+}
+
+#[test]
+fn test_new_account() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let liquidity_provider = test_environment.new_account("liquidity_provider");
+
+    assert_eq!(test_environment.account("trader"), trader);
+    assert_eq!(
+        test_environment.account("liquidity_provider"),
+        liquidity_provider
+    );
+    assert_ne!(trader, liquidity_provider);
+}
+
+#[test]
+fn test_ed25519_accounts() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .ed25519_accounts()
+        .additional_accounts(1)
+        .build();
+    test_environment.new_account_ed25519("ed25519");
+
+    assert_eq!(
+        test_environment.main_account.key,
+        PublicKey::Secp256k1(test_environment.public_key)
+    );
+    assert!(matches!(
+        test_environment.accounts[0].key,
+        PublicKey::Ed25519(_)
+    ));
+    assert!(matches!(
+        test_environment.test_account("ed25519").key,
+        PublicKey::Ed25519(_)
+    ));
+    test_environment.execute_expect_success(false);
+}
+
+#[test]
+fn test_divisibility_tokens() {
+    let mut test_environment = TestEnvironmentBuilder::new().extra_resources().build();
+    let (account, d0_address, d6_address) = (
+        test_environment.account,
+        test_environment.resource(TestAddress::D0),
+        test_environment.resource(TestAddress::D6),
+    );
+    let trader = test_environment.new_account("trader");
+
+    test_environment.transfer(account, trader, d6_address, dec!("0.000001"));
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, d6_address),
+        dec!("0.000001")
+    );
+    // Amounts finer than the divisibility can't be withdrawn
+    for (resource, amount) in [(d0_address, dec!("1.5")), (d6_address, dec!("0.0000001"))] {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(account, resource, amount)
+            .try_deposit_entire_worktop_or_abort(trader, None)
+            .build();
+        let initial_proofs = test_environment.signatures(&[account]);
+        test_environment
+            .test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_failure();
+    }
+}
+
+#[test]
+fn test_resource() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+
+    assert_eq!(
+        test_environment.resource(TestAddress::X),
+        test_environment.x_address
+    );
+    let k_nft_address = test_environment.resource(TestAddress::K);
+    assert_eq!(test_environment.k_nft_address, Some(k_nft_address));
+    assert_eq!(test_environment.balance(TestAddress::A), MAX_SUPPLY);
+}
+
+#[test]
+fn test_create_resource() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let usdt_address = test_environment.create_resource(
+        "USDT",
+        dec!(1000),
+        6,
+        metadata! {
+            init {
+                "symbol" => "USDT".to_owned(), locked;
+            }
+        },
+    );
+
+    assert_eq!(test_environment.resource_by_symbol("USDT"), usdt_address);
+    assert_eq!(
+        test_environment
+            .create_snapshot()
+            .revive()
+            .resource_by_symbol("USDT"),
+        usdt_address
+    );
+}
+
+#[test]
+fn test_mint_and_burn() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let u_address = test_environment.u_address();
+
+    test_environment.mint_to_account(u_address, dec!(10), trader);
+    test_environment.burn_from_account(u_address, dec!(4), trader);
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, u_address),
+        dec!(6)
+    );
+}
+
+#[test]
+fn test_recall_and_freeze() {
+    let mut test_environment = TestEnvironmentBuilder::new().extra_resources().build();
+    let trader = test_environment.new_account("trader");
+    let recallable_address = test_environment.recallable_address.unwrap();
+    let account = test_environment.account;
+    test_environment.test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(account, recallable_address, dec!(10))
+            .try_deposit_entire_worktop_or_abort(trader, None)
+            .build(),
+        vec![NonFungibleGlobalId::from_public_key(
+            &test_environment.public_key,
+        )],
+    );
+    let vault_id = test_environment
+        .test_runner
+        .get_component_vaults(trader, recallable_address)[0];
+
+    let withdraw_from_trader = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .withdraw_from_account(trader, recallable_address, dec!(1))
+        .try_deposit_entire_worktop_or_abort(account, None)
+        .build();
+    let trader_proofs = test_environment.signatures(&[trader]);
+
+    test_environment.recall_from_vault(vault_id, dec!(3));
+    test_environment.freeze_vault(vault_id);
+
+    assert_eq!(
+        test_environment.test_runner.inspect_vault_balance(vault_id),
+        Some(dec!(7))
+    );
+    test_environment
+        .test_runner
+        .execute_manifest(withdraw_from_trader.clone(), trader_proofs.clone())
+        .expect_commit_failure();
+    test_environment.unfreeze_vault(vault_id);
+    test_environment
+        .test_runner
+        .execute_manifest(withdraw_from_trader, trader_proofs)
+        .expect_commit_success();
+    assert_eq!(
+        test_environment.test_runner.inspect_vault_balance(vault_id),
+        Some(dec!(6))
+    );
+}
+
+#[derive(ScryptoSbor, ManifestSbor, NonFungibleData, Debug, PartialEq)]
+struct TestPosition {
+    liquidity: Decimal,
+}
+
+#[test]
+fn test_create_non_fungible_resource_with_data() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let position_address = test_environment.create_non_fungible_resource_with_data(vec![
+        TestPosition { liquidity: dec!(1) },
+        TestPosition { liquidity: dec!(2) },
+    ]);
+
+    assert_eq!(
+        test_environment.non_fungible_data::<TestPosition>(position_address, nft_id!(2)),
+        TestPosition { liquidity: dec!(2) }
+    );
+}
+
+#[test]
+fn test_nft_collection_size() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .nft_collection_size(100)
+        .build();
+    let account = test_environment.account;
+    let j_nft_address = test_environment.j_nft_address();
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, j_nft_address),
+        dec!(100)
+    );
+}
+
+#[test]
+fn test_badges() {
+    let test_environment = TestEnvironmentBuilder::new()
+        .badge("moderator_badge")
+        .badge("treasury_badge")
+        .build();
+
+    assert_ne!(
+        test_environment.badge("moderator_badge"),
+        test_environment.badge("treasury_badge")
+    );
+    assert_eq!(
+        test_environment.create_snapshot().revive().badges,
+        test_environment.badges
+    );
+}
+
+#[test]
+fn test_fund_account_xrd() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let balance_before = test_environment
+        .test_runner
+        .get_component_balance(trader, XRD);
+
+    test_environment.fund_account_xrd(trader, dec!(25000.5));
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, XRD),
+        balance_before + dec!(25000.5)
+    );
+    // Three faucet calls, the surplus of the last one ends up in the sink account
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(faucet_sink_account(), XRD),
+        dec!(4999.5)
+    );
+}
+
+#[test]
+fn test_transfer() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let trader = test_environment.new_account("trader");
+    let (x_address, j_nft_address) = (test_environment.x_address, test_environment.j_nft_address());
+
+    test_environment.transfer(account, trader, x_address, dec!(5));
+    test_environment.transfer_non_fungibles(account, trader, j_nft_address, nft_ids!(1, 2));
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, x_address),
+        dec!(5)
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, j_nft_address),
+        dec!(2)
+    );
+}
+
+#[test]
+fn test_balance_changes() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let trader = test_environment.new_account("trader");
+    let (x_address, j_nft_address) = (test_environment.x_address, test_environment.j_nft_address());
+    test_environment.add_instructions("transfer", |builder| {
+        builder
+            .withdraw_from_account(account, x_address, dec!(3))
+            .withdraw_non_fungibles_from_account(account, j_nft_address, nft_ids!(1))
+            .try_deposit_entire_worktop_or_abort(trader, None)
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let balance_changes = receipt.balance_changes();
+    assert_eq!(balance_changes.fungible(account, x_address), dec!(-3));
+    assert_eq!(
+        balance_changes.non_fungible(account, j_nft_address),
+        (BTreeSet::new(), BTreeSet::from([nft_id!(1)]))
+    );
+    assert_eq!(balance_changes.fungible(trader, x_address), dec!(3));
+    assert_eq!(balance_changes.fungible(account, XRD), Decimal::ZERO);
+    assert!(balance_changes.fee(account) > Decimal::ZERO);
+    assert_eq!(balance_changes.resources.len(), 2);
+}
+
+#[test]
+fn test_balance_changes_cancel_out() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let (x_address, j_nft_address) = (test_environment.x_address, test_environment.j_nft_address());
+    test_environment.add_instructions("round_trip", |builder| {
+        builder
+            .withdraw_from_account(account, x_address, dec!(3))
+            .withdraw_non_fungibles_from_account(account, j_nft_address, nft_ids!(1))
+            .try_deposit_entire_worktop_or_abort(account, None)
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let balance_changes = receipt.balance_changes();
+    assert_eq!(balance_changes.fungible(account, x_address), Decimal::ZERO);
+    assert_eq!(
+        balance_changes.non_fungible(account, j_nft_address),
+        (BTreeSet::new(), BTreeSet::new())
+    );
+    assert!(balance_changes.resources.is_empty());
+    assert!(balance_changes.fee(account) > Decimal::ZERO);
+}
+
+#[test]
+fn test_fee_summary() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let fee_breakdown = receipt.fee_breakdown();
+    assert!(receipt.total_cost_units() > 0);
+    assert!(fee_breakdown.execution > Decimal::ZERO);
+    assert_eq!(fee_breakdown.royalties, Decimal::ZERO);
+    assert_eq!(fee_breakdown.total(), receipt.fee_paid_xrd());
+    assert_eq!(
+        receipt.balance_changes().fee(test_environment.account),
+        receipt.fee_paid_xrd()
+    );
+}
+
+#[test]
+fn test_cost_by_label() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, dec!(1))
+    });
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, dec!(1))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let costs = receipt.cost_by_label("withdraw").unwrap();
+    assert_eq!(costs.len(), 2);
+    assert!(costs.iter().all(|&cost| cost > 0));
+}
+
+#[test]
+fn test_cost_by_label_nested_calls() {
+    let packages: HashMap<&str, &str> = HashMap::from([("hello_swap", "examples/hello_swap")]);
+    let mut test_environment = TestEnvironment::new(packages);
+    let package_address = test_environment.package_address("hello_swap");
+    let (x_address, y_address) = (test_environment.x_address, test_environment.y_address);
+    test_environment.call_with_account_bucket(
+        "instantiate",
+        y_address,
+        dec!(10),
+        |builder, y_bucket| {
+            builder.call_function(
+                package_address,
+                "HelloSwap",
+                "instantiate",
+                manifest_args!(x_address, y_bucket, dec!(2)),
+            )
+        },
+    );
+    let receipt = test_environment.execute_expect_success(false);
+    let (pool_address, _): (ComponentAddress, Decimal) = receipt.outputs("instantiate")[0];
+    let account = test_environment.account;
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, dec!(1))
+    });
+    // The swap calls the vaults of the pool from within the instruction
+    test_environment.call_with_account_bucket("swap", x_address, dec!(3), |builder, x_bucket| {
+        builder.call_method(pool_address, "swap", manifest_args!(x_bucket))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let withdraw_cost = receipt.cost_by_label("withdraw").unwrap()[0];
+    let swap_cost = receipt.cost_by_label("swap").unwrap()[0];
+    assert!(swap_cost > withdraw_cost);
+    let attributed_cost: u64 = receipt
+        .execution_receipt
+        .cost_by_instruction()
+        .unwrap()
+        .iter()
+        .sum();
+    assert!(
+        attributed_cost
+            <= receipt
+                .execution_receipt
+                .fee_summary
+                .total_execution_cost_units_consumed as u64
+    );
+}
+
+#[test]
+fn test_execute_expect_failure_containing() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, MAX_SUPPLY)
+    });
+
+    test_environment.execute_expect_failure_containing("InsufficientBalance", false);
+
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, MAX_SUPPLY)
+    });
+
+    test_environment
+        .execute_expect_failure_matching(r"ResourceError\(.*InsufficientBalance", false);
+}
+
+#[test]
+fn test_execute_expect_specific_failure() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, MAX_SUPPLY)
+    });
+
+    test_environment.execute_expect_specific_failure(
+        |error| matches!(error, RuntimeError::ApplicationError(_)),
+        false,
+    );
+}
+
+#[test]
+fn test_execute_expect_auth_failure() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let x_address = test_environment.x_address;
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(trader, x_address, dec!(1))
+    });
+
+    test_environment.execute_expect_auth_failure(false);
+}
+
+#[test]
+fn test_execute_without_preview() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+
+    let receipt = test_environment.execute_without_preview(false);
+    assert!(receipt.try_preview_receipt().is_none());
+    receipt.execution_receipt.expect_commit_success();
+
+    test_environment.preview_enabled = false;
+    assert!(test_environment
+        .execute(false)
+        .try_preview_receipt()
+        .is_none());
+    test_environment.preview_enabled = true;
+    test_environment
+        .execute(false)
+        .preview_receipt()
+        .expect_commit_success();
+}
+
+#[test]
+fn test_execute_with_preview_flags() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let preview_flags = PreviewFlags {
+        use_free_credit: true,
+        assume_all_signature_proofs: true,
+        skip_epoch_check: true,
+        ..Default::default()
+    };
+
+    let receipt = test_environment.execute_with_preview_flags(preview_flags.clone(), false);
+    receipt.preview_receipt().expect_commit_success();
+
+    test_environment.preview_flags = preview_flags;
+    test_environment.execute_expect_success(false);
+}
+
+#[test]
+fn test_preview() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let trader = test_environment.new_account("trader");
+    test_environment.add_instructions("transfer", |builder| {
+        builder
+            .withdraw_from_account(account, x_address, dec!(1))
+            .try_deposit_entire_worktop_or_abort(trader, None)
+    });
+
+    test_environment.preview(false).expect_commit_success();
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, x_address),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_execute_as() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let x_address = test_environment.x_address;
+    test_environment.transfer(test_environment.account, trader, x_address, dec!(5));
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(trader, x_address, dec!(2))
+    });
+
+    let receipt = test_environment.execute_as("trader", false);
+
+    receipt.execution_receipt.expect_commit_success();
+    let balance_changes = receipt.balance_changes();
+    assert!(balance_changes.fee(trader) > Decimal::ZERO);
+    assert_eq!(balance_changes.fee(test_environment.account), Decimal::ZERO);
+    assert_eq!(balance_changes.fungible(trader, x_address), Decimal::ZERO);
+}
+
+#[test]
+fn test_fee_config() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let sponsor = test_environment.new_account("sponsor");
+
+    test_environment.fee_config = FeeConfig {
+        payer: FeePayer::Account(sponsor),
+        amount: dec!(100),
+        tip_percentage: 0,
+    };
+    let receipt = test_environment.execute_expect_success(false);
+    assert!(receipt.balance_changes().fee(sponsor) > Decimal::ZERO);
+    assert_eq!(receipt.balance_changes().fee(account), Decimal::ZERO);
+
+    test_environment.fee_config.payer = FeePayer::Component(FAUCET);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(receipt.balance_changes().fee(sponsor), Decimal::ZERO);
+    assert_eq!(receipt.balance_changes().fee(account), Decimal::ZERO);
+    assert_eq!(receipt.fee_breakdown().tipping, Decimal::ZERO);
+
+    test_environment.fee_config.tip_percentage = 10;
+    let receipt = test_environment.execute_expect_success(false);
+    let fee_breakdown = receipt.fee_breakdown();
+    assert!(fee_breakdown.tipping > Decimal::ZERO);
+    assert!(fee_breakdown.tipping <= (fee_breakdown.execution + fee_breakdown.finalization) / 10);
+    assert!(test_environment.history[receipt.transaction_index]
+        .notarized_transaction
+        .is_some());
+}
+
+#[test]
+fn test_execute_all() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let trader = test_environment.new_account("trader");
+    let manifests = (0..3)
+        .map(|_| {
+            ManifestBuilder::new()
+                .lock_standard_test_fee(account)
+                .withdraw_from_account(account, x_address, dec!(1))
+                .try_deposit_entire_worktop_or_abort(trader, None)
+                .build()
+        })
+        .collect();
+
+    let receipts = test_environment.execute_all(manifests);
+
+    assert_eq!(receipts.len(), 3);
+    for receipt in &receipts {
+        receipt.execution_receipt.expect_commit_success();
+        assert_eq!(
+            receipt.balance_changes().fungible(trader, x_address),
+            dec!(1)
+        );
+    }
+}
+
+#[test]
+fn test_history() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+
+    let receipt = test_environment.execute_expect_success(false);
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, MAX_SUPPLY)
+    });
+    test_environment.execute_expect_failure(false);
+
+    assert_eq!(test_environment.transaction_count(), 2);
+    assert_eq!(test_environment.failed_transactions().len(), 1);
+    assert!(test_environment.history()[0].is_success());
+    assert!(test_environment.total_fees_paid_xrd() > receipt.fee_paid_xrd());
+}
+
+#[test]
+fn test_dump_manifest() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    test_environment.execute_expect_success(false);
+    let path = std::env::temp_dir().join("scrypto_testenv_test_dump_manifest.rtm");
+
+    test_environment.dump_manifest(&path);
+
+    let manifest_text = std::fs::read_to_string(&path).unwrap();
+    assert!(manifest_text.contains("\"lock_fee\""));
+    assert!(manifest_text.contains("\"deposit_batch\""));
+}
+
+#[test]
+fn test_dump_pending_manifest() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    test_environment.execute_expect_success(false);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    test_environment.add_instructions("withdraw", |builder| {
+        builder.withdraw_from_account(account, x_address, dec!(1))
+    });
+    let path = std::env::temp_dir().join("scrypto_testenv_test_dump_pending_manifest.rtm");
+
+    test_environment.dump_manifest(&path);
+
+    let manifest_text = std::fs::read_to_string(&path).unwrap();
+    assert!(manifest_text.contains("\"withdraw\""));
+    assert!(!manifest_text.contains("\"deposit_batch\""));
+}
+
+#[test]
+fn test_execute_manifest_file() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let trader = test_environment.new_account("trader");
+    let x_address = test_environment.x_address;
+    let path = std::env::temp_dir().join("scrypto_testenv_test_execute_manifest_file.rtm");
+    std::fs::write(
+        &path,
+        r#"
+CALL_METHOD Address("${account}") "lock_fee" Decimal("5000");
+CALL_METHOD Address("${account}") "withdraw" Address("${X}") Decimal("${amount}");
+CALL_METHOD Address("${account:trader}") "try_deposit_batch_or_abort" Expression("ENTIRE_WORKTOP") None;
+"#,
+    )
+    .unwrap();
+
+    let receipt =
+        test_environment.execute_manifest_file(&path, HashMap::from([("amount", "3".to_string())]));
+
+    receipt.execution_receipt.expect_commit_success();
+    assert_eq!(
+        receipt.balance_changes().fungible(trader, x_address),
+        dec!(3)
+    );
+}
+
+#[test]
+#[should_panic(expected = "No Info log containing \"swapped\" found")]
+fn test_assert_log_contains() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    assert!(receipt.logs().is_empty());
+    receipt.assert_log_contains(Level::Info, "swapped");
+}
+
+#[test]
+fn test_logs() {
+    let packages: HashMap<&str, &str> = HashMap::from([("hello_swap", "examples/hello_swap")]);
+    let mut test_environment = TestEnvironment::new(packages);
+    let package_address = test_environment.package_address("hello_swap");
+    let (x_address, y_address) = (test_environment.x_address, test_environment.y_address);
+    test_environment.call_with_bucket("instantiate", y_address, dec!(10), |builder, y_bucket| {
+        builder.call_function(
+            package_address,
+            "HelloSwap",
+            "instantiate",
+            manifest_args!(x_address, y_bucket, dec!(2)),
+        )
+    });
+    let receipt = test_environment.execute_expect_success(false);
+    let (pool_address, _): (ComponentAddress, Decimal) = receipt.outputs("instantiate")[0];
+    test_environment.call_with_bucket("swap", x_address, dec!(3), |builder, x_bucket| {
+        builder.call_method(pool_address, "swap", manifest_args!(x_bucket))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    assert_eq!(
+        receipt.logs(),
+        vec![(Level::Info, "swapped 2 for 1".to_string())]
+    );
+    receipt.assert_log_contains(Level::Info, "swapped");
+}
+
+#[test]
+fn test_single_flight_generation() {
+    use crate::cache::lock_cache_for_test;
+
+    let _cache_guard = lock_cache_for_test();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                TestEnvironmentBuilder::new()
+                    .additional_accounts(11)
+                    .build()
+                    .setup_timings
+                    .cache_misses
+            })
+        })
+        .collect();
+
+    let cache_misses: usize = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .sum();
+
+    // Without caching every thread generates its own environment
+    let expected_misses = match cache_enabled() {
+        true => 1,
+        false => 4,
+    };
+    assert_eq!(cache_misses, expected_misses);
+}
+
+#[test]
+fn test_generation_locks_released() {
+    let test_environment = TestEnvironmentBuilder::new()
+        .additional_accounts(23)
+        .build();
+    let config = test_environment.config().clone();
+    let cache_key = (config.clone(), packages_fingerprint(&config));
+
+    assert!(!ENVIRONMENT_GENERATION_LOCKS
+        .lock()
+        .unwrap()
+        .contains_key(&cache_key));
+}
+
+#[test]
+fn test_evict_least_recently_used() {
+    let test_environment = TestEnvironmentBuilder::new().build();
+    let mut cache: HashMap<&str, CachedEnvironment> = [("a", 3), ("b", 1), ("c", 2)]
+        .into_iter()
+        .map(|(key, last_used)| {
+            let cached = CachedEnvironment {
+                snapshot: test_environment.create_snapshot(),
+                last_used: AtomicU64::new(last_used),
+            };
+            (key, cached)
+        })
+        .collect();
+
+    evict_least_recently_used(&mut cache, 2);
+    let mut keys: Vec<&str> = cache.keys().copied().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["a", "c"]);
+
+    evict_least_recently_used(&mut cache, 2);
+    assert_eq!(cache.len(), 2);
+    evict_least_recently_used(&mut cache, 0);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_lazy_resources() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .additional_accounts(19)
+        .balance(0, "V", dec!(5))
+        .build();
+
+    assert_eq!(test_environment.u_address, None);
+    assert_eq!(test_environment.j_nft_address, None);
+    assert_eq!(test_environment.d0_address, None);
+    assert!(!test_environment.resources_by_symbol.contains_key("U"));
+    // Configured balances create U and V during the setup
+    let (trader, v_address) = (
+        test_environment.accounts[0].address,
+        test_environment.v_address.unwrap(),
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, v_address),
+        dec!(5)
+    );
+
+    let u_address = test_environment.u_address();
+
+    assert_eq!(test_environment.u_address(), u_address);
+    assert_eq!(test_environment.resource_by_symbol("U"), u_address);
+    assert_eq!(test_environment.balance(TestAddress::U), dec!(1000000000));
+    // The access is kept in snapshots
+    let mut test_environment_revived = test_environment.create_snapshot().revive();
+    assert_eq!(test_environment_revived.u_address, Some(u_address));
+    assert_eq!(test_environment_revived.j_nft_address, None);
+    assert_eq!(
+        test_environment_revived.balance(TestAddress::U),
+        dec!(1000000000)
+    );
+}
+
+#[test]
+fn test_simulator_options() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .with_kernel_trace()
+        .without_receipt_substate_check()
+        .additional_accounts(1)
+        .build();
+    assert!(test_environment.config().kernel_trace);
+    assert!(test_environment.config().skip_receipt_substate_check);
+    let (account, trader, a_address) = (
+        test_environment.account,
+        test_environment.accounts[0].address,
+        test_environment.a_address,
+    );
+
+    test_environment.transfer(account, trader, a_address, dec!(1));
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(trader, a_address),
+        dec!(1)
+    );
+}
+
+#[test]
+fn test_protocol_version() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .protocol_version(ProtocolVersion::Anemone)
+        .packages(packages)
+        .build();
+    let (account, a_address) = (test_environment.account, test_environment.a_address);
+
+    assert_eq!(
+        test_environment.config().protocol_version,
+        Some(ProtocolVersion::Anemone)
+    );
+    assert_eq!(
+        test_environment.ledger_protocol_version(),
+        ProtocolVersion::Anemone
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, a_address),
+        MAX_SUPPLY
+    );
+    assert_eq!(
+        TestEnvironmentBuilder::new()
+            .protocol_version(ProtocolVersion::Babylon)
+            .build()
+            .ledger_protocol_version(),
+        ProtocolVersion::Babylon
+    );
+    assert_eq!(
+        TestEnvironmentBuilder::new()
+            .build()
+            .ledger_protocol_version(),
+        ProtocolVersion::Bottlenose
+    );
+}
+
+#[test]
+fn test_apply_protocol_update() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .protocol_version(ProtocolVersion::Anemone)
+        .additional_accounts(1)
+        .build();
+    let (account, trader, a_address) = (
+        test_environment.account,
+        test_environment.accounts[0].address,
+        test_environment.a_address,
+    );
+    test_environment.transfer(account, trader, a_address, dec!(1));
+
+    test_environment.apply_protocol_update(ProtocolVersion::Bottlenose.protocol_update().unwrap());
+
+    assert_eq!(
+        test_environment.ledger_protocol_version(),
+        ProtocolVersion::Bottlenose
+    );
+    test_environment.transfer(trader, account, a_address, dec!(1));
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, a_address),
+        MAX_SUPPLY
+    );
+}
+
+#[test]
+fn test_genesis() {
+    let genesis_account = ComponentAddress::virtual_account_from_public_key(
+        &Secp256k1PrivateKey::from_u64(1000).unwrap().public_key(),
+    );
+    let test_environment = TestEnvironmentBuilder::new()
+        .genesis(GenesisConfig {
+            epoch: 100,
+            xrd_balances: vec![(genesis_account, dec!(1234))],
+            ..Default::default()
+        })
+        .build();
+
+    assert_eq!(
+        test_environment.test_runner.get_current_epoch(),
+        Epoch::of(100)
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(genesis_account, XRD),
+        dec!(1234)
+    );
+}
+
+#[test]
+fn test_deterministic_addresses() {
+    let config = TestEnvironmentConfig {
+        additional_accounts: 2,
+        badges: vec!["admin".to_string()],
+        ..Default::default()
+    };
+
+    let mut test_environment = TestEnvironment::generate_new_test_environment(&config).unwrap();
+    let mut test_environment_other =
+        TestEnvironment::generate_new_test_environment(&config).unwrap();
+
+    assert_eq!(test_environment.account, test_environment_other.account);
+    assert_eq!(
+        test_environment.accounts[1].address,
+        test_environment_other.accounts[1].address
+    );
+    assert_eq!(test_environment.a_address, test_environment_other.a_address);
+    assert_eq!(
+        test_environment.badge("admin"),
+        test_environment_other.badge("admin")
+    );
+    assert_eq!(
+        test_environment.u_address(),
+        test_environment_other.u_address()
+    );
+    assert_eq!(
+        test_environment.new_account("trader"),
+        test_environment_other.new_account("trader")
+    );
+}
+
+#[test]
+fn test_deterministic_mode() {
+    let test_environment = TestEnvironmentBuilder::new()
+        .deterministic()
+        .package("hello_swap", "examples/hello_swap")
+        .build();
+
+    assert_eq!(
+        test_environment
+            .package_address("hello_swap")
+            .as_node_id()
+            .0,
+        [
+            13, 48, 81, 136, 38, 146, 47, 47, 229, 13, 96, 183, 156, 122, 224, 216, 85, 27, 77,
+            120, 103, 138, 174, 79, 95, 243, 242, 52, 226, 218
+        ]
+    );
+    // Private key 1, the first key handed out by the simulator
+    assert_eq!(
+        test_environment.main_account.key,
+        PublicKey::Secp256k1(Secp256k1PublicKey([
+            0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+            0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+            0x5b, 0x16, 0xf8, 0x17, 0x98
+        ]))
+    );
 }
 
-pub trait TransactionReceiptOutputBuckets {
-    fn output_buckets(&self, instruction_ids: Vec<usize>) -> Vec<Vec<ResourceSpecifier>>;
-    fn outputs<T>(&self, instruction_ids: Vec<usize>) -> Vec<T>
-    where
-        T: ScryptoDecode;
+#[test]
+fn test_execute_with_epoch_range() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let epoch = test_environment.current_epoch();
+
+    test_environment
+        .execute_with_epoch_range(epoch, epoch.after(10).unwrap(), false)
+        .execution_receipt
+        .expect_commit_success();
+
+    test_environment.advance_epochs(20);
+    // Expired
+    test_environment
+        .execute_with_epoch_range(epoch, epoch.after(10).unwrap(), false)
+        .execution_receipt
+        .expect_rejection();
+    // Not yet valid
+    test_environment
+        .execute_with_epoch_range(epoch.after(30).unwrap(), epoch.after(40).unwrap(), false)
+        .execution_receipt
+        .expect_rejection();
 }
 
-impl TransactionReceiptOutputBuckets for TransactionReceipt {
-    fn output_buckets(&self, instruction_ids: Vec<usize>) -> Vec<Vec<ResourceSpecifier>> {
-        match &self.expect_commit_success().execution_trace {
-            None => vec![],
-            Some(execution_trace) => {
-                let worktop_changes = execution_trace.worktop_changes();
-                instruction_ids
-                    .iter()
-                    .filter_map(|id| {
-                        let instruction_worktop_changes = worktop_changes.get(id).unwrap();
-                        Some(
-                            instruction_worktop_changes
-                                .iter()
-                                .filter_map(|change| match change {
-                                    WorktopChange::Put(resource_specifier) => {
-                                        Some(resource_specifier.clone())
-                                    }
-                                    _ => None,
-                                })
-                                .collect(),
-                        )
-                    })
-                    .collect()
-            }
-        }
-    }
+#[test]
+fn test_resubmit() {
+    use radix_engine::transaction::RejectionReason;
 
-    fn outputs<T>(&self, instruction_ids: Vec<usize>) -> Vec<T>
-    where
-        T: ScryptoDecode,
-    {
-        instruction_ids
-            .iter()
-            .filter_map(|id| Some(self.expect_commit_success().output(*id)))
-            .collect()
-    }
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let epoch = test_environment.current_epoch();
+    test_environment
+        .execute_with_epoch_range(epoch, epoch.after(10).unwrap(), false)
+        .execution_receipt
+        .expect_commit_success();
+
+    let receipt = test_environment.resubmit(0);
+
+    assert_eq!(
+        receipt.execution_receipt.expect_rejection(),
+        &RejectionReason::IntentHashPreviouslyCommitted
+    );
+    assert_eq!(receipt.transaction_index, 1);
+    assert_eq!(
+        test_environment.history()[0].notarized_transaction,
+        test_environment.history()[1].notarized_transaction
+    );
 }
 
-pub trait GetResourceAddress {
-    fn address(&self) -> ResourceAddress;
+#[test]
+fn test_allocate_global_address() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, a_address) = (test_environment.account, test_environment.a_address);
+
+    test_environment.allocate_global_address(ACCOUNT_PACKAGE, ACCOUNT_BLUEPRINT, "new_account");
+    test_environment.add_instructions("create_account", |builder| {
+        builder
+            .with_name_lookup(|builder, lookup| {
+                let reservation = lookup.address_reservation("new_account");
+                builder.create_account_advanced(OwnerRole::None, Some(reservation))
+            })
+            .withdraw_from_account(account, a_address, dec!(10))
+            .try_deposit_entire_worktop_or_abort("new_account", None)
+    });
+    assert_eq!(
+        test_environment.instruction_ids_by_label["new_account"],
+        vec![INSTRUCTION_COUNTER_INIT]
+    );
+    assert_eq!(
+        test_environment.instruction_ids_by_label["create_account"],
+        vec![INSTRUCTION_COUNTER_INIT + 3]
+    );
+    let receipt = test_environment.execute_expect_success(false);
+
+    let new_account = receipt
+        .execution_receipt
+        .expect_commit_success()
+        .new_component_addresses()[0];
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(new_account, a_address),
+        dec!(10)
+    );
 }
 
-impl GetResourceAddress for ResourceSpecifier {
-    fn address(&self) -> ResourceAddress {
-        match self {
-            ResourceSpecifier::Amount(address, _) => *address,
-            ResourceSpecifier::Ids(address, _) => *address,
-        }
-    }
+#[test]
+fn test_key_seed() {
+    let config = |key_seed| TestEnvironmentConfig {
+        key_seed,
+        ..Default::default()
+    };
+
+    let test_environment = TestEnvironment::generate_new_test_environment(&config(7)).unwrap();
+
+    assert_eq!(
+        TestEnvironment::generate_new_test_environment(&config(7))
+            .unwrap()
+            .account,
+        test_environment.account
+    );
+    assert_ne!(
+        TestEnvironment::generate_new_test_environment(&config(0))
+            .unwrap()
+            .account,
+        test_environment.account
+    );
+    assert_ne!(
+        TestEnvironment::generate_new_test_environment(&config(8))
+            .unwrap()
+            .account,
+        test_environment.account
+    );
+    // Keys are derived from the seed directly, so huge seeds don't take longer
+    let mut test_environment_max_seed =
+        TestEnvironment::generate_new_test_environment(&config(u64::MAX)).unwrap();
+    assert_ne!(test_environment_max_seed.account, test_environment.account);
+    let trader = test_environment_max_seed.new_account("trader");
+    assert_ne!(trader, test_environment_max_seed.account);
 }
 
-pub fn sort_addresses(
-    a_address: ResourceAddress,
-    b_address: ResourceAddress,
-) -> (ResourceAddress, ResourceAddress) {
-    if a_address < b_address {
-        (a_address, b_address)
-    } else {
-        (b_address, a_address)
+#[test]
+fn test_new_identity() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+
+    let identity = test_environment.new_identity("dapp");
+
+    assert_eq!(test_environment.identity("dapp"), identity);
+    let owner_badge = test_environment.identity_owner_badge(identity);
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_component_balance(account, owner_badge.resource_address()),
+        dec!(1)
+    );
+    let mut test_environment_revived = test_environment.create_snapshot().revive();
+    assert_eq!(test_environment_revived.identity("dapp"), identity);
+    assert_eq!(
+        test_environment_revived
+            .test_runner
+            .get_component_balance(account, IDENTITY_OWNER_BADGE),
+        dec!(1)
+    );
+}
+
+#[test]
+fn test_deterministic_package_address() {
+    assert_eq!(
+        deterministic_package_address("hello_swap"),
+        deterministic_package_address("hello_swap")
+    );
+    assert_ne!(
+        deterministic_package_address("hello_swap"),
+        deterministic_package_address("other")
+    );
+}
+
+#[test]
+fn test_try_variants() {
+    let packages: HashMap<&str, &str> = HashMap::from([("missing", "does/not/exist")]);
+    assert_eq!(
+        TestEnvironment::try_new(packages).err(),
+        Some(TestEnvError::PackageDirNotFound(PathBuf::from(
+            "does/not/exist"
+        )))
+    );
+
+    let packages: HashMap<&str, &str> = HashMap::from([("invalid", "testdata/invalid")]);
+    assert!(matches!(
+        TestEnvironment::try_new(packages),
+        Err(TestEnvError::CompilationFailed { .. })
+    ));
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::try_new(packages).unwrap();
+    assert_eq!(
+        test_environment.try_package_address("missing"),
+        Err(TestEnvError::PackageNotFound("missing".to_string()))
+    );
+
+    // Invalid wasm is reported as error instead of a panic of the simulator
+    let (_, definition) = test_environment.compile_package(Path::new("testdata/kv_store"));
+    assert!(matches!(
+        test_environment.try_publish_compiled_package("invalid", (vec![0, 1, 2, 3], definition)),
+        Err(TestEnvError::SetupFailed(_))
+    ));
+    assert_eq!(
+        test_environment.try_package_address("invalid"),
+        Err(TestEnvError::PackageNotFound("invalid".to_string()))
+    );
+
+    let x_address = test_environment.x_address;
+    test_environment.add_instructions("assert", |builder| {
+        builder.assert_worktop_contains(x_address, dec!(1))
+    });
+    assert!(matches!(
+        test_environment.try_execute(false),
+        Err(TestEnvError::TransactionFailed { .. })
+    ));
+}
+
+#[test]
+fn test_helper_namespace() {
+    struct PoolTestHelper {
+        env: TestEnvironment,
+    }
+    impl TestHelperExecution for PoolTestHelper {
+        fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {
+            &mut self.env
+        }
+        fn namespace(&self) -> Option<&str> {
+            Some("pool")
+        }
     }
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut helper = PoolTestHelper {
+        env: TestEnvironment::new(packages),
+    };
+
+    assert_eq!(helper.name("x_bucket"), "pool_x_bucket_1");
+    assert_eq!(helper.label("swap"), "pool::swap");
+    assert_eq!(helper.env.name("x_bucket"), "x_bucket_1");
+    assert_eq!(helper.env.label("swap"), "swap");
+
+    // Instructions registered through the helper are labeled in its namespace
+    let account = helper.env.account;
+    helper.add_instructions("deposit", |builder| builder.deposit_entire_worktop(account));
+    helper
+        .env
+        .add_instructions("deposit", |builder| builder.deposit_entire_worktop(account));
+    assert_eq!(
+        helper.env.instruction_ids_by_label["pool::deposit"].len(),
+        1
+    );
+    assert_eq!(helper.env.instruction_ids_by_label["deposit"].len(), 1);
 }
 
-pub trait CreateFungibleResourceAdvanced {
-    fn create_fungible_resource_advanced(
-        &mut self,
-        amount: Decimal,
-        divisibility: u8,
-        account: ComponentAddress,
-        metadata: ModuleConfig<MetadataInit>,
-    ) -> ResourceAddress;
+#[test]
+fn test_apply_protocol_update_enables_blueprints() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .protocol_version(ProtocolVersion::Anemone)
+        .build();
+    let instantiate_locker = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_function(
+            LOCKER_PACKAGE,
+            ACCOUNT_LOCKER_BLUEPRINT,
+            ACCOUNT_LOCKER_INSTANTIATE_SIMPLE_IDENT,
+            AccountLockerInstantiateSimpleManifestInput {
+                allow_recover: false,
+            },
+        )
+        .build();
+    let receipt = test_environment
+        .test_runner
+        .execute_manifest(instantiate_locker.clone(), vec![]);
+    assert!(!receipt.is_commit_success());
+
+    test_environment.apply_protocol_update(ProtocolUpdate::Bottlenose);
+
+    test_environment
+        .test_runner
+        .execute_manifest(instantiate_locker, vec![])
+        .expect_commit_success();
 }
 
-impl CreateFungibleResourceAdvanced for LedgerSimulator<NoExtension, InMemorySubstateDatabase> {
-    fn create_fungible_resource_advanced(
-        &mut self,
-        amount: Decimal,
-        divisibility: u8,
-        account: ComponentAddress,
-        metadata: ModuleConfig<MetadataInit>,
-    ) -> ResourceAddress {
+#[test]
+fn test_package_owner_role() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package("hello_swap", "examples/hello_swap")
+        .package("kv_store", "testdata/kv_store")
+        .package_owner_role("kv_store", OwnerRole::None)
+        .build();
+    // Setting metadata of a package requires its owner role
+    let mut set_name_as_admin = |package_name: &str| {
+        let package_address = test_environment.package_address(package_name);
         let manifest = ManifestBuilder::new()
             .lock_fee_from_faucet()
-            .create_fungible_resource(
-                OwnerRole::None,
-                true,
-                divisibility,
-                FungibleResourceRoles::default(),
-                metadata,
-                Some(amount),
+            .create_proof_from_account_of_amount(
+                test_environment.account,
+                test_environment.admin_badge_address,
+                dec!(1),
             )
-            .try_deposit_entire_worktop_or_abort(account, None)
+            .set_metadata(package_address, "name", "renamed")
             .build();
-        let receipt = self.execute_manifest(manifest, vec![]);
-        receipt.expect_commit(true).new_resource_addresses()[0]
-    }
+        let initial_proofs = test_environment.signatures(&[test_environment.account]);
+        test_environment
+            .test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .is_commit_success()
+    };
+
+    assert!(set_name_as_admin("hello_swap"));
+    assert!(!set_name_as_admin("kv_store"));
 }
 
 #[test]
-fn test_nft_id() {
-    assert_eq!(nft_id!(3), NonFungibleLocalId::Integer((3).into()))
+fn test_package_metadata() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package("hello_swap", "examples/hello_swap")
+        .package_metadata("hello_swap", "name", "Hello Swap")
+        .link_package_to_dapp_definition("hello_swap")
+        .build();
+    let package_address: GlobalAddress = test_environment.package_address("hello_swap").into();
+    let dapp_definition = test_environment.dapp_definition;
+
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_metadata(package_address, "name"),
+        Some(MetadataValue::String("Hello Swap".to_string()))
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_metadata(package_address, "dapp_definition"),
+        Some(MetadataValue::GlobalAddress(dapp_definition.into()))
+    );
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_metadata(package_address, "description"),
+        None
+    );
 }
 
 #[test]
-fn test_nft_ids() {
+fn test_publish_package_from_files() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (code, definition) = test_environment.compile_package(Path::new("examples/hello_swap"));
+    let wasm_path = std::env::temp_dir().join("scrypto_testenv_test_publish_package.wasm");
+    // Different path types for the wasm and rpd file
+    let rpd_path = std::env::temp_dir()
+        .join("scrypto_testenv_test_publish_package.rpd")
+        .display()
+        .to_string();
+    std::fs::write(&wasm_path, &code).unwrap();
+    std::fs::write(&rpd_path, manifest_encode(&definition).unwrap()).unwrap();
+
+    let package_address =
+        test_environment.publish_package_from_files("hello_swap", &wasm_path, &rpd_path);
+
     assert_eq!(
-        nft_ids!(1, 3, 2),
-        IndexSet::from([
-            NonFungibleLocalId::Integer((1).into()),
-            NonFungibleLocalId::Integer((2).into()),
-            NonFungibleLocalId::Integer((3).into()),
-        ])
-    )
+        test_environment.package_address("hello_swap"),
+        package_address
+    );
+    assert_eq!(
+        test_environment.package_definitions.get("hello_swap"),
+        Some(&definition)
+    );
 }
 
 #[test]
-fn test_test_environment_snapshot() {
-    let packages: HashMap<&str, &str> = HashMap::new();
-    let test_environment = TestEnvironment::new(packages);
-    let test_environment_new = TestEnvironmentSnapshot::from(&test_environment).revive();
+fn test_publish_package_bytes() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package_metadata("in_memory", "name", "In Memory")
+        .build();
+    let (code, definition) = test_environment.compile_package(Path::new("examples/hello_swap"));
 
-    assert!(test_environment.package_addresses == test_environment_new.package_addresses);
-    assert!(test_environment.public_key == test_environment_new.public_key);
-    assert!(test_environment.account == test_environment_new.account);
-    assert!(test_environment.dapp_definition == test_environment_new.dapp_definition);
-    assert!(test_environment.admin_badge_address == test_environment_new.admin_badge_address);
-    assert!(test_environment.a_address == test_environment_new.a_address);
-    assert!(test_environment.b_address == test_environment_new.b_address);
-    assert!(test_environment.x_address == test_environment_new.x_address);
-    assert!(test_environment.y_address == test_environment_new.y_address);
-    assert!(test_environment.u_address == test_environment_new.u_address);
-    assert!(test_environment.v_address == test_environment_new.v_address);
-    assert!(test_environment.j_nft_address == test_environment_new.j_nft_address);
-    assert!(test_environment.k_nft_address == test_environment_new.k_nft_address);
+    let package_address = test_environment.publish_package_bytes("in_memory", code, definition);
+
+    assert_eq!(
+        test_environment.package_address("in_memory"),
+        package_address
+    );
+    // The config of the package name is applied to in-memory packages as well
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_metadata(package_address.into(), "name"),
+        Some(MetadataValue::String("In Memory".to_string()))
+    );
+}
+
+#[test]
+fn test_package_at_address() {
+    let address = deterministic_package_address("preallocated");
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package("preallocated", "testdata/kv_store")
+        .package_at_address("preallocated", address)
+        .package_metadata("preallocated", "name", "Preallocated")
+        .package_owner_role("preallocated", OwnerRole::None)
+        .build();
+
+    assert_eq!(test_environment.package_address("preallocated"), address);
+    assert_eq!(
+        test_environment
+            .test_runner
+            .get_metadata(address.into(), "name"),
+        Some(MetadataValue::String("Preallocated".to_string()))
+    );
+    // Without owner the metadata can't be changed anymore, not even by the admin badge
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .create_proof_from_account_of_amount(
+            test_environment.account,
+            test_environment.admin_badge_address,
+            dec!(1),
+        )
+        .set_metadata(address, "name", "renamed")
+        .build();
+    let initial_proofs = test_environment.signatures(&[test_environment.account]);
+    test_environment
+        .test_runner
+        .execute_manifest(manifest, initial_proofs)
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_upgrade_package() {
+    let packages: HashMap<&str, &str> = HashMap::from([("pool", "testdata/kv_store")]);
+    let mut test_environment = TestEnvironment::new(packages);
+    let old_package_address = test_environment.package_address("pool");
+
+    let mut migrated = None;
+    let new_package_address = test_environment.upgrade_package_with_migration(
+        "pool",
+        "examples/hello_swap",
+        |_, old_package, new_package| migrated = Some((old_package, new_package)),
+    );
+
+    assert_ne!(new_package_address, old_package_address);
+    assert_eq!(migrated, Some((old_package_address, new_package_address)));
+    assert_eq!(
+        test_environment.package_address("pool"),
+        new_package_address
+    );
+    assert_eq!(
+        test_environment.previous_package_addresses.get("pool"),
+        Some(&vec![old_package_address])
+    );
+    assert!(test_environment.package_definitions["pool"]
+        .blueprints
+        .contains_key("HelloSwap"));
+
+    let newest_package_address = test_environment.upgrade_package("pool", "testdata/kv_store");
+    assert_eq!(
+        test_environment.previous_package_addresses.get("pool"),
+        Some(&vec![old_package_address, new_package_address])
+    );
+    assert_eq!(
+        test_environment.package_address("pool"),
+        newest_package_address
+    );
+}
+
+#[test]
+fn test_upgrade_package_deterministic() {
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package("pool", "testdata/kv_store")
+        .deterministic()
+        .build();
+    let old_package_address = test_environment.package_address("pool");
+
+    let new_package_address = test_environment.upgrade_package("pool", "examples/hello_swap");
+    let newest_package_address = test_environment.upgrade_package("pool", "testdata/kv_store");
+
+    assert_eq!(old_package_address, deterministic_package_address("pool"));
+    assert_eq!(new_package_address, deterministic_package_address("pool@1"));
+    assert_eq!(
+        newest_package_address,
+        deterministic_package_address("pool@2")
+    );
+    assert_eq!(
+        test_environment.previous_package_addresses.get("pool"),
+        Some(&vec![old_package_address, new_package_address])
+    );
+    assert!(test_environment.package_definitions["pool"]
+        .blueprints
+        .contains_key("KvStore"));
+}
+
+#[test]
+fn test_workspace() {
+    let test_environment = TestEnvironmentBuilder::new()
+        .workspace("testdata/workspace")
+        .package("first", "testdata/workspace/first")
+        .package("second", "testdata/workspace/second-package")
+        .build();
+
+    assert!(test_environment.package_definitions["first"]
+        .blueprints
+        .contains_key("First"));
+    assert!(test_environment.package_definitions["second"]
+        .blueprints
+        .contains_key("Second"));
+}
+
+#[test]
+fn test_compiler_options() {
+    let call_functions = |options: CompilerOptions| {
+        let mut test_environment = TestEnvironmentBuilder::new()
+            .package("compiler_options", "testdata/compiler_options")
+            .compiler_options(options)
+            .build();
+        let package_address = test_environment.package_address("compiler_options");
+        test_environment.add_instructions("test_hook_enabled", |builder| {
+            builder.call_function(
+                package_address,
+                "CompilerOptions",
+                "test_hook_enabled",
+                manifest_args!(),
+            )
+        });
+        test_environment.add_instructions("greeting", |builder| {
+            builder.call_function(
+                package_address,
+                "CompilerOptions",
+                "greeting",
+                manifest_args!(),
+            )
+        });
+        let receipt = test_environment.execute_expect_success(false);
+        let test_hook_enabled: bool = receipt.outputs("test_hook_enabled")[0];
+        let greeting: String = receipt.outputs("greeting")[0];
+        (test_hook_enabled, greeting)
+    };
+
+    assert_eq!(
+        call_functions(CompilerOptions::default()),
+        (false, "none".to_string())
+    );
+    assert_eq!(
+        call_functions(CompilerOptions {
+            features: vec!["test_hook".to_string()],
+            debug: true,
+            env_vars: BTreeMap::from([("TESTENV_GREETING".to_string(), "hello".to_string())]),
+            ..Default::default()
+        }),
+        (true, "hello".to_string())
+    );
+}
+
+#[cfg(feature = "coverage")]
+#[test]
+fn test_coverage() {
+    let build = || {
+        TestEnvironmentBuilder::new()
+            .package("hello_swap", "examples/hello_swap")
+            .coverage()
+            .try_build()
+    };
+    let Some(coverage_dir) = std::env::var_os(COVERAGE_DIRECTORY) else {
+        assert!(matches!(build(), Err(TestEnvError::SetupFailed(_))));
+        return;
+    };
+
+    let mut test_environment = build().unwrap();
+    assert_eq!(
+        test_environment.config().coverage_dir,
+        Some(PathBuf::from(coverage_dir))
+    );
+    let (package_address, x_address, v_address) = (
+        test_environment.package_address("hello_swap"),
+        test_environment.x_address,
+        test_environment.v_address(),
+    );
+    test_environment.call_with_bucket("instantiate", v_address, dec!(10), |builder, v_bucket| {
+        builder.call_function(
+            package_address,
+            "HelloSwap",
+            "instantiate",
+            manifest_args!(x_address, v_bucket, dec!(1)),
+        )
+    });
+    test_environment.execute_expect_success(false);
+
+    assert!(test_environment.coverage_files().iter().any(|path| path
+        .components()
+        .any(|component| component.as_os_str() == "HelloSwap")));
+}
+
+#[test]
+fn test_compile_packages_in_parallel() {
+    // Options not used by other tests, so none of the packages is cached yet
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .compiler_options(CompilerOptions {
+            env_vars: BTreeMap::from([(
+                "TESTENV_PARALLEL_COMPILATION".to_string(),
+                "1".to_string(),
+            )]),
+            ..Default::default()
+        })
+        .build();
+    let package_dirs = vec![
+        PathBuf::from("examples/hello_swap"),
+        PathBuf::from("testdata/kv_store"),
+        PathBuf::from("testdata/royalties"),
+    ];
+
+    let compiled_packages = test_environment
+        .compile_packages_in_parallel(package_dirs.clone())
+        .unwrap();
+
+    assert_eq!(compiled_packages.len(), 3);
+    for (package_dir, blueprint_name) in
+        package_dirs.iter().zip(["HelloSwap", "KvStore", "Counter"])
+    {
+        assert!(compiled_packages[package_dir]
+            .1
+            .blueprints
+            .contains_key(blueprint_name));
+    }
+    // Compiled packages are cached, so they are not compiled again
+    if cache_enabled() {
+        assert!(test_environment
+            .compile_packages_in_parallel(package_dirs)
+            .unwrap()
+            .is_empty());
+    }
 }
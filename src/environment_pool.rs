@@ -0,0 +1,121 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use crate::{TestEnvironment, TestEnvironmentBuilder, TestEnvironmentSnapshot};
+
+/// Keeps `size` revived environments ready, so tests don't pay the revival on checkout.
+///
+/// Meant to be shared by a whole test suite:
+/// ```ignore
+/// lazy_static! {
+///     static ref POOL: TestEnvironmentPool =
+///         TestEnvironmentPool::new(TestEnvironmentBuilder::new().package("dex", "."), 8);
+/// }
+///
+/// #[test]
+/// fn test_swap() {
+///     let mut test_environment = POOL.checkout();
+///     ...
+/// }
+/// ```
+pub struct TestEnvironmentPool {
+    snapshot: Arc<TestEnvironmentSnapshot>,
+    size: usize,
+    idle: Arc<Mutex<Vec<TestEnvironment>>>,
+}
+
+impl TestEnvironmentPool {
+    pub fn new(builder: TestEnvironmentBuilder, size: usize) -> Self {
+        let pool = Self {
+            snapshot: Arc::new(builder.build().create_snapshot()),
+            size,
+            idle: Arc::new(Mutex::new(Vec::with_capacity(size))),
+        };
+        for _ in 0..size {
+            pool.refill();
+        }
+        pool
+    }
+
+    /// Takes a ready environment from the pool (revives one if the pool ran dry).
+    /// Once the checked out environment is dropped, it's replaced by a fresh one in the background.
+    pub fn checkout(&self) -> PooledTestEnvironment<'_> {
+        let idle_environment = self.idle.lock().unwrap().pop();
+        let environment = idle_environment.unwrap_or_else(|| self.snapshot.revive());
+        PooledTestEnvironment {
+            pool: self,
+            environment: Some(environment),
+        }
+    }
+
+    /// Number of environments ready to be checked out
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    // Revives an environment on a background thread, unless the pool is already full
+    fn refill(&self) {
+        let (snapshot, idle, size) = (self.snapshot.clone(), self.idle.clone(), self.size);
+        std::thread::spawn(move || {
+            let environment = snapshot.revive();
+            let mut idle = idle.lock().unwrap();
+            if idle.len() < size {
+                idle.push(environment);
+            }
+        });
+    }
+}
+
+/// Environment checked out of a `TestEnvironmentPool`, derefs to `TestEnvironment`
+pub struct PooledTestEnvironment<'a> {
+    pool: &'a TestEnvironmentPool,
+    environment: Option<TestEnvironment>,
+}
+
+impl Deref for PooledTestEnvironment<'_> {
+    type Target = TestEnvironment;
+
+    fn deref(&self) -> &TestEnvironment {
+        self.environment.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledTestEnvironment<'_> {
+    fn deref_mut(&mut self) -> &mut TestEnvironment {
+        self.environment.as_mut().unwrap()
+    }
+}
+
+// A used environment can't be reset cheaper than reviving a new one,
+// so it's discarded and the pool is topped up again instead
+impl Drop for PooledTestEnvironment<'_> {
+    fn drop(&mut self) {
+        self.environment.take();
+        self.pool.refill();
+    }
+}
+
+#[test]
+fn test_pool_checkout() {
+    use crate::TestAddress;
+    use scrypto::prelude::*;
+
+    let pool = TestEnvironmentPool::new(TestEnvironmentBuilder::new().additional_accounts(1), 2);
+    let balance = {
+        let mut test_environment = pool.checkout();
+        let (account, trader, a_address) = (
+            test_environment.account,
+            test_environment.accounts[0].address,
+            test_environment.a_address,
+        );
+        let balance = test_environment.balance(TestAddress::A);
+        test_environment.transfer(account, trader, a_address, dec!(10));
+        assert_eq!(test_environment.balance(TestAddress::A), balance - dec!(10));
+        balance
+    };
+
+    let mut test_environment = pool.checkout();
+
+    assert_eq!(test_environment.balance(TestAddress::A), balance);
+    assert_eq!(test_environment.transaction_count(), 0);
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Errors returned by the `try_` variants of the TestEnvironment API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestEnvError {
+    /// A package directory passed to `try_new` doesn't exist
+    PackageDirNotFound(PathBuf),
+    /// A package didn't compile, with the error of the Scrypto compiler
+    /// (cargo's own output goes to the terminal)
+    CompilationFailed { package_dir: PathBuf, error: String },
+    /// Setting up the environment failed for another reason
+    SetupFailed(String),
+    /// No package has been published under this name
+    PackageNotFound(String),
+    /// A royalty is configured for a blueprint the package doesn't define
+    BlueprintNotFound {
+        package_name: String,
+        blueprint_name: String,
+    },
+    /// No resource is registered under this symbol
+    ResourceNotFound(String),
+    /// Reading or writing the disk cache failed
+    DiskCacheFailed { path: PathBuf, error: String },
+    /// The transaction was committed, but failed
+    TransactionFailed {
+        transaction_index: usize,
+        message: String,
+    },
+    /// The transaction was rejected (or aborted) and not committed
+    TransactionRejected {
+        transaction_index: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for TestEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestEnvError::PackageDirNotFound(package_dir) => {
+                write!(f, "Package directory {:?} not found", package_dir)
+            }
+            TestEnvError::CompilationFailed { package_dir, error } => {
+                write!(f, "Failed to compile package {:?}:\n{}", package_dir, error)
+            }
+            TestEnvError::SetupFailed(message) => {
+                write!(f, "Test environment setup failed: {}", message)
+            }
+            TestEnvError::PackageNotFound(package_name) => {
+                write!(f, "Package {:?} not found", package_name)
+            }
+            TestEnvError::BlueprintNotFound {
+                package_name,
+                blueprint_name,
+            } => write!(
+                f,
+                "Blueprint {:?} not found in package {:?}",
+                blueprint_name, package_name
+            ),
+            TestEnvError::ResourceNotFound(symbol) => {
+                write!(f, "Resource {:?} not found", symbol)
+            }
+            TestEnvError::DiskCacheFailed { path, error } => {
+                write!(f, "Disk cache {:?} failed: {}", path, error)
+            }
+            TestEnvError::TransactionFailed {
+                transaction_index,
+                message,
+            } => write!(f, "Transaction {} failed: {}", transaction_index, message),
+            TestEnvError::TransactionRejected {
+                transaction_index,
+                message,
+            } => write!(f, "Transaction {} rejected: {}", transaction_index, message),
+        }
+    }
+}
+
+impl std::error::Error for TestEnvError {}
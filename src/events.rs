@@ -0,0 +1,265 @@
+use radix_engine::transaction::{TransactionReceipt, TransactionResult};
+use scrypto::prelude::*;
+use std::fmt::{self, Debug};
+
+use crate::{Receipt, TestEnvironment};
+
+/// Application event emitted by a transaction of the environment history
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// Position of the emitting transaction in `TestEnvironment::history`
+    pub transaction_index: usize,
+    pub emitter: Emitter,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl RecordedEvent {
+    pub(crate) fn from_receipt(
+        receipt: &TransactionReceipt,
+        transaction_index: usize,
+    ) -> Vec<RecordedEvent> {
+        match &receipt.result {
+            TransactionResult::Commit(commit_result) => commit_result
+                .application_events
+                .iter()
+                .map(|(EventTypeIdentifier(emitter, name), data)| RecordedEvent {
+                    transaction_index,
+                    emitter: emitter.clone(),
+                    name: name.clone(),
+                    data: data.clone(),
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the event was emitted by a method of the given global entity
+    pub fn is_emitted_by(&self, address: GlobalAddress) -> bool {
+        matches!(&self.emitter, Emitter::Method(node_id, _) if node_id == address.as_node_id())
+    }
+
+    pub fn is<T: ScryptoEvent>(&self) -> bool {
+        self.name == T::EVENT_NAME
+    }
+
+    pub fn decode<T: ScryptoEvent + ScryptoDecode>(&self) -> T {
+        scrypto_decode(&self.data)
+            .unwrap_or_else(|_| panic!("Failed to decode event {}", self.name))
+    }
+}
+
+/// Describes an expected event by name and optionally emitter and field predicates
+pub struct EventMatcher {
+    name: String,
+    emitter: Option<GlobalAddress>,
+    predicates: Vec<Box<dyn Fn(&RecordedEvent) -> bool>>,
+}
+
+impl EventMatcher {
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            emitter: None,
+            predicates: vec![],
+        }
+    }
+
+    pub fn of<T: ScryptoEvent>() -> Self {
+        Self::named(T::EVENT_NAME)
+    }
+
+    pub fn from<A: Into<GlobalAddress>>(mut self, address: A) -> Self {
+        self.emitter = Some(address.into());
+        self
+    }
+
+    /// Additionally requires the decoded event to fulfill `predicate`,
+    /// e.g. `EventMatcher::of::<SwapEvent>().with(|event: &SwapEvent| event.input_amount == dec!(1))`
+    pub fn with<T, F>(mut self, predicate: F) -> Self
+    where
+        T: ScryptoEvent + ScryptoDecode,
+        F: Fn(&T) -> bool + 'static,
+    {
+        self.predicates.push(Box::new(move |event: &RecordedEvent| {
+            scrypto_decode::<T>(&event.data).is_ok_and(|event| predicate(&event))
+        }));
+        self
+    }
+
+    pub fn matches(&self, event: &RecordedEvent) -> bool {
+        let emitter_matches = match self.emitter {
+            Some(emitter) => event.is_emitted_by(emitter),
+            None => true,
+        };
+        event.name == self.name
+            && emitter_matches
+            && self.predicates.iter().all(|predicate| predicate(event))
+    }
+}
+
+impl Debug for EventMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EventMatcher({}, emitter: {:?}, {} predicates)",
+            self.name,
+            self.emitter,
+            self.predicates.len()
+        )
+    }
+}
+
+/// Asserts that events matching `sequence` occur in this order (other events may occur in between)
+fn assert_event_sequence(events: &[RecordedEvent], sequence: &[EventMatcher]) {
+    let mut remaining = events.iter();
+    for (position, matcher) in sequence.iter().enumerate() {
+        assert!(
+            remaining.any(|event| matcher.matches(event)),
+            "Event {:?} (position {} of the expected sequence) not found in order, emitted events: {:?}",
+            matcher,
+            position,
+            events.iter().map(|event| &event.name).collect::<Vec<_>>()
+        );
+    }
+}
+
+fn assert_event_count(events: &[RecordedEvent], matcher: &EventMatcher, count: usize) {
+    let actual = events.iter().filter(|event| matcher.matches(event)).count();
+    assert_eq!(
+        actual, count,
+        "Expected {} events matching {:?}, found {}",
+        count, matcher, actual
+    );
+}
+
+impl Receipt {
+    pub fn assert_event_sequence(&self, sequence: &[EventMatcher]) {
+        assert_event_sequence(&self.events(), sequence);
+    }
+
+    pub fn assert_event_count(&self, matcher: &EventMatcher, count: usize) {
+        assert_event_count(&self.events(), matcher, count);
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        RecordedEvent::from_receipt(&self.execution_receipt, self.transaction_index)
+    }
+
+    /// Events emitted by the given component or resource only, leaving out e.g. the
+    /// account and vault events of the same transaction
+    pub fn events_from<A: Into<GlobalAddress>>(&self, address: A) -> Vec<RecordedEvent> {
+        let address = address.into();
+        self.events()
+            .into_iter()
+            .filter(|event| event.is_emitted_by(address))
+            .collect()
+    }
+}
+
+impl TestEnvironment {
+    /// All events emitted by the transactions in the history, in order
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.history()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, transaction)| {
+                RecordedEvent::from_receipt(&transaction.receipt, index)
+            })
+            .collect()
+    }
+
+    pub fn events_named(&self, name: &str) -> Vec<RecordedEvent> {
+        self.events()
+            .into_iter()
+            .filter(|event| event.name == name)
+            .collect()
+    }
+
+    pub fn events_emitted_by<A: Into<GlobalAddress>>(&self, address: A) -> Vec<RecordedEvent> {
+        let address = address.into();
+        self.events()
+            .into_iter()
+            .filter(|event| event.is_emitted_by(address))
+            .collect()
+    }
+
+    /// Same as `Receipt::assert_event_sequence`, but across all transactions of the history
+    pub fn assert_event_sequence(&self, sequence: &[EventMatcher]) {
+        assert_event_sequence(&self.events(), sequence);
+    }
+
+    pub fn assert_event_count(&self, matcher: &EventMatcher, count: usize) {
+        assert_event_count(&self.events(), matcher, count);
+    }
+
+    /// Decoded events of type `T` across the history, e.g. to assert on all `SwapEvent`s of a scenario
+    pub fn events_of_type<T: ScryptoEvent + ScryptoDecode>(&self) -> Vec<T> {
+        self.events()
+            .iter()
+            .filter(|event| event.is::<T>())
+            .map(|event| event.decode())
+            .collect()
+    }
+}
+
+#[test]
+fn test_events() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+
+    test_environment.execute_expect_success(false);
+    let events_per_transaction = test_environment.events().len();
+    test_environment.execute_expect_success(false);
+
+    let events = test_environment.events();
+    assert!(events_per_transaction > 0);
+    assert_eq!(events.len(), 2 * events_per_transaction);
+    assert_eq!(events.last().unwrap().transaction_index, 1);
+    assert_eq!(test_environment.events_named(&events[0].name).len() % 2, 0);
+}
+
+#[test]
+fn test_events_from() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, u_address) = (test_environment.account, test_environment.u_address());
+    let admin_badge_address = test_environment.admin_badge_address;
+    test_environment.add_instructions("mint", |builder| {
+        builder
+            .create_proof_from_account_of_amount(account, admin_badge_address, dec!(1))
+            .mint_fungible(u_address, dec!(10))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let events = receipt.events_from(u_address);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "MintFungibleResourceEvent");
+    assert!(receipt.events().len() > events.len());
+}
+
+#[test]
+fn test_assert_event_sequence() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let u_address = test_environment.u_address();
+    test_environment.present_admin_badge();
+    test_environment.add_instructions("mint", |builder| builder.mint_fungible(u_address, dec!(10)));
+    test_environment.add_instructions("mint_more", |builder| {
+        builder.mint_fungible(u_address, dec!(5))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    let mint = || EventMatcher::named("MintFungibleResourceEvent").from(u_address);
+    receipt.assert_event_sequence(&[mint(), mint()]);
+    receipt.assert_event_count(&mint(), 2);
+    test_environment.assert_event_count(&mint(), 2);
+}
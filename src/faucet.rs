@@ -0,0 +1,52 @@
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+// The faucet hands out XRD only once per transaction, so at most one of
+// `get_free_xrd` and `call_with_free_xrd` can be used per manifest
+impl TestEnvironment {
+    /// Adds a call of the faucet's `free` method (10000 XRD) tracked under `label`.
+    /// The XRD stays on the worktop and ends up in the active account unless taken before.
+    pub fn get_free_xrd(&mut self, label: &str) -> &mut Self {
+        self.add_instructions(label, |builder| builder.get_free_xrd_from_faucet())
+    }
+
+    /// Adds a lock of `amount` fee from the faucet tracked under `label`, on top of the
+    /// fee locked by the configured fee payer
+    pub fn lock_fee_from_faucet(&mut self, label: &str, amount: Decimal) -> &mut Self {
+        self.add_instructions(label, |builder| builder.lock_fee(FAUCET, amount))
+    }
+
+    /// Same as `call_with_bucket` but injects a bucket of `amount` XRD from the faucet
+    /// (at most 10000 XRD), leaving the balances of the environment accounts untouched
+    pub fn call_with_free_xrd<F>(&mut self, label: &str, amount: Decimal, call: F) -> &mut Self
+    where
+        F: FnOnce(ManifestBuilder, ManifestBucket) -> ManifestBuilder,
+    {
+        let bucket_name = self.name("free_xrd_bucket");
+        self.add_instructions(label, |builder| {
+            builder
+                .get_free_xrd_from_faucet()
+                .take_from_worktop(XRD, amount, &bucket_name)
+                .with_name_lookup(|builder, lookup| call(builder, lookup.bucket(&bucket_name)))
+        })
+    }
+}
+
+#[test]
+fn test_call_with_free_xrd() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let bob = test_environment.new_account("bob");
+
+    test_environment.lock_fee_from_faucet("lock_fee", dec!(10));
+    test_environment.call_with_free_xrd("fund_bob", dec!(100), |builder, bucket| {
+        builder.try_deposit_or_abort(bob, None, bucket)
+    });
+    let receipt = test_environment.execute_expect_success(false);
+
+    assert_eq!(receipt.balance_changes().fungible(bob, XRD), dec!(100));
+}
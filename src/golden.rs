@@ -0,0 +1,195 @@
+use radix_engine::transaction::{BalanceChange, TransactionOutcome, TransactionResult};
+use scrypto::prelude::*;
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::Receipt;
+
+/// Set to `1` to regenerate the baselines instead of comparing against them
+pub const UPDATE_GOLDEN_ENV: &str = "TESTENV_UPDATE_GOLDEN";
+
+/// Maps addresses to the environment's stable labels, numbering unknown (newly created)
+/// addresses in order of appearance. Addresses with several labels (e.g. `A` and `X`) get the
+/// alphabetically first one.
+pub(crate) struct AddressLabeler {
+    known: HashMap<GlobalAddress, String>,
+    new: Vec<GlobalAddress>,
+}
+
+impl AddressLabeler {
+    pub(crate) fn new(address_labels: &[(String, GlobalAddress)]) -> Self {
+        let mut address_labels = address_labels.to_vec();
+        address_labels.sort_by(|(label, _), (other_label, _)| label.cmp(other_label));
+        let mut known = HashMap::new();
+        for (label, address) in address_labels {
+            known.entry(address).or_insert(label);
+        }
+        Self { known, new: vec![] }
+    }
+
+    pub(crate) fn label(&mut self, address: GlobalAddress) -> String {
+        if let Some(label) = self.known.get(&address) {
+            return label.clone();
+        }
+        let index = match self.new.iter().position(|&new| new == address) {
+            Some(index) => index,
+            None => {
+                self.new.push(address);
+                self.new.len() - 1
+            }
+        };
+        format!("new:{}", index)
+    }
+
+    pub(crate) fn label_node(&mut self, node_id: &NodeId) -> String {
+        match node_id.is_global() {
+            true => self.label(GlobalAddress::new_or_panic(node_id.0)),
+            false => "internal".to_string(),
+        }
+    }
+}
+
+impl Receipt {
+    pub(crate) fn outcome(&self) -> String {
+        match &self.execution_receipt.result {
+            TransactionResult::Commit(commit_result) => match &commit_result.outcome {
+                TransactionOutcome::Success(_) => "success".to_string(),
+                TransactionOutcome::Failure(error) => format!("failure: {:?}", error),
+            },
+            TransactionResult::Reject(_) => "rejected".to_string(),
+            TransactionResult::Abort(_) => "aborted".to_string(),
+        }
+    }
+
+    /// Emitted events as `<emitter>::<event name>`
+    pub(crate) fn labeled_events(&self, labeler: &mut AddressLabeler) -> Vec<String> {
+        let commit_result = match &self.execution_receipt.result {
+            TransactionResult::Commit(commit_result) => commit_result,
+            _ => return vec![],
+        };
+        commit_result
+            .application_events
+            .iter()
+            .map(|(EventTypeIdentifier(emitter, name), _)| {
+                let emitter = match emitter {
+                    Emitter::Method(node_id, _) => labeler.label_node(node_id),
+                    Emitter::Function(blueprint_id) => blueprint_id.blueprint_name.clone(),
+                };
+                format!("{}::{}", emitter, name)
+            })
+            .collect()
+    }
+
+    /// Normalized representation of the execution compared by `assert_matches_golden`:
+    /// outcome, cost units (only charged if committed), balance changes and emitted events
+    /// with addresses replaced by labels
+    pub fn golden(&self) -> Value {
+        let mut labeler = AddressLabeler::new(&self.address_labels);
+        let outcome = self.outcome();
+        let cost_units = match &self.execution_receipt.result {
+            TransactionResult::Commit(_) => json!(self.total_cost_units()),
+            _ => Value::Null,
+        };
+        let events = self.labeled_events(&mut labeler);
+        let mut balance_changes: BTreeMap<String, BTreeMap<String, Value>> = BTreeMap::new();
+        // Label new addresses in address order instead of the hash map order of the changes
+        let mut accounts: Vec<_> = self.balance_changes().resources.iter().collect();
+        accounts.sort_by_key(|(account, _)| account.as_node_id().0);
+        for (account, changes) in accounts {
+            let account = labeler.label((*account).into());
+            let mut changes: Vec<_> = changes.iter().collect();
+            changes.sort_by_key(|(resource, _)| resource.as_node_id().0);
+            for (resource, change) in changes {
+                let change = match change {
+                    BalanceChange::Fungible(delta) => json!(delta.to_string()),
+                    BalanceChange::NonFungible { added, removed } => json!({
+                        "added": added.iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+                        "removed": removed.iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+                    }),
+                };
+                balance_changes
+                    .entry(account.clone())
+                    .or_default()
+                    .insert(labeler.label((*resource).into()), change);
+            }
+        }
+        json!({
+            "outcome": outcome,
+            "cost_units": cost_units,
+            "balance_changes": balance_changes,
+            "events": events,
+        })
+    }
+
+    /// Compares the normalized receipt against the baseline stored at `path`.
+    /// Run with `TESTENV_UPDATE_GOLDEN=1` to (re)generate the baseline.
+    pub fn assert_matches_golden<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        let golden = self.golden();
+        if std::env::var(UPDATE_GOLDEN_ENV).as_deref() == Ok("1") {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).unwrap();
+            }
+            std::fs::write(path, serde_json::to_string_pretty(&golden).unwrap() + "\n")
+                .unwrap_or_else(|_| panic!("Failed to write golden receipt {:?}", path));
+            return;
+        }
+        let baseline = std::fs::read_to_string(path).unwrap_or_else(|_| {
+            panic!(
+                "Golden receipt {:?} not found, run with {}=1 to create it",
+                path, UPDATE_GOLDEN_ENV
+            )
+        });
+        let baseline: Value = serde_json::from_str(&baseline)
+            .unwrap_or_else(|_| panic!("Invalid golden receipt {:?}", path));
+        assert!(
+            golden == baseline,
+            "Receipt does not match golden receipt {:?} (run with {}=1 to update)\nexpected: {}\nactual: {}",
+            path,
+            UPDATE_GOLDEN_ENV,
+            serde_json::to_string_pretty(&baseline).unwrap(),
+            serde_json::to_string_pretty(&golden).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_golden() {
+    use crate::{TestEnvironment, TestHelperExecution};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, a_address) = (test_environment.account, test_environment.a_address);
+    let trader = test_environment.new_account("trader");
+    test_environment.add_instructions("transfer", |builder| {
+        builder
+            .withdraw_from_account(account, a_address, dec!(5))
+            .try_deposit_entire_worktop_or_abort(trader, None)
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+    let golden = receipt.golden();
+
+    assert_eq!(golden["outcome"], json!("success"));
+    assert_eq!(golden["balance_changes"]["account"]["A"], json!("-5"));
+    assert_eq!(golden["balance_changes"]["account:trader"]["A"], json!("5"));
+    assert_eq!(golden, receipt.golden());
+}
+
+#[test]
+fn test_golden_rejected() {
+    use crate::{TestEnvironment, TestHelperExecution};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let epoch = test_environment.current_epoch();
+
+    // Not yet valid, so rejected without any execution
+    let receipt = test_environment.execute_with_epoch_range(
+        epoch.after(10).unwrap(),
+        epoch.after(20).unwrap(),
+        false,
+    );
+
+    receipt.assert_matches_golden("testdata/golden_rejected.json");
+}
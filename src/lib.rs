@@ -1,5 +1,52 @@
+pub mod access_controller;
+pub mod auth;
+pub mod bench;
+pub mod cache;
+mod compiler;
 pub mod constants;
 pub mod environment;
+pub mod environment_pool;
+pub mod error;
+pub mod events;
+pub mod faucet;
+pub mod golden;
+pub mod locker;
+pub mod metadata;
+pub mod native_pools;
+pub mod persistence;
+pub mod presets;
+pub mod royalties;
+pub mod schema;
+pub mod shared;
+pub mod staking;
+pub mod state;
+pub mod stubs;
+pub mod summary;
+pub mod time;
+pub mod timings;
+mod workspace;
 
+pub use access_controller::*;
+pub use auth::*;
+pub use bench::*;
 pub use constants::*;
 pub use environment::*;
+pub use environment_pool::*;
+pub use error::*;
+pub use events::*;
+pub use faucet::*;
+pub use golden::*;
+pub use locker::*;
+pub use metadata::*;
+pub use native_pools::*;
+pub use persistence::*;
+pub use presets::*;
+pub use royalties::*;
+pub use schema::*;
+pub use shared::*;
+pub use staking::*;
+pub use state::*;
+pub use stubs::*;
+pub use summary::*;
+pub use time::*;
+pub use timings::*;
@@ -0,0 +1,147 @@
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+impl TestEnvironment {
+    /// Instantiates an `AccountLocker` whose owner, storer and recoverer is the admin badge
+    pub fn create_account_locker(&mut self) -> ComponentAddress {
+        let admin_rule = rule!(require(self.admin_badge_address));
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(
+                LOCKER_PACKAGE,
+                ACCOUNT_LOCKER_BLUEPRINT,
+                ACCOUNT_LOCKER_INSTANTIATE_IDENT,
+                AccountLockerInstantiateManifestInput {
+                    owner_role: OwnerRole::Updatable(admin_rule.clone()),
+                    storer_role: admin_rule.clone(),
+                    storer_updater_role: admin_rule.clone(),
+                    recoverer_role: admin_rule.clone(),
+                    recoverer_updater_role: admin_rule,
+                    address_reservation: None,
+                },
+            )
+            .build();
+        self.test_runner
+            .execute_manifest(manifest, vec![])
+            .expect_commit_success()
+            .new_component_addresses()[0]
+    }
+
+    /// Stores `amount` of `resource` of the main account in `locker` for `claimant`,
+    /// tracked under `label`. Presents the admin badge as storer.
+    pub fn store_in_locker(
+        &mut self,
+        label: &str,
+        locker: ComponentAddress,
+        claimant: ComponentAddress,
+        resource: ResourceAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        self.present_admin_badge();
+        self.call_with_account_bucket(label, resource, amount, |builder, bucket| {
+            builder.call_method(
+                locker,
+                ACCOUNT_LOCKER_STORE_IDENT,
+                manifest_args!(claimant, bucket, false),
+            )
+        })
+    }
+
+    /// Stores the given amounts of `resource` of the main account in `locker` for each
+    /// claimant at once, tracked under `label`. Presents the admin badge as storer.
+    pub fn airdrop_to_locker(
+        &mut self,
+        label: &str,
+        locker: ComponentAddress,
+        resource: ResourceAddress,
+        amounts: Vec<(ComponentAddress, Decimal)>,
+    ) -> &mut Self {
+        let total = amounts
+            .iter()
+            .fold(Decimal::ZERO, |total, (_, amount)| total + *amount);
+        let claimants: IndexMap<ComponentAddress, ResourceSpecifier> = amounts
+            .into_iter()
+            .map(|(claimant, amount)| (claimant, ResourceSpecifier::Fungible(amount)))
+            .collect();
+        self.present_admin_badge();
+        self.call_with_account_bucket(label, resource, total, |builder, bucket| {
+            builder.call_method(
+                locker,
+                ACCOUNT_LOCKER_AIRDROP_IDENT,
+                manifest_args!(claimants, bucket, false),
+            )
+        })
+    }
+
+    /// Claims `amount` of `resource` stored for `claimant`, tracked under `label`.
+    /// The claimant has to sign, so make it the active account first:
+    /// ```ignore
+    /// test_environment.set_active_account("user");
+    /// test_environment.claim_from_locker("claim", locker, user, x_address, dec!(5));
+    /// ```
+    pub fn claim_from_locker(
+        &mut self,
+        label: &str,
+        locker: ComponentAddress,
+        claimant: ComponentAddress,
+        resource: ResourceAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        self.add_instructions(label, |builder| {
+            builder.call_method(
+                locker,
+                ACCOUNT_LOCKER_CLAIM_IDENT,
+                manifest_args!(claimant, resource, amount),
+            )
+        })
+    }
+
+    /// Same as `claim_from_locker` but claims the non fungibles `ids`
+    pub fn claim_non_fungibles_from_locker(
+        &mut self,
+        label: &str,
+        locker: ComponentAddress,
+        claimant: ComponentAddress,
+        resource: ResourceAddress,
+        ids: IndexSet<NonFungibleLocalId>,
+    ) -> &mut Self {
+        self.add_instructions(label, |builder| {
+            builder.call_method(
+                locker,
+                ACCOUNT_LOCKER_CLAIM_NON_FUNGIBLES_IDENT,
+                manifest_args!(claimant, resource, ids),
+            )
+        })
+    }
+}
+
+#[test]
+fn test_account_locker() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let x_address = test_environment.x_address;
+    let locker = test_environment.create_account_locker();
+    let alice = test_environment.new_account("alice");
+    let bob = test_environment.new_account("bob");
+
+    test_environment.store_in_locker("store", locker, alice, x_address, dec!(5));
+    test_environment.airdrop_to_locker(
+        "airdrop",
+        locker,
+        x_address,
+        vec![(alice, dec!(1)), (bob, dec!(2))],
+    );
+    test_environment.execute_expect_success(false);
+
+    test_environment.set_active_account("alice");
+    test_environment.claim_from_locker("claim", locker, alice, x_address, dec!(6));
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt.balance_changes().fungible(alice, x_address),
+        dec!(6)
+    );
+}
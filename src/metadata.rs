@@ -0,0 +1,405 @@
+use radix_engine::system::system_substates::KeyValueEntrySubstate;
+use radix_substate_store_interface::{
+    db_key_mapper::SpreadPrefixKeyMapper, interface::SubstateDatabase,
+};
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+/// Metadata keys defined for resources by the Radix metadata standard with their expected value type
+pub const RESOURCE_METADATA_STANDARD: [(&str, MetadataType); 6] = [
+    ("name", MetadataType::String),
+    ("symbol", MetadataType::String),
+    ("description", MetadataType::String),
+    ("tags", MetadataType::StringArray),
+    ("icon_url", MetadataType::Url),
+    ("info_url", MetadataType::Url),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataType {
+    String,
+    StringArray,
+    Url,
+}
+
+impl MetadataType {
+    fn matches(&self, value: &MetadataValue) -> bool {
+        matches!(
+            (self, value),
+            (MetadataType::String, MetadataValue::String(_))
+                | (MetadataType::StringArray, MetadataValue::StringArray(_))
+                | (MetadataType::Url, MetadataValue::Url(_))
+        )
+    }
+}
+
+/// Which of the standard keys have to be present and locked
+#[derive(Debug, Clone)]
+pub struct MetadataRequirements {
+    pub required_keys: Vec<String>,
+    pub locked_keys: Vec<String>,
+}
+
+impl Default for MetadataRequirements {
+    /// Wallets need at least a name and a symbol to display a token properly
+    fn default() -> Self {
+        Self {
+            required_keys: vec!["name".to_string(), "symbol".to_string()],
+            locked_keys: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataViolation {
+    Missing {
+        key: String,
+    },
+    WrongType {
+        key: String,
+        expected: MetadataType,
+        actual: MetadataValue,
+    },
+    NotLocked {
+        key: String,
+    },
+    InvalidSymbol {
+        symbol: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DappDefinitionViolation {
+    /// `account_type` is not set to "dapp definition"
+    NotDappDefinition,
+    /// `claimed_entities` is missing or not a list of addresses
+    MissingClaimedEntities,
+    /// A claimed entity does not point back via its `dapp_definition(s)` metadata
+    NotLinkedBack { entity: GlobalAddress },
+}
+
+impl TestEnvironment {
+    /// Returns the metadata value stored under `key` and whether the entry is locked
+    pub fn metadata_entry(
+        &self,
+        address: GlobalAddress,
+        key: &str,
+    ) -> Option<(MetadataValue, bool)> {
+        let entry = self
+            .test_runner
+            .substate_db()
+            .get_mapped::<SpreadPrefixKeyMapper, KeyValueEntrySubstate<MetadataValue>>(
+                address.as_node_id(),
+                METADATA_BASE_PARTITION,
+                &SubstateKey::Map(scrypto_encode(key).unwrap()),
+            )?;
+        let locked = entry.is_locked();
+        entry.into_value().map(|value| (value, locked))
+    }
+
+    /// Sets the metadata entry `key` of `entity`, presenting the admin badge and signing with
+    /// the main account (and with `entity` itself if it is an account created by the environment)
+    pub fn set_metadata<V: ToMetadataEntry>(&mut self, entity: GlobalAddress, key: &str, value: V) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .set_metadata(entity, key, value)
+            .build();
+        let mut signers = vec![self.account];
+        if let Ok(component) = ComponentAddress::try_from(entity.as_node_id().as_bytes()) {
+            if component != self.account && self.public_key_of(component).is_some() {
+                signers.push(component);
+            }
+        }
+        let initial_proofs = self.signatures(&signers);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    pub fn assert_metadata_eq<V: ToMetadataEntry>(
+        &self,
+        entity: GlobalAddress,
+        key: &str,
+        expected: V,
+    ) {
+        let actual = self.metadata_entry(entity, key).map(|(value, _)| value);
+        assert_eq!(
+            actual,
+            expected.to_metadata_entry(),
+            "Unexpected metadata {:?} of {:?}",
+            key,
+            entity
+        );
+    }
+
+    /// Checks the metadata of `resource` against the Radix metadata standard and
+    /// returns all violations found (empty if compliant)
+    pub fn check_resource_metadata(
+        &self,
+        resource: ResourceAddress,
+        requirements: &MetadataRequirements,
+    ) -> Vec<MetadataViolation> {
+        let mut violations = vec![];
+        for (key, expected) in RESOURCE_METADATA_STANDARD {
+            let required = requirements.required_keys.iter().any(|k| k == key);
+            let must_be_locked = requirements.locked_keys.iter().any(|k| k == key);
+            let (value, locked) = match self.metadata_entry(resource.into(), key) {
+                Some(entry) => entry,
+                None => {
+                    if required {
+                        violations.push(MetadataViolation::Missing {
+                            key: key.to_string(),
+                        });
+                    }
+                    continue;
+                }
+            };
+            if !expected.matches(&value) {
+                violations.push(MetadataViolation::WrongType {
+                    key: key.to_string(),
+                    expected,
+                    actual: value.clone(),
+                });
+            }
+            if must_be_locked && !locked {
+                violations.push(MetadataViolation::NotLocked {
+                    key: key.to_string(),
+                });
+            }
+            if let MetadataValue::String(symbol) = &value {
+                if key == "symbol" && (symbol.is_empty() || symbol.contains(char::is_whitespace)) {
+                    violations.push(MetadataViolation::InvalidSymbol {
+                        symbol: symbol.clone(),
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    /// Walks the `claimed_entities` of a dapp definition account and verifies that every
+    /// claimed entity links back via its `dapp_definition` (components, packages) or
+    /// `dapp_definitions` (resources) metadata
+    pub fn verify_dapp_definition(
+        &self,
+        dapp_definition: ComponentAddress,
+    ) -> Vec<DappDefinitionViolation> {
+        let mut violations = vec![];
+        let dapp_definition: GlobalAddress = dapp_definition.into();
+        match self.metadata_entry(dapp_definition, "account_type") {
+            Some((MetadataValue::String(account_type), _)) if account_type == "dapp definition" => {
+            }
+            _ => violations.push(DappDefinitionViolation::NotDappDefinition),
+        }
+        let claimed_entities = match self.metadata_entry(dapp_definition, "claimed_entities") {
+            Some((MetadataValue::GlobalAddressArray(claimed_entities), _)) => claimed_entities,
+            _ => {
+                violations.push(DappDefinitionViolation::MissingClaimedEntities);
+                return violations;
+            }
+        };
+        for entity in claimed_entities {
+            if !self.links_back(entity, dapp_definition) {
+                violations.push(DappDefinitionViolation::NotLinkedBack { entity });
+            }
+        }
+        violations
+    }
+
+    fn links_back(&self, entity: GlobalAddress, dapp_definition: GlobalAddress) -> bool {
+        match self.metadata_entry(entity, "dapp_definition") {
+            Some((MetadataValue::GlobalAddress(address), _)) => address == dapp_definition,
+            _ => match self.metadata_entry(entity, "dapp_definitions") {
+                Some((MetadataValue::GlobalAddressArray(addresses), _)) => {
+                    addresses.contains(&dapp_definition)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Configures the environment's `dapp_definition` account per the metadata standard,
+    /// claiming `claimed_entities` (which have to link back) and `claimed_websites`
+    pub fn configure_dapp_definition(
+        &mut self,
+        name: &str,
+        claimed_entities: Vec<GlobalAddress>,
+        claimed_websites: Vec<&str>,
+    ) {
+        let claimed_websites: Vec<UncheckedOrigin> = claimed_websites
+            .into_iter()
+            .map(UncheckedOrigin::of)
+            .collect();
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .set_metadata(self.dapp_definition, "account_type", "dapp definition")
+            .set_metadata(self.dapp_definition, "name", name)
+            .set_metadata(self.dapp_definition, "claimed_entities", claimed_entities)
+            .set_metadata(self.dapp_definition, "claimed_websites", claimed_websites)
+            .build();
+        let initial_proofs = self.signatures(&[self.dapp_definition]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Asserts that `entity` and the environment's `dapp_definition` point to each other
+    pub fn assert_two_way_linked(&self, entity: GlobalAddress) {
+        let dapp_definition: GlobalAddress = self.dapp_definition.into();
+        assert!(
+            self.links_back(entity, dapp_definition),
+            "{:?} does not link to the dapp definition {:?}",
+            entity,
+            dapp_definition
+        );
+        let claimed = match self.metadata_entry(dapp_definition, "claimed_entities") {
+            Some((MetadataValue::GlobalAddressArray(claimed_entities), _)) => {
+                claimed_entities.contains(&entity)
+            }
+            _ => false,
+        };
+        assert!(
+            claimed,
+            "The dapp definition {:?} does not claim {:?}",
+            dapp_definition, entity
+        );
+    }
+
+    pub fn assert_dapp_definition_linked(&self, dapp_definition: ComponentAddress) {
+        let violations = self.verify_dapp_definition(dapp_definition);
+        assert!(
+            violations.is_empty(),
+            "Dapp definition {:?} is not linked correctly: {:?}",
+            dapp_definition,
+            violations
+        );
+    }
+
+    pub fn assert_resource_metadata_compliant(
+        &self,
+        resource: ResourceAddress,
+        requirements: &MetadataRequirements,
+    ) {
+        let violations = self.check_resource_metadata(resource, requirements);
+        assert!(
+            violations.is_empty(),
+            "Resource {:?} violates the metadata standard: {:?}",
+            resource,
+            violations
+        );
+    }
+}
+
+#[test]
+fn test_check_resource_metadata() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let u_address = test_environment.u_address();
+    let requirements = MetadataRequirements {
+        locked_keys: vec!["name".to_string(), "symbol".to_string()],
+        ..Default::default()
+    };
+
+    test_environment.assert_resource_metadata_compliant(test_environment.a_address, &requirements);
+    assert_eq!(
+        test_environment.check_resource_metadata(u_address, &requirements),
+        vec![
+            MetadataViolation::Missing {
+                key: "name".to_string()
+            },
+            MetadataViolation::Missing {
+                key: "symbol".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_set_metadata() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let admin_badge_address = test_environment.admin_badge_address;
+    let resource = test_environment.create_resource(
+        "M",
+        dec!(100),
+        DIVISIBILITY_MAXIMUM,
+        metadata! {
+            roles {
+                metadata_setter => rule!(require(admin_badge_address));
+                metadata_setter_updater => rule!(deny_all);
+                metadata_locker => rule!(deny_all);
+                metadata_locker_updater => rule!(deny_all);
+            },
+            init {
+                "name" => "Before".to_owned(), updatable;
+            }
+        },
+    );
+    test_environment.assert_metadata_eq(resource.into(), "name", "Before".to_owned());
+
+    test_environment.set_metadata(resource.into(), "name", "After".to_owned());
+    test_environment.set_metadata(resource.into(), "tags", vec!["defi".to_owned()]);
+
+    test_environment.assert_metadata_eq(resource.into(), "name", "After".to_owned());
+    test_environment.assert_metadata_eq(resource.into(), "tags", vec!["defi".to_owned()]);
+}
+
+#[test]
+fn test_configure_dapp_definition() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let dapp_definition: GlobalAddress = test_environment.dapp_definition.into();
+    let resource = test_environment.create_resource(
+        "L",
+        dec!(100),
+        DIVISIBILITY_MAXIMUM,
+        metadata! {
+            init {
+                "dapp_definitions" => vec![dapp_definition], locked;
+            }
+        },
+    );
+
+    test_environment.configure_dapp_definition(
+        "Test dApp",
+        vec![resource.into()],
+        vec!["https://example.com"],
+    );
+
+    test_environment.assert_two_way_linked(resource.into());
+    test_environment.assert_dapp_definition_linked(test_environment.dapp_definition);
+    test_environment.assert_metadata_eq(dapp_definition, "name", "Test dApp");
+}
+
+#[test]
+fn test_verify_dapp_definition() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let dapp_definition = test_environment.dapp_definition;
+    assert_eq!(
+        test_environment.verify_dapp_definition(dapp_definition),
+        vec![
+            DappDefinitionViolation::NotDappDefinition,
+            DappDefinitionViolation::MissingClaimedEntities,
+        ]
+    );
+    // Claimed by the dapp definition, but not pointing back to it
+    let resource = test_environment.create_resource(
+        "N",
+        dec!(100),
+        DIVISIBILITY_MAXIMUM,
+        ModuleConfig::default(),
+    );
+
+    test_environment.configure_dapp_definition("Test dApp", vec![resource.into()], vec![]);
+
+    assert_eq!(
+        test_environment.verify_dapp_definition(dapp_definition),
+        vec![DappDefinitionViolation::NotLinkedBack {
+            entity: resource.into()
+        }]
+    );
+}
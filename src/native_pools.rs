@@ -0,0 +1,299 @@
+use radix_engine::system::system_db_reader::SystemDatabaseReader;
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::{TestEnvironment, TestHelperExecution};
+
+impl TestEnvironment {
+    /// Instantiates a native `OneResourcePool` of `resource`, managed by the admin badge
+    pub fn create_one_resource_pool(&mut self, resource: ResourceAddress) -> ComponentAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(
+                POOL_PACKAGE,
+                ONE_RESOURCE_POOL_BLUEPRINT_IDENT,
+                ONE_RESOURCE_POOL_INSTANTIATE_IDENT,
+                OneResourcePoolInstantiateManifestInput {
+                    owner_role: self.pool_owner_role(),
+                    pool_manager_rule: rule!(require(self.admin_badge_address)),
+                    resource_address: resource,
+                    address_reservation: None,
+                },
+            )
+            .build();
+        self.instantiate_native_pool(manifest)
+    }
+
+    /// Instantiates a native `TwoResourcePool` of `resources`, managed by the admin badge
+    pub fn create_two_resource_pool(
+        &mut self,
+        resources: (ResourceAddress, ResourceAddress),
+    ) -> ComponentAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(
+                POOL_PACKAGE,
+                TWO_RESOURCE_POOL_BLUEPRINT_IDENT,
+                TWO_RESOURCE_POOL_INSTANTIATE_IDENT,
+                TwoResourcePoolInstantiateManifestInput {
+                    owner_role: self.pool_owner_role(),
+                    pool_manager_rule: rule!(require(self.admin_badge_address)),
+                    resource_addresses: resources,
+                    address_reservation: None,
+                },
+            )
+            .build();
+        self.instantiate_native_pool(manifest)
+    }
+
+    /// Instantiates a native `MultiResourcePool` of `resources`, managed by the admin badge
+    pub fn create_multi_resource_pool(
+        &mut self,
+        resources: Vec<ResourceAddress>,
+    ) -> ComponentAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(
+                POOL_PACKAGE,
+                MULTI_RESOURCE_POOL_BLUEPRINT_IDENT,
+                MULTI_RESOURCE_POOL_INSTANTIATE_IDENT,
+                MultiResourcePoolInstantiateManifestInput {
+                    owner_role: self.pool_owner_role(),
+                    pool_manager_rule: rule!(require(self.admin_badge_address)),
+                    resource_addresses: resources.into_iter().collect(),
+                    address_reservation: None,
+                },
+            )
+            .build();
+        self.instantiate_native_pool(manifest)
+    }
+
+    fn pool_owner_role(&self) -> OwnerRole {
+        OwnerRole::Updatable(rule!(require(self.admin_badge_address)))
+    }
+
+    fn instantiate_native_pool(&mut self, manifest: TransactionManifestV1) -> ComponentAddress {
+        self.test_runner
+            .execute_manifest(manifest, vec![])
+            .expect_commit_success()
+            .new_component_addresses()[0]
+    }
+
+    /// Pool units minted by a native pool
+    pub fn pool_unit_resource(&mut self, pool: ComponentAddress) -> ResourceAddress {
+        match self.test_runner.get_metadata(pool.into(), "pool_unit") {
+            Some(MetadataValue::GlobalAddress(address)) => {
+                ResourceAddress::try_from(address.as_node_id().as_bytes())
+                    .expect("Invalid pool unit address")
+            }
+            _ => panic!("{:?} is not a native pool", pool),
+        }
+    }
+
+    fn native_pool_blueprint(&mut self, pool: ComponentAddress) -> String {
+        SystemDatabaseReader::new(self.test_runner.substate_db())
+            .get_object_info(pool)
+            .unwrap_or_else(|_| panic!("{:?} is not a native pool", pool))
+            .blueprint_info
+            .blueprint_id
+            .blueprint_name
+    }
+
+    /// Contributes `amounts` from the main account to a native pool, tracked under `label`
+    /// (pool units and change are the output buckets). Contributing requires the admin badge,
+    /// which is presented automatically. The resources have to be in the order of the pool.
+    pub fn contribute(
+        &mut self,
+        label: &str,
+        pool: ComponentAddress,
+        amounts: Vec<(ResourceAddress, Decimal)>,
+    ) -> &mut Self {
+        let blueprint = self.native_pool_blueprint(pool);
+        let bucket_names: Vec<String> = (0..amounts.len())
+            .map(|index| self.name(&format!("contribution_{}", index)))
+            .collect();
+        let (account, admin_badge_address) = (self.account, self.admin_badge_address);
+        self.add_instructions(label, |builder| {
+            let mut builder =
+                builder.create_proof_from_account_of_amount(account, admin_badge_address, dec!(1));
+            for ((resource, amount), bucket_name) in amounts.iter().zip(&bucket_names) {
+                builder = builder
+                    .withdraw_from_account(account, *resource, *amount)
+                    .take_from_worktop(*resource, *amount, bucket_name);
+            }
+            builder.with_name_lookup(|builder, lookup| {
+                let buckets: Vec<ManifestBucket> = bucket_names
+                    .iter()
+                    .map(|bucket_name| lookup.bucket(bucket_name))
+                    .collect();
+                let arguments = match blueprint.as_str() {
+                    ONE_RESOURCE_POOL_BLUEPRINT_IDENT => manifest_args!(buckets[0]),
+                    TWO_RESOURCE_POOL_BLUEPRINT_IDENT => manifest_args!((buckets[0], buckets[1])),
+                    _ => manifest_args!(buckets),
+                };
+                builder.call_method(pool, "contribute", arguments)
+            })
+        })
+    }
+
+    /// Redeems `amount` pool units of the main account, tracked under `label`
+    pub fn redeem(&mut self, label: &str, pool: ComponentAddress, amount: Decimal) -> &mut Self {
+        let pool_unit_resource = self.pool_unit_resource(pool);
+        self.call_with_account_bucket(label, pool_unit_resource, amount, |builder, pool_units| {
+            builder.call_method(pool, "redeem", manifest_args!(pool_units))
+        })
+    }
+
+    /// Deposits `amount` of `resource` into a native pool without minting pool units,
+    /// e.g. to simulate collected fees. Presents the admin badge.
+    pub fn protected_deposit(
+        &mut self,
+        label: &str,
+        pool: ComponentAddress,
+        resource: ResourceAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        let bucket_name = self.name("protected_deposit");
+        let (account, admin_badge_address) = (self.account, self.admin_badge_address);
+        self.add_instructions(label, |builder| {
+            builder
+                .create_proof_from_account_of_amount(account, admin_badge_address, dec!(1))
+                .withdraw_from_account(account, resource, amount)
+                .take_from_worktop(resource, amount, &bucket_name)
+                .with_name_lookup(|builder, lookup| {
+                    let bucket = lookup.bucket(&bucket_name);
+                    builder.call_method(pool, "protected_deposit", manifest_args!(bucket))
+                })
+        })
+    }
+
+    /// Withdraws exactly `amount` of `resource` from a native pool without burning pool units,
+    /// tracked under `label`. Presents the admin badge.
+    pub fn protected_withdraw(
+        &mut self,
+        label: &str,
+        pool: ComponentAddress,
+        resource: ResourceAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        let blueprint = self.native_pool_blueprint(pool);
+        let arguments = match blueprint.as_str() {
+            ONE_RESOURCE_POOL_BLUEPRINT_IDENT => manifest_args!(amount, WithdrawStrategy::Exact),
+            _ => manifest_args!(resource, amount, WithdrawStrategy::Exact),
+        };
+        let (account, admin_badge_address) = (self.account, self.admin_badge_address);
+        self.add_instructions(label, |builder| {
+            builder
+                .create_proof_from_account_of_amount(account, admin_badge_address, dec!(1))
+                .call_method(pool, "protected_withdraw", arguments)
+        })
+    }
+}
+
+#[test]
+fn test_one_resource_pool() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let pool = test_environment.create_one_resource_pool(x_address);
+    let pool_unit_resource = test_environment.pool_unit_resource(pool);
+
+    test_environment.contribute("contribute", pool, vec![(x_address, dec!(10))]);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt
+            .balance_changes()
+            .fungible(account, pool_unit_resource),
+        dec!(10)
+    );
+
+    test_environment.protected_deposit("deposit", pool, x_address, dec!(5));
+    test_environment.protected_withdraw("withdraw", pool, x_address, dec!(3));
+    test_environment.execute_expect_success(false);
+
+    test_environment.redeem("redeem", pool, dec!(10));
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt.balance_changes().fungible(account, x_address),
+        dec!(12)
+    );
+}
+
+#[test]
+fn test_multi_resource_pool() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address, y_address, u_address) = (
+        test_environment.account,
+        test_environment.x_address,
+        test_environment.y_address,
+        test_environment.u_address(),
+    );
+    let pool = test_environment.create_multi_resource_pool(vec![x_address, y_address, u_address]);
+    let pool_unit_resource = test_environment.pool_unit_resource(pool);
+
+    test_environment.contribute(
+        "contribute",
+        pool,
+        vec![
+            (x_address, dec!(10)),
+            (y_address, dec!(20)),
+            (u_address, dec!(30)),
+        ],
+    );
+    let receipt = test_environment.execute_expect_success(false);
+    let pool_units = receipt
+        .balance_changes()
+        .fungible(account, pool_unit_resource);
+    assert!(pool_units > Decimal::ZERO);
+
+    test_environment.protected_deposit("deposit", pool, u_address, dec!(6));
+    test_environment.protected_withdraw("withdraw", pool, y_address, dec!(4));
+    test_environment.execute_expect_success(false);
+
+    test_environment.redeem("redeem", pool, pool_units);
+    let receipt = test_environment.execute_expect_success(false);
+    let balance_changes = receipt.balance_changes();
+    assert_eq!(balance_changes.fungible(account, x_address), dec!(10));
+    assert_eq!(balance_changes.fungible(account, y_address), dec!(16));
+    assert_eq!(balance_changes.fungible(account, u_address), dec!(36));
+}
+
+#[test]
+fn test_two_resource_pool() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address, y_address) = (
+        test_environment.account,
+        test_environment.x_address,
+        test_environment.y_address,
+    );
+    let pool = test_environment.create_two_resource_pool((x_address, y_address));
+    let pool_unit_resource = test_environment.pool_unit_resource(pool);
+
+    test_environment.contribute(
+        "contribute",
+        pool,
+        vec![(x_address, dec!(10)), (y_address, dec!(20))],
+    );
+    let receipt = test_environment.execute_expect_success(false);
+    let pool_units = receipt
+        .balance_changes()
+        .fungible(account, pool_unit_resource);
+    assert!(pool_units > Decimal::ZERO);
+
+    test_environment.protected_deposit("deposit", pool, x_address, dec!(10));
+    test_environment.protected_withdraw("withdraw", pool, y_address, dec!(10));
+    test_environment.execute_expect_success(false);
+
+    test_environment.redeem("redeem", pool, pool_units);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt.balance_changes().fungible(account, x_address),
+        dec!(20)
+    );
+    assert_eq!(
+        receipt.balance_changes().fungible(account, y_address),
+        dec!(10)
+    );
+}
@@ -0,0 +1,233 @@
+use radix_engine::blueprints::package::PackageDefinition;
+use radix_substate_store_interface::interface::{
+    CommittableSubstateDatabase, DatabaseUpdates, DbSortKey, ListableSubstateDatabase,
+    NodeDatabaseUpdates, PartitionDatabaseUpdates,
+};
+use scrypto::prelude::*;
+use std::path::Path;
+
+use crate::environment::KeyGenerator;
+use crate::{TestAccount, TestEnvironmentConfig, TestEnvironmentSnapshot};
+
+/// On-disk representation of a TestEnvironmentSnapshot
+#[derive(ScryptoSbor)]
+struct PersistedSnapshot {
+    /// (node key, partition number, sort key, value) of every substate
+    substates: Vec<(Vec<u8>, u8, Vec<u8>, Vec<u8>)>,
+    next_transaction_nonce: u32,
+
+    package_addresses: Vec<(String, PackageAddress)>,
+    previous_package_addresses: Vec<(String, Vec<PackageAddress>)>,
+    package_definitions: Vec<(String, PackageDefinition)>,
+    public_key: Secp256k1PublicKey,
+    account: ComponentAddress,
+    dapp_definition: ComponentAddress,
+    accounts: Vec<TestAccount>,
+    accounts_by_label: Vec<(String, TestAccount)>,
+    identities_by_label: Vec<(String, ComponentAddress)>,
+    main_account: TestAccount,
+    dapp_definition_account: TestAccount,
+    key_generator: KeyGenerator,
+
+    admin_badge_address: ResourceAddress,
+    badges: Vec<(String, ResourceAddress)>,
+    a_address: ResourceAddress,
+    b_address: ResourceAddress,
+    x_address: ResourceAddress,
+    y_address: ResourceAddress,
+    u_address: Option<ResourceAddress>,
+    v_address: Option<ResourceAddress>,
+    d0_address: Option<ResourceAddress>,
+    d6_address: Option<ResourceAddress>,
+    recallable_address: Option<ResourceAddress>,
+    j_nft_address: Option<ResourceAddress>,
+    k_nft_address: Option<ResourceAddress>,
+    string_nft_address: Option<ResourceAddress>,
+    bytes_nft_address: Option<ResourceAddress>,
+    ruid_nft_address: Option<ResourceAddress>,
+    resources_by_symbol: Vec<(String, ResourceAddress)>,
+}
+
+impl TestEnvironmentSnapshot {
+    /// Writes the snapshot to `path`, so expensive setups can be reloaded by other test binaries.
+    /// The environment config is not persisted and has to be passed to `load` again.
+    pub fn save<P: AsRef<Path>>(&self, path: P) {
+        self.write(path.as_ref())
+            .unwrap_or_else(|_| panic!("Failed to write snapshot to {:?}", path.as_ref()));
+    }
+
+    /// Like `save`, but returns the I/O error instead of panicking
+    pub(crate) fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut test_environment = self.revive();
+        // Nonces handed out by the simulator are derived from a counter, which has to
+        // continue after loading to not repeat the intents of already executed transactions
+        let next_transaction_nonce = test_environment.test_runner.next_transaction_nonce();
+        let persisted = PersistedSnapshot {
+            substates: test_environment
+                .substates()
+                .into_iter()
+                .map(|((partition_key, sort_key), value)| {
+                    (
+                        partition_key.node_key,
+                        partition_key.partition_num,
+                        sort_key.0,
+                        value,
+                    )
+                })
+                .collect(),
+            next_transaction_nonce,
+
+            package_addresses: self.package_addresses.clone().into_iter().collect(),
+            previous_package_addresses: self
+                .previous_package_addresses
+                .clone()
+                .into_iter()
+                .collect(),
+            package_definitions: self.package_definitions.clone().into_iter().collect(),
+            public_key: self.public_key,
+            account: self.account,
+            dapp_definition: self.dapp_definition,
+            accounts: self.accounts.clone(),
+            accounts_by_label: self.accounts_by_label.clone().into_iter().collect(),
+            identities_by_label: self.identities_by_label.clone().into_iter().collect(),
+            main_account: self.main_account.clone(),
+            dapp_definition_account: self.dapp_definition_account.clone(),
+            key_generator: self.key_generator.clone(),
+
+            admin_badge_address: self.admin_badge_address,
+            badges: self.badges.clone().into_iter().collect(),
+            a_address: self.a_address,
+            b_address: self.b_address,
+            x_address: self.x_address,
+            y_address: self.y_address,
+            u_address: self.u_address,
+            v_address: self.v_address,
+            d0_address: self.d0_address,
+            d6_address: self.d6_address,
+            recallable_address: self.recallable_address,
+            j_nft_address: self.j_nft_address,
+            k_nft_address: self.k_nft_address,
+            string_nft_address: self.string_nft_address,
+            bytes_nft_address: self.bytes_nft_address,
+            ruid_nft_address: self.ruid_nft_address,
+            resources_by_symbol: self.resources_by_symbol.clone().into_iter().collect(),
+        };
+        std::fs::write(path, scrypto_encode(&persisted).unwrap())
+    }
+
+    /// Loads a snapshot written by `save`. `config` has to match the config of the saved
+    /// environment, since it provides the simulator configuration for reviving.
+    pub fn load<P: AsRef<Path>>(path: P, config: TestEnvironmentConfig) -> Self {
+        let bytes = std::fs::read(path.as_ref())
+            .unwrap_or_else(|_| panic!("Failed to read snapshot {:?}", path.as_ref()));
+        let persisted: PersistedSnapshot = scrypto_decode(&bytes)
+            .unwrap_or_else(|_| panic!("Invalid snapshot file {:?}", path.as_ref()));
+
+        // Replace the state of a freshly bootstrapped ledger partition by partition
+        let mut test_runner = config.simulator_builder().build();
+        let mut partitions: IndexMap<Vec<u8>, IndexMap<u8, IndexMap<DbSortKey, Vec<u8>>>> =
+            IndexMap::new();
+        for partition_key in test_runner.substate_db().list_partition_keys() {
+            partitions
+                .entry(partition_key.node_key)
+                .or_default()
+                .entry(partition_key.partition_num)
+                .or_default();
+        }
+        for (node_key, partition_num, sort_key, value) in persisted.substates {
+            partitions
+                .entry(node_key)
+                .or_default()
+                .entry(partition_num)
+                .or_default()
+                .insert(DbSortKey(sort_key), value);
+        }
+        let database_updates = DatabaseUpdates {
+            node_updates: partitions
+                .into_iter()
+                .map(|(node_key, node_partitions)| {
+                    let partition_updates = node_partitions
+                        .into_iter()
+                        .map(|(partition_num, new_substate_values)| {
+                            (
+                                partition_num,
+                                PartitionDatabaseUpdates::Reset {
+                                    new_substate_values,
+                                },
+                            )
+                        })
+                        .collect();
+                    (node_key, NodeDatabaseUpdates { partition_updates })
+                })
+                .collect(),
+        };
+        test_runner.substate_db_mut().commit(&database_updates);
+        for _ in 0..persisted.next_transaction_nonce {
+            test_runner.next_transaction_nonce();
+        }
+
+        TestEnvironmentSnapshot {
+            test_runner_snapshot: test_runner.create_snapshot(),
+
+            package_addresses: persisted.package_addresses.into_iter().collect(),
+            previous_package_addresses: persisted.previous_package_addresses.into_iter().collect(),
+            package_definitions: persisted.package_definitions.into_iter().collect(),
+            public_key: persisted.public_key,
+            account: persisted.account,
+            dapp_definition: persisted.dapp_definition,
+            accounts: persisted.accounts,
+            accounts_by_label: persisted.accounts_by_label.into_iter().collect(),
+            identities_by_label: persisted.identities_by_label.into_iter().collect(),
+            main_account: persisted.main_account,
+            dapp_definition_account: persisted.dapp_definition_account,
+            key_generator: persisted.key_generator,
+
+            admin_badge_address: persisted.admin_badge_address,
+            badges: persisted.badges.into_iter().collect(),
+            a_address: persisted.a_address,
+            b_address: persisted.b_address,
+            x_address: persisted.x_address,
+            y_address: persisted.y_address,
+            u_address: persisted.u_address,
+            v_address: persisted.v_address,
+            d0_address: persisted.d0_address,
+            d6_address: persisted.d6_address,
+            recallable_address: persisted.recallable_address,
+            j_nft_address: persisted.j_nft_address,
+            k_nft_address: persisted.k_nft_address,
+            string_nft_address: persisted.string_nft_address,
+            bytes_nft_address: persisted.bytes_nft_address,
+            ruid_nft_address: persisted.ruid_nft_address,
+            resources_by_symbol: persisted.resources_by_symbol.into_iter().collect(),
+
+            config,
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_save_and_load() {
+    use crate::TestEnvironment;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let trader = test_environment.new_account("trader");
+    test_environment.transfer(account, trader, x_address, dec!(3));
+    let path = std::env::temp_dir().join("scrypto_testenv_test_snapshot_save_and_load.bin");
+
+    test_environment.create_snapshot().save(&path);
+    let mut test_environment_loaded =
+        TestEnvironmentSnapshot::load(&path, test_environment.config().clone()).revive();
+
+    assert_eq!(test_environment_loaded.account("trader"), trader);
+    assert_eq!(
+        test_environment_loaded
+            .test_runner
+            .get_component_balance(trader, x_address),
+        dec!(3)
+    );
+    let new_account = test_environment_loaded.new_account("second");
+    assert_ne!(new_account, trader);
+    test_environment_loaded.transfer(trader, new_account, x_address, dec!(1));
+}
@@ -0,0 +1,102 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::RwLock;
+
+use crate::TestEnvironmentBuilder;
+
+/// Pinned source of a preset package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresetSource {
+    /// Local package directory, e.g. a vendored copy or git submodule
+    Path(PathBuf),
+    /// Package inside a git repository checked out at a fixed revision
+    Git {
+        url: String,
+        rev: String,
+        subdir: PathBuf,
+    },
+}
+
+lazy_static! {
+    static ref PACKAGE_PRESETS: RwLock<HashMap<String, PresetSource>> = RwLock::new(HashMap::new());
+}
+
+/// Registers a package under `name` so it can be added with `TestEnvironmentBuilder::with_preset`.
+/// Since presets resolve to a fixed directory, all tests share the compiled package via the package cache.
+pub fn register_package_preset(name: &str, source: PresetSource) {
+    let mut write_lock = PACKAGE_PRESETS.write().unwrap();
+    write_lock.insert(name.to_string(), source);
+}
+
+/// Resolves a registered preset to its package directory, checking out git sources into
+/// `target/testenv-presets` on first use
+pub fn preset_dir(name: &str) -> PathBuf {
+    let source = PACKAGE_PRESETS
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .unwrap_or_else(|| panic!("Package preset {:?} not registered", name));
+    match source {
+        PresetSource::Path(package_dir) => package_dir,
+        PresetSource::Git { url, rev, subdir } => {
+            let checkout_dir = presets_dir().join(format!("{}-{}", name, rev));
+            if !checkout_dir.exists() {
+                git_checkout(&url, &rev, &checkout_dir);
+            }
+            checkout_dir.join(subdir)
+        }
+    }
+}
+
+fn presets_dir() -> PathBuf {
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
+    PathBuf::from(target_dir).join("testenv-presets")
+}
+
+fn git_checkout(url: &str, rev: &str, checkout_dir: &Path) {
+    // Clone into a temporary directory first, so an interrupted clone is never mistaken for a checkout
+    let clone_dir = checkout_dir.with_extension("tmp");
+    let _ = std::fs::remove_dir_all(&clone_dir);
+    run_git(Command::new("git").arg("clone").arg(url).arg(&clone_dir));
+    run_git(
+        Command::new("git")
+            .arg("-C")
+            .arg(&clone_dir)
+            .arg("checkout")
+            .arg(rev),
+    );
+    // Another test may have finished the same checkout in the meantime
+    if std::fs::rename(&clone_dir, checkout_dir).is_err() {
+        let _ = std::fs::remove_dir_all(&clone_dir);
+    }
+}
+
+fn run_git(command: &mut Command) {
+    let status = command
+        .status()
+        .unwrap_or_else(|error| panic!("Failed to run {:?}: {}", command, error));
+    assert!(status.success(), "{:?} failed with {}", command, status);
+}
+
+impl TestEnvironmentBuilder {
+    /// Adds a package registered with `register_package_preset`
+    pub fn with_preset(self, name: &str) -> Self {
+        self.package(name, preset_dir(name))
+    }
+}
+
+#[test]
+fn test_preset_dir() {
+    register_package_preset(
+        "hello_swap",
+        PresetSource::Path(PathBuf::from("examples/hello_swap")),
+    );
+
+    assert_eq!(
+        preset_dir("hello_swap"),
+        PathBuf::from("examples/hello_swap")
+    );
+}
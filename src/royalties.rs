@@ -0,0 +1,189 @@
+use radix_engine::system::system_modules::costing::RoyaltyRecipient;
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::{Receipt, TestEnvironment};
+
+impl TestEnvironment {
+    /// Sets the royalty of `method` of a royalty enabled component owned by the admin badge
+    /// (e.g. instantiated with `OwnerRole::Updatable(rule!(require(admin_badge_address)))`)
+    pub fn set_component_royalty(
+        &mut self,
+        component: ComponentAddress,
+        method: &str,
+        amount: RoyaltyAmount,
+    ) {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.admin_badge_address, dec!(1))
+            .set_component_royalty(component, method, amount)
+            .build();
+        let initial_proofs = self.signatures(&[self.account]);
+        self.test_runner
+            .execute_manifest(manifest, initial_proofs)
+            .expect_commit_success();
+    }
+
+    /// Royalties accrued by `component` which have not been claimed yet
+    pub fn component_royalty_balance(&mut self, component: ComponentAddress) -> Decimal {
+        self.test_runner.inspect_component_royalty(component)
+    }
+
+    /// Claims the royalties accrued by a package published by the environment into the active
+    /// account, tracked under `label` (the claimed XRD is the output bucket)
+    pub fn claim_package_royalties(&mut self, label: &str, package: PackageAddress) -> &mut Self {
+        self.present_admin_badge();
+        self.add_instructions(label, |builder| builder.claim_package_royalties(package))
+    }
+
+    /// Claims the royalties accrued by a component owned by the admin badge into the active
+    /// account, tracked under `label` (the claimed XRD is the output bucket)
+    pub fn claim_component_royalties(
+        &mut self,
+        label: &str,
+        component: ComponentAddress,
+    ) -> &mut Self {
+        self.present_admin_badge();
+        self.add_instructions(label, |builder| {
+            builder.claim_component_royalties(component)
+        })
+    }
+}
+
+impl Receipt {
+    /// XRD royalties paid by the transaction per receiving package or component
+    pub fn royalties_paid(&self) -> IndexMap<GlobalAddress, Decimal> {
+        let mut royalties_paid: IndexMap<GlobalAddress, Decimal> = IndexMap::new();
+        for (royalty_recipient, amount) in
+            &self.execution_receipt.fee_destination.to_royalty_recipients
+        {
+            let recipient: GlobalAddress = match royalty_recipient {
+                RoyaltyRecipient::Package(package, _) => (*package).into(),
+                RoyaltyRecipient::Component(component, _) => (*component).into(),
+            };
+            *royalties_paid.entry(recipient).or_default() += *amount;
+        }
+        royalties_paid
+    }
+
+    /// XRD royalties paid by the transaction to the component or package `recipient`
+    pub fn royalty_paid_to(&self, recipient: GlobalAddress) -> Decimal {
+        self.royalties_paid()
+            .get(&recipient)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn test_component_royalties() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::from([("royalties", "testdata/royalties")]);
+    let mut test_environment = TestEnvironment::new(packages);
+    let package_address = test_environment.package_address("royalties");
+    let admin_badge_address = test_environment.admin_badge_address;
+    test_environment.add_instructions("instantiate", |builder| {
+        builder.call_function(
+            package_address,
+            "Counter",
+            "instantiate",
+            manifest_args!(admin_badge_address),
+        )
+    });
+    let component: ComponentAddress = test_environment
+        .execute_expect_success(false)
+        .outputs("instantiate")[0];
+    let increment = |test_environment: &mut TestEnvironment| {
+        test_environment.add_instructions("increment", |builder| {
+            builder.call_method(component, "increment", manifest_args!())
+        });
+        test_environment.execute_expect_success(false)
+    };
+
+    let receipt = increment(&mut test_environment);
+    assert_eq!(receipt.royalty_paid_to(component.into()), dec!(2));
+    assert_eq!(receipt.royalties_paid().len(), 1);
+    assert_eq!(
+        test_environment.component_royalty_balance(component),
+        dec!(2)
+    );
+
+    test_environment.set_component_royalty(component, "increment", RoyaltyAmount::Xrd(dec!(3)));
+    let receipt = increment(&mut test_environment);
+    assert_eq!(receipt.royalty_paid_to(component.into()), dec!(3));
+    assert_eq!(
+        test_environment.component_royalty_balance(component),
+        dec!(5)
+    );
+
+    test_environment.claim_component_royalties("claim", component);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt.output_buckets("claim"),
+        vec![vec![ResourceSpecifier::Amount(XRD, dec!(5))]]
+    );
+    assert_eq!(
+        test_environment.component_royalty_balance(component),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_package_royalties() {
+    use crate::{TestEnvironmentBuilder, TestHelperExecution};
+
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .package("royalties", "testdata/royalties")
+        .package_royalty("royalties", "Counter", "increment", dec!(1))
+        .build();
+    let package_address = test_environment.package_address("royalties");
+    let admin_badge_address = test_environment.admin_badge_address;
+    test_environment.add_instructions("instantiate", |builder| {
+        builder.call_function(
+            package_address,
+            "Counter",
+            "instantiate",
+            manifest_args!(admin_badge_address),
+        )
+    });
+    let receipt = test_environment.execute_expect_success(false);
+    // Functions without a configured royalty stay free
+    assert_eq!(
+        receipt.royalty_paid_to(package_address.into()),
+        Decimal::ZERO
+    );
+    let component: ComponentAddress = receipt.outputs("instantiate")[0];
+
+    test_environment.add_instructions("increment", |builder| {
+        builder.call_method(component, "increment", manifest_args!())
+    });
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(receipt.royalty_paid_to(package_address.into()), dec!(1));
+    assert_eq!(receipt.royalty_paid_to(component.into()), dec!(2));
+
+    test_environment.claim_package_royalties("claim", package_address);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt.output_buckets("claim"),
+        vec![vec![ResourceSpecifier::Amount(XRD, dec!(1))]]
+    );
+}
+
+#[test]
+fn test_package_royalties_missing_blueprint() {
+    use crate::{TestEnvError, TestEnvironmentBuilder};
+
+    let result = TestEnvironmentBuilder::new()
+        .package("royalties", "testdata/royalties")
+        .package_royalty("royalties", "Missing", "increment", dec!(1))
+        .try_build();
+
+    assert_eq!(
+        result.err(),
+        Some(TestEnvError::BlueprintNotFound {
+            package_name: "royalties".to_string(),
+            blueprint_name: "Missing".to_string(),
+        })
+    );
+}
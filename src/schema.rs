@@ -0,0 +1,167 @@
+use radix_engine::blueprints::package::PackageDefinition;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+/// Function or method of a published blueprint, as declared in its package definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlueprintFunction {
+    pub name: String,
+    /// `None` for functions, `&self` or `&mut self` for methods
+    pub receiver: Option<Receiver>,
+    /// Name and type name of every argument
+    pub arguments: Vec<(String, String)>,
+    pub output: String,
+}
+
+impl BlueprintFunction {
+    pub fn is_method(&self) -> bool {
+        self.receiver.is_some()
+    }
+}
+
+impl TestEnvironment {
+    /// Lists the functions and methods of `blueprint_name` in the package published as
+    /// `package_name`, e.g. to run the same check against every method:
+    /// ```ignore
+    /// for method in env.blueprint_functions("pool", "Pool").iter().filter(|f| f.is_method()) {
+    ///     ...
+    /// }
+    /// ```
+    pub fn blueprint_functions(
+        &self,
+        package_name: &str,
+        blueprint_name: &str,
+    ) -> Vec<BlueprintFunction> {
+        let definition = self
+            .package_definitions
+            .get(package_name)
+            .unwrap_or_else(|| panic!("Package {:?} not found", package_name));
+        definition_functions(definition, blueprint_name).unwrap_or_else(|| {
+            panic!(
+                "Blueprint {:?} not found in package {:?}",
+                blueprint_name, package_name
+            )
+        })
+    }
+}
+
+/// Functions and methods of `blueprint_name` in `definition`, `None` if there is no such blueprint
+pub(crate) fn definition_functions(
+    definition: &PackageDefinition,
+    blueprint_name: &str,
+) -> Option<Vec<BlueprintFunction>> {
+    let blueprint = definition.blueprints.get(blueprint_name)?;
+    let schema = blueprint.schema.schema.v1();
+    let functions = blueprint
+        .schema
+        .functions
+        .functions
+        .iter()
+        .map(|(name, function)| BlueprintFunction {
+            name: name.clone(),
+            receiver: function
+                .receiver
+                .as_ref()
+                .map(|receiver_info| receiver_info.receiver.clone()),
+            arguments: match function.input {
+                TypeRef::Static(input_type) => arguments(schema, input_type),
+                TypeRef::Generic(_) => vec![],
+            },
+            output: match function.output {
+                TypeRef::Static(output_type) => type_name(schema, output_type),
+                TypeRef::Generic(index) => format!("Generic({})", index),
+            },
+        })
+        .collect();
+    Some(functions)
+}
+
+// Function inputs are encoded as a struct with one field per argument
+fn arguments(
+    schema: &SchemaV1<ScryptoCustomSchema>,
+    input_type: LocalTypeId,
+) -> Vec<(String, String)> {
+    let field_types = match schema.resolve_type_kind(input_type) {
+        Some(TypeKind::Tuple { field_types }) => field_types.clone(),
+        _ => return vec![],
+    };
+    let field_names: Vec<String> = match schema
+        .resolve_type_metadata(input_type)
+        .and_then(|metadata| metadata.child_names.as_ref())
+    {
+        Some(ChildNames::NamedFields(names)) => names.iter().map(|name| name.to_string()).collect(),
+        _ => (0..field_types.len())
+            .map(|index| index.to_string())
+            .collect(),
+    };
+    field_names
+        .into_iter()
+        .zip(field_types)
+        .map(|(name, field_type)| (name, type_name(schema, field_type)))
+        .collect()
+}
+
+fn type_name(schema: &SchemaV1<ScryptoCustomSchema>, type_id: LocalTypeId) -> String {
+    if let Some(name) = schema
+        .resolve_type_metadata(type_id)
+        .and_then(|metadata| metadata.get_name())
+    {
+        return name.to_string();
+    }
+    match schema.resolve_type_kind(type_id) {
+        Some(TypeKind::Array { element_type }) => {
+            format!("Vec<{}>", type_name(schema, *element_type))
+        }
+        Some(TypeKind::Tuple { field_types }) => format!(
+            "({})",
+            field_types
+                .iter()
+                .map(|field_type| type_name(schema, *field_type))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Some(TypeKind::Map {
+            key_type,
+            value_type,
+        }) => format!(
+            "Map<{}, {}>",
+            type_name(schema, *key_type),
+            type_name(schema, *value_type)
+        ),
+        Some(type_kind) => format!("{:?}", type_kind),
+        None => "Unknown".to_string(),
+    }
+}
+
+#[test]
+fn test_blueprint_functions() {
+    let packages: HashMap<&str, &str> = HashMap::from([("hello_swap", "examples/hello_swap")]);
+    let test_environment = TestEnvironment::new(packages);
+    let functions = test_environment.blueprint_functions("hello_swap", "HelloSwap");
+    let function = |name: &str| {
+        functions
+            .iter()
+            .find(|function| function.name == name)
+            .unwrap_or_else(|| panic!("Function {:?} not found", name))
+    };
+
+    assert_eq!(functions.len(), 2);
+    let instantiate = function("instantiate");
+    assert!(!instantiate.is_method());
+    assert_eq!(
+        instantiate.arguments,
+        vec![
+            ("x_address".to_string(), "ResourceAddress".to_string()),
+            ("y_bucket".to_string(), "Bucket".to_string()),
+            ("price".to_string(), "Decimal".to_string()),
+        ]
+    );
+    let swap = function("swap");
+    assert_eq!(swap.receiver, Some(Receiver::SelfRefMut));
+    assert_eq!(
+        swap.arguments,
+        vec![("x_bucket".to_string(), "Bucket".to_string())]
+    );
+    assert_eq!(swap.output, "(Bucket, Bucket)");
+}
@@ -0,0 +1,141 @@
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::TestEnvironment;
+
+/// Shared handle to one TestEnvironment, so several helpers (e.g. of a pool and a router)
+/// can build into the same manifest and ledger. Cloning the handle shares the environment,
+/// which the helpers return from `TestHelperExecution::env` via `self.env.env()`:
+/// ```ignore
+/// let env = TestEnvironmentHandle::new(TestEnvironment::new(packages));
+/// let mut pool = PoolTestHelper::new(env.clone());
+/// let mut router = RouterTestHelper::new(env.clone());
+/// pool.add_liquidity(...);
+/// router.swap(...);
+/// env.env().execute_expect_success(false);
+/// ```
+/// Helpers only borrowing the environment for a while can also implement `TestHelperExecution`
+/// for `struct PoolTestHelper<'a> { env: &'a mut TestEnvironment }` instead.
+#[derive(Clone)]
+pub struct TestEnvironmentHandle(Rc<RefCell<TestEnvironment>>);
+
+impl TestEnvironmentHandle {
+    pub fn new(test_environment: TestEnvironment) -> Self {
+        Self(Rc::new(RefCell::new(test_environment)))
+    }
+
+    /// Mutable access to the environment, panics if it is already borrowed by another handle
+    pub fn env(&self) -> RefMut<'_, TestEnvironment> {
+        self.0
+            .try_borrow_mut()
+            .expect("TestEnvironment is already borrowed via another handle")
+    }
+
+    /// Runs `f` with mutable access to the environment
+    pub fn with<R, F: FnOnce(&mut TestEnvironment) -> R>(&self, f: F) -> R {
+        f(&mut self.env())
+    }
+
+    /// Returns the environment, panics if other handles to it still exist
+    pub fn into_inner(self) -> TestEnvironment {
+        Rc::try_unwrap(self.0)
+            .ok()
+            .expect("TestEnvironment is still shared with other handles")
+            .into_inner()
+    }
+}
+
+impl From<TestEnvironment> for TestEnvironmentHandle {
+    fn from(test_environment: TestEnvironment) -> Self {
+        Self::new(test_environment)
+    }
+}
+
+#[test]
+fn test_shared_environment() {
+    use crate::TestHelperExecution;
+    use radix_transactions::prelude::*;
+    use scrypto::prelude::*;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let handle = TestEnvironmentHandle::new(TestEnvironment::new(packages));
+    let (pool_env, router_env) = (handle.clone(), handle.clone());
+    let (account, x_address, y_address) =
+        handle.with(|env| (env.account, env.x_address, env.y_address));
+
+    pool_env
+        .env()
+        .call_with_bucket("pool::deposit", x_address, dec!(1), |builder, bucket| {
+            builder.call_method(account, "deposit", manifest_args!(bucket))
+        });
+    router_env
+        .env()
+        .call_with_bucket("router::deposit", y_address, dec!(2), |builder, bucket| {
+            builder.call_method(account, "deposit", manifest_args!(bucket))
+        });
+    let receipt = handle.env().execute_expect_success(false);
+
+    assert_eq!(receipt.outputs::<()>("pool::deposit"), vec![()]);
+    assert_eq!(receipt.outputs::<()>("router::deposit"), vec![()]);
+    drop((pool_env, router_env));
+    assert_eq!(handle.into_inner().transaction_count(), 1);
+}
+
+#[test]
+fn test_shared_environment_helpers() {
+    use crate::TestHelperExecution;
+    use radix_transactions::prelude::*;
+    use scrypto::prelude::*;
+    use std::ops::DerefMut;
+
+    struct DepositTestHelper {
+        env: TestEnvironmentHandle,
+        namespace: &'static str,
+    }
+    impl TestHelperExecution for DepositTestHelper {
+        fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {
+            self.env.env()
+        }
+        fn namespace(&self) -> Option<&str> {
+            Some(self.namespace)
+        }
+    }
+    impl DepositTestHelper {
+        fn deposit(&mut self, resource_address: ResourceAddress, amount: Decimal) -> &mut Self {
+            let bucket_name = self.name("bucket");
+            let account = self.env().account;
+            self.add_instructions("deposit", |builder| {
+                builder
+                    .withdraw_from_account(account, resource_address, amount)
+                    .take_from_worktop(resource_address, amount, &bucket_name)
+                    .with_name_lookup(|builder, lookup| {
+                        let bucket = lookup.bucket(&bucket_name);
+                        builder.call_method(account, "deposit", manifest_args!(bucket))
+                    })
+            })
+        }
+    }
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let handle = TestEnvironmentHandle::new(TestEnvironment::new(packages));
+    let (x_address, y_address) = handle.with(|env| (env.x_address, env.y_address));
+    let mut pool = DepositTestHelper {
+        env: handle.clone(),
+        namespace: "pool",
+    };
+    let mut router = DepositTestHelper {
+        env: handle.clone(),
+        namespace: "router",
+    };
+
+    // Both helpers build into the same manifest under their own names and labels
+    pool.deposit(x_address, dec!(1));
+    router.deposit(y_address, dec!(2));
+    pool.deposit(y_address, dec!(3));
+    let receipt = router.execute_expect_success(false);
+
+    assert_eq!(receipt.outputs::<()>("pool::deposit"), vec![(), ()]);
+    assert_eq!(receipt.outputs::<()>("router::deposit"), vec![()]);
+    drop((pool, router));
+    assert_eq!(handle.into_inner().transaction_count(), 1);
+}
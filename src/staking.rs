@@ -0,0 +1,131 @@
+use radix_transactions::prelude::*;
+use scrypto::prelude::*;
+
+use crate::TestEnvironment;
+
+impl TestEnvironment {
+    /// Creates a validator accepting delegated stake, with its owner badge deposited
+    /// into the main account
+    pub fn create_validator(&mut self) -> ComponentAddress {
+        let (public_key, _) = self.new_key_pair();
+        let validator = self
+            .test_runner
+            .new_validator_with_pub_key(public_key, self.account);
+        let owner_badge_id = NonFungibleLocalId::bytes(validator.as_node_id().0).unwrap();
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(
+                self.account,
+                VALIDATOR_OWNER_BADGE,
+                [owner_badge_id],
+            )
+            .call_method(
+                validator,
+                VALIDATOR_UPDATE_ACCEPT_DELEGATED_STAKE_IDENT,
+                manifest_args!(true),
+            )
+            .build();
+        self.test_runner
+            .execute_manifest(
+                manifest,
+                vec![NonFungibleGlobalId::from_public_key(&self.public_key)],
+            )
+            .expect_commit_success();
+        validator
+    }
+
+    /// Stake units of `validator`
+    pub fn stake_unit_resource(&mut self, validator: ComponentAddress) -> ResourceAddress {
+        self.test_runner
+            .get_validator_info(validator)
+            .stake_unit_resource
+    }
+
+    /// Claim NFT resource of `validator`, received when unstaking
+    pub fn claim_nft_resource(&mut self, validator: ComponentAddress) -> ResourceAddress {
+        self.test_runner.get_validator_info(validator).claim_nft
+    }
+
+    /// Stakes `amount` XRD of the main account to `validator`, tracked under `label`
+    /// (the stake units are the output bucket)
+    pub fn stake(
+        &mut self,
+        label: &str,
+        validator: ComponentAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        self.call_with_account_bucket(label, XRD, amount, |builder, xrd_bucket| {
+            builder.call_method(validator, VALIDATOR_STAKE_IDENT, manifest_args!(xrd_bucket))
+        })
+    }
+
+    /// Unstakes `amount` stake units of the main account from `validator`, tracked under `label`
+    /// (the claim NFT is the output bucket)
+    pub fn unstake(
+        &mut self,
+        label: &str,
+        validator: ComponentAddress,
+        amount: Decimal,
+    ) -> &mut Self {
+        let stake_unit_resource = self.stake_unit_resource(validator);
+        self.call_with_account_bucket(label, stake_unit_resource, amount, |builder, lsu_bucket| {
+            builder.call_method(
+                validator,
+                VALIDATOR_UNSTAKE_IDENT,
+                manifest_args!(lsu_bucket),
+            )
+        })
+    }
+
+    /// Claims the XRD of the claim NFTs `ids` of the main account once the unstake delay
+    /// has passed (see `advance_epochs`), tracked under `label`
+    pub fn claim_xrd(
+        &mut self,
+        label: &str,
+        validator: ComponentAddress,
+        ids: IndexSet<NonFungibleLocalId>,
+    ) -> &mut Self {
+        let claim_nft_resource = self.claim_nft_resource(validator);
+        self.call_with_non_fungible_bucket(label, claim_nft_resource, ids, |builder, nft_bucket| {
+            builder.call_method(
+                validator,
+                VALIDATOR_CLAIM_XRD_IDENT,
+                manifest_args!(nft_bucket),
+            )
+        })
+    }
+}
+
+#[test]
+fn test_staking() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let account = test_environment.account;
+    let validator = test_environment.create_validator();
+    let stake_unit_resource = test_environment.stake_unit_resource(validator);
+    let claim_nft_resource = test_environment.claim_nft_resource(validator);
+
+    test_environment.stake("stake", validator, dec!(100));
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(
+        receipt
+            .balance_changes()
+            .fungible(account, stake_unit_resource),
+        dec!(100)
+    );
+
+    test_environment.unstake("unstake", validator, dec!(100));
+    let receipt = test_environment.execute_expect_success(false);
+    let (claim_ids, _) = receipt
+        .balance_changes()
+        .non_fungible(account, claim_nft_resource);
+    let claim_ids: IndexSet<NonFungibleLocalId> = claim_ids.into_iter().collect();
+    assert_eq!(claim_ids.len(), 1);
+
+    test_environment.advance_epochs(test_environment.config().genesis.num_unstake_epochs + 1);
+    test_environment.claim_xrd("claim", validator, claim_ids);
+    let receipt = test_environment.execute_expect_success(false);
+    assert_eq!(receipt.balance_changes().fungible(account, XRD), dec!(100));
+}
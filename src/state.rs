@@ -0,0 +1,399 @@
+use radix_engine::{
+    blueprints::resource::{
+        BurnFungibleResourceEvent, BurnNonFungibleResourceEvent, MintFungibleResourceEvent,
+        MintNonFungibleResourceEvent,
+    },
+    system::system_substates::KeyValueEntrySubstate,
+    transaction::TransactionResult,
+};
+use radix_substate_store_interface::{
+    db_key_mapper::{DatabaseKeyMapper, MapKey, SpreadPrefixKeyMapper},
+    interface::{DbPartitionKey, DbSortKey, ListableSubstateDatabase, SubstateDatabase},
+};
+use radix_transactions::{builder::ManifestBuilder, prelude::*};
+use scrypto::prelude::*;
+use std::io::Write;
+
+use crate::{Receipt, TestEnvironment, TestEnvironmentSnapshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubstateChange {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// Substate differing between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstateDiff {
+    pub node_id: NodeId,
+    pub partition: PartitionNumber,
+    /// Database sort key of the substate within its partition
+    pub sort_key: Vec<u8>,
+    pub change: SubstateChange,
+}
+
+impl TestEnvironment {
+    /// Reads the value stored under `key` in the KeyValueStore `store`
+    /// (e.g. the `KeyValueStore::id` of a component state field)
+    pub fn kv_store_entry<K: ScryptoEncode, V: ScryptoDecode>(
+        &self,
+        store: NodeId,
+        key: &K,
+    ) -> Option<V> {
+        self.test_runner
+            .substate_db()
+            .get_mapped::<SpreadPrefixKeyMapper, KeyValueEntrySubstate<V>>(
+                &store,
+                MAIN_BASE_PARTITION,
+                &SubstateKey::Map(scrypto_encode(key).unwrap()),
+            )?
+            .into_value()
+    }
+
+    /// All entries of the KeyValueStore `store` (in database order, not insertion order)
+    pub fn kv_store_entries<K: ScryptoDecode, V: ScryptoDecode>(
+        &self,
+        store: NodeId,
+    ) -> Vec<(K, V)> {
+        self.key_value_entries(store, MAIN_BASE_PARTITION)
+    }
+
+    /// Vaults of `resource` owned by `component`, e.g. the reserves of a pool
+    pub fn component_vaults(
+        &mut self,
+        component: ComponentAddress,
+        resource: ResourceAddress,
+    ) -> Vec<NodeId> {
+        self.test_runner.get_component_vaults(component, resource)
+    }
+
+    /// Amount of `resource` held by `component` across all its vaults
+    pub fn component_vault_balance(
+        &mut self,
+        component: ComponentAddress,
+        resource: ResourceAddress,
+    ) -> Decimal {
+        self.test_runner.get_component_balance(component, resource)
+    }
+
+    /// Current total supply of `resource`, `None` if the resource does not track its total supply
+    pub fn total_supply(&mut self, resource: ResourceAddress) -> Option<Decimal> {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                resource,
+                RESOURCE_MANAGER_GET_TOTAL_SUPPLY_IDENT,
+                manifest_args!(),
+            )
+            .build();
+        let receipt = self.test_runner.preview_manifest(
+            manifest,
+            vec![],
+            0,
+            PreviewFlags {
+                assume_all_signature_proofs: true,
+                ..Default::default()
+            },
+        );
+        receipt.expect_commit_success().output(1)
+    }
+
+    /// Nodes written by the transactions in the history, in order of their first write
+    pub fn touched_nodes(&self) -> IndexSet<NodeId> {
+        self.history()
+            .iter()
+            .filter_map(|transaction| match &transaction.receipt.result {
+                TransactionResult::Commit(commit_result) => Some(commit_result),
+                _ => None,
+            })
+            .flat_map(|commit_result| commit_result.state_updates.by_node.keys().copied())
+            .collect()
+    }
+
+    /// Writes all substates of `node` (or of all nodes touched by the history if `None`) in a
+    /// readable form: one block per node, grouped by partition, with hex sort keys and decoded values
+    pub fn dump_state<W: Write>(&self, mut writer: W, node: Option<NodeId>) -> std::io::Result<()> {
+        let nodes = match node {
+            Some(node_id) => {
+                let mut nodes = IndexSet::new();
+                nodes.insert(node_id);
+                nodes
+            }
+            None => self.touched_nodes(),
+        };
+        let encoder = AddressBech32Encoder::for_simulator();
+        let substate_db = self.test_runner.substate_db();
+        let partition_keys: Vec<_> = substate_db.list_partition_keys().collect();
+        for node_id in nodes {
+            let node_name = encoder
+                .encode(node_id.as_bytes())
+                .unwrap_or_else(|_| format!("{:?}", node_id));
+            writeln!(writer, "== {} ==", node_name)?;
+            for partition_key in &partition_keys {
+                let (partition_node_id, partition) =
+                    SpreadPrefixKeyMapper::from_db_partition_key(partition_key);
+                if partition_node_id != node_id {
+                    continue;
+                }
+                writeln!(writer, "  partition {}", partition.0)?;
+                for (sort_key, value) in substate_db.list_entries(partition_key) {
+                    let sort_key: String = sort_key
+                        .0
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect();
+                    match scrypto_decode::<ScryptoValue>(&value) {
+                        Ok(value) => writeln!(writer, "    {}: {:?}", sort_key, value)?,
+                        Err(_) => writeln!(writer, "    {}: {} raw bytes", sort_key, value.len())?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn substates(&self) -> BTreeMap<(DbPartitionKey, DbSortKey), Vec<u8>> {
+        let substate_db = self.test_runner.substate_db();
+        let partition_keys: Vec<_> = substate_db.list_partition_keys().collect();
+        partition_keys
+            .into_iter()
+            .flat_map(|partition_key| {
+                substate_db
+                    .list_entries(&partition_key)
+                    .map(|(sort_key, value)| ((partition_key.clone(), sort_key), value))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Entries of a key value partition, which is the main partition for KeyValueStores
+    /// and e.g. `METADATA_BASE_PARTITION` for the metadata of global entities
+    pub(crate) fn key_value_entries<K: ScryptoDecode, V: ScryptoDecode>(
+        &self,
+        node_id: NodeId,
+        partition: PartitionNumber,
+    ) -> Vec<(K, V)> {
+        self.test_runner
+            .substate_db()
+            .list_mapped::<SpreadPrefixKeyMapper, KeyValueEntrySubstate<V>, MapKey>(
+                &node_id, partition,
+            )
+            .filter_map(|(substate_key, entry)| {
+                let key = match substate_key {
+                    SubstateKey::Map(key) => scrypto_decode::<K>(&key).unwrap(),
+                    _ => return None,
+                };
+                entry.into_value().map(|value| (key, value))
+            })
+            .collect()
+    }
+}
+
+impl TestEnvironmentSnapshot {
+    /// Substates created, updated or deleted between this (earlier) snapshot and `other`
+    pub fn diff(&self, other: &TestEnvironmentSnapshot) -> Vec<SubstateDiff> {
+        let before = self.revive().substates();
+        let after = other.revive().substates();
+        let mut diffs: Vec<SubstateDiff> = vec![];
+        for (key, value) in &after {
+            let change = match before.get(key) {
+                None => SubstateChange::Created,
+                Some(previous) if previous != value => SubstateChange::Updated,
+                Some(_) => continue,
+            };
+            diffs.push(SubstateDiff::new(key, change));
+        }
+        for key in before.keys().filter(|key| !after.contains_key(*key)) {
+            diffs.push(SubstateDiff::new(key, SubstateChange::Deleted));
+        }
+        diffs
+    }
+}
+
+impl SubstateDiff {
+    fn new(key: &(DbPartitionKey, DbSortKey), change: SubstateChange) -> Self {
+        let (node_id, partition) = SpreadPrefixKeyMapper::from_db_partition_key(&key.0);
+        Self {
+            node_id,
+            partition,
+            sort_key: key.1 .0.clone(),
+            change,
+        }
+    }
+}
+
+impl Receipt {
+    /// Net amount of `resource` minted (positive) or burned (negative) by the transaction.
+    /// Fails if a mint or burn event of the resource can't be decoded.
+    pub fn supply_delta(&self, resource: ResourceAddress) -> Result<Decimal, DecodeError> {
+        let mut delta = Decimal::ZERO;
+        for event in self.events_from(resource) {
+            if event.is::<MintFungibleResourceEvent>() {
+                delta += scrypto_decode::<MintFungibleResourceEvent>(&event.data)?.amount;
+            } else if event.is::<BurnFungibleResourceEvent>() {
+                delta -= scrypto_decode::<BurnFungibleResourceEvent>(&event.data)?.amount;
+            } else if event.is::<MintNonFungibleResourceEvent>() {
+                let ids = scrypto_decode::<MintNonFungibleResourceEvent>(&event.data)?.ids;
+                delta += Decimal::from(ids.len());
+            } else if event.is::<BurnNonFungibleResourceEvent>() {
+                let ids = scrypto_decode::<BurnNonFungibleResourceEvent>(&event.data)?.ids;
+                delta -= Decimal::from(ids.len());
+            }
+        }
+        Ok(delta)
+    }
+}
+
+#[test]
+fn test_key_value_entries() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let test_environment = TestEnvironment::new(packages);
+    let a_address = test_environment.a_address;
+
+    let entries: Vec<(String, MetadataValue)> =
+        test_environment.key_value_entries(*a_address.as_node_id(), METADATA_BASE_PARTITION);
+
+    assert!(entries.contains(&("symbol".to_string(), MetadataValue::String("A".to_string()))));
+    assert_eq!(
+        test_environment.kv_store_entry::<String, MetadataValue>(
+            *a_address.as_node_id(),
+            &"symbol".to_string()
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_kv_store_entry() {
+    use crate::TestHelperExecution;
+
+    #[derive(ScryptoSbor)]
+    struct KvStoreState {
+        prices: Own,
+    }
+
+    let packages: HashMap<&str, &str> = HashMap::from([("kv_store", "testdata/kv_store")]);
+    let mut test_environment = TestEnvironment::new(packages);
+    let package_address = test_environment.package_address("kv_store");
+    test_environment.add_instructions("instantiate", |builder| {
+        builder.call_function(package_address, "KvStore", "instantiate", manifest_args!())
+    });
+    let receipt = test_environment.execute_expect_success(false);
+    let component: ComponentAddress = receipt.outputs("instantiate")[0];
+    let prices = test_environment
+        .test_runner
+        .get_component_state::<KvStoreState>(component)
+        .prices
+        .0;
+
+    assert_eq!(
+        test_environment.kv_store_entry::<String, Decimal>(prices, &"XRD".to_string()),
+        Some(dec!(2))
+    );
+    assert_eq!(
+        test_environment.kv_store_entry::<String, Decimal>(prices, &"BTC".to_string()),
+        None
+    );
+    assert_eq!(
+        test_environment.kv_store_entries::<String, Decimal>(prices),
+        vec![("XRD".to_string(), dec!(2))]
+    );
+}
+
+#[test]
+fn test_component_vault_balance() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let trader = test_environment.new_account("trader");
+    test_environment.transfer(account, trader, x_address, dec!(7));
+
+    assert_eq!(
+        test_environment.component_vaults(trader, x_address).len(),
+        1
+    );
+    assert_eq!(
+        test_environment.component_vault_balance(trader, x_address),
+        dec!(7)
+    );
+    assert!(test_environment
+        .component_vaults(trader, test_environment.y_address)
+        .is_empty());
+}
+
+#[test]
+fn test_supply_delta() {
+    use crate::{TestHelperExecution, MAX_SUPPLY};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let u_address = test_environment.u_address();
+    let total_supply = test_environment.total_supply(u_address).unwrap();
+    test_environment.present_admin_badge();
+    test_environment.add_instructions("mint_and_burn", |builder| {
+        builder
+            .mint_fungible(u_address, dec!(10))
+            .burn_all_from_worktop(u_address)
+            .mint_fungible(u_address, dec!(4))
+    });
+
+    let receipt = test_environment.execute_expect_success(false);
+
+    assert_eq!(receipt.supply_delta(u_address), Ok(dec!(4)));
+    assert_eq!(
+        receipt.supply_delta(test_environment.a_address),
+        Ok(Decimal::ZERO)
+    );
+    assert_eq!(
+        test_environment.total_supply(u_address),
+        Some(total_supply + dec!(4))
+    );
+    assert_eq!(
+        test_environment.total_supply(test_environment.a_address),
+        Some(MAX_SUPPLY)
+    );
+}
+
+#[test]
+fn test_dump_state() {
+    use crate::TestHelperExecution;
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    test_environment.execute_expect_success(false);
+    let account_name = AddressBech32Encoder::for_simulator()
+        .encode(test_environment.account.as_node_id().as_bytes())
+        .unwrap();
+
+    let mut dump = Vec::new();
+    test_environment
+        .dump_state(&mut dump, Some(*test_environment.account.as_node_id()))
+        .unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+
+    assert!(dump.starts_with(&format!("== {} ==", account_name)));
+    assert!(dump.contains("  partition "));
+    assert!(!test_environment.touched_nodes().is_empty());
+}
+
+#[test]
+fn test_snapshot_diff() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, x_address) = (test_environment.account, test_environment.x_address);
+    let trader = test_environment.new_account("trader");
+    let before = test_environment.create_snapshot();
+
+    test_environment.transfer(account, trader, x_address, dec!(1));
+    let after = test_environment.create_snapshot();
+
+    let diffs = before.diff(&after);
+    assert!(
+        diffs
+            .iter()
+            .any(|diff| diff.node_id == *trader.as_node_id()
+                && diff.change != SubstateChange::Deleted)
+    );
+    assert!(before.diff(&before).is_empty());
+}
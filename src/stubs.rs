@@ -0,0 +1,346 @@
+use radix_engine::blueprints::package::PackageDefinition;
+use scrypto::prelude::*;
+use std::fmt::Write;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::schema::definition_functions;
+use crate::BlueprintFunction;
+
+/// Generates Rust source of one test helper struct per blueprint of `definition` (published as
+/// `package_name`), with one labeled method per blueprint function, like the hand-written helper
+/// of the `hello_swap` example. Bucket and proof arguments are taken as `(resource, amount)` and
+/// withdrawn from / proven by the environment account, arguments of custom types as `ManifestValue`.
+/// The source is formatted with `rustfmt` if it is installed.
+pub fn generate_helper_stubs(package_name: &str, definition: &PackageDefinition) -> String {
+    let mut source = String::new();
+    writeln!(
+        source,
+        "// Generated by scrypto_testenv::generate_helper_stubs, do not edit\n\
+         use scrypto::prelude::*;\n\
+         use scrypto_testenv::*;\n\
+         use std::ops::DerefMut;"
+    )
+    .unwrap();
+    for blueprint_name in definition.blueprints.keys() {
+        let functions = definition_functions(definition, blueprint_name).unwrap();
+        source.push_str(&helper_stub(package_name, blueprint_name, &functions));
+    }
+    rustfmt(source)
+}
+
+// Returns `source` unchanged if rustfmt is not available or fails
+fn rustfmt(source: String) -> String {
+    let Ok(mut child) = Command::new("rustfmt")
+        .args(["--edition", "2021"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return source;
+    };
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(source.as_bytes()).is_ok());
+    match child.wait_with_output() {
+        Ok(output) if written && output.status.success() => {
+            String::from_utf8(output.stdout).unwrap_or(source)
+        }
+        _ => source,
+    }
+}
+
+/// Build script variant of `generate_helper_stubs`, reading the definition from a `.rpd` file
+/// (e.g. written by `scrypto build`) and writing the helpers to `out_path`:
+/// ```ignore
+/// // build.rs
+/// let out_path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("helpers.rs");
+/// write_helper_stubs("hello_swap", "target/wasm32-unknown-unknown/release/hello_swap.rpd", out_path);
+/// // tests/helper.rs
+/// include!(concat!(env!("OUT_DIR"), "/helpers.rs"));
+/// ```
+pub fn write_helper_stubs<P: AsRef<Path>, Q: AsRef<Path>>(
+    package_name: &str,
+    rpd_path: P,
+    out_path: Q,
+) {
+    let rpd = std::fs::read(rpd_path.as_ref())
+        .unwrap_or_else(|_| panic!("Failed to read package definition {:?}", rpd_path.as_ref()));
+    let definition: PackageDefinition = manifest_decode(&rpd)
+        .unwrap_or_else(|_| panic!("Invalid package definition {:?}", rpd_path.as_ref()));
+    std::fs::write(
+        out_path.as_ref(),
+        generate_helper_stubs(package_name, &definition),
+    )
+    .unwrap_or_else(|_| panic!("Failed to write helpers to {:?}", out_path.as_ref()));
+}
+
+// Methods of the generated helpers, including the ones of `TestHelperExecution`,
+// which must not be shadowed by the stub of a blueprint function with the same name
+const RESERVED_METHOD_NAMES: [&str; 23] = [
+    "new",
+    "add_instructions",
+    "bench",
+    "env",
+    "execute",
+    "execute_as",
+    "execute_expect_auth_failure",
+    "execute_expect_failure",
+    "execute_expect_failure_containing",
+    "execute_expect_failure_matching",
+    "execute_expect_rejection",
+    "execute_expect_specific_failure",
+    "execute_expect_success",
+    "execute_with_epoch_range",
+    "execute_with_preview_flags",
+    "execute_without_preview",
+    "label",
+    "name",
+    "namespace",
+    "new_instruction",
+    "preview",
+    "reset_instructions",
+    "try_execute",
+];
+
+/// Name of the stub method of `function_name`, with a trailing underscore for reserved names
+/// (the instruction label stays the plain function name)
+fn method_name(function_name: &str) -> String {
+    match RESERVED_METHOD_NAMES.contains(&function_name) {
+        true => format!("{}_", function_name),
+        false => function_name.to_string(),
+    }
+}
+
+fn helper_stub(
+    package_name: &str,
+    blueprint_name: &str,
+    functions: &[BlueprintFunction],
+) -> String {
+    let helper_name = format!("{}TestHelper", blueprint_name);
+    let mut source = String::new();
+    writeln!(
+        source,
+        "
+pub struct {helper_name} {{
+    pub env: TestEnvironment,
+    /// Component called by the methods, has to be set after instantiating the blueprint
+    pub component_address: Option<ComponentAddress>,
+}}
+
+impl TestHelperExecution for {helper_name} {{
+    fn env(&mut self) -> impl DerefMut<Target = TestEnvironment> + '_ {{
+        &mut self.env
+    }}
+}}
+
+impl {helper_name} {{
+    pub fn new(env: TestEnvironment) -> Self {{
+        Self {{
+            env,
+            component_address: None,
+        }}
+    }}"
+    )
+    .unwrap();
+    for function in functions {
+        source.push_str(&helper_method(package_name, blueprint_name, function));
+    }
+    source.push_str("}\n");
+    source
+}
+
+fn helper_method(package_name: &str, blueprint_name: &str, function: &BlueprintFunction) -> String {
+    let mut parameters = vec!["&mut self".to_string()];
+    let mut names = String::new();
+    let mut setup = String::new();
+    let mut lookups = String::new();
+    let mut call_arguments = vec![];
+    for (argument_name, type_name) in &function.arguments {
+        let argument = format!("arg_{}", argument_name);
+        match type_name.as_str() {
+            "Bucket" | "FungibleBucket" | "NonFungibleBucket" => {
+                parameters.push(format!("{}: (ResourceAddress, Decimal)", argument));
+                setup.push_str(&format!(
+                    "
+                .withdraw_from_account(account, {argument}.0, {argument}.1)
+                .take_from_worktop({argument}.0, {argument}.1, &{argument}_name)"
+                ));
+                lookups.push_str(&format!(
+                    "
+                    let {argument} = lookup.bucket(&{argument}_name);"
+                ));
+                names.push_str(&name_lookup(&argument));
+            }
+            "Proof" | "FungibleProof" | "NonFungibleProof" => {
+                parameters.push(format!("{}: (ResourceAddress, Decimal)", argument));
+                setup.push_str(&format!(
+                    "
+                .create_proof_from_account_of_amount(account, {argument}.0, {argument}.1)
+                .pop_from_auth_zone(&{argument}_name)"
+                ));
+                lookups.push_str(&format!(
+                    "
+                    let {argument} = lookup.proof(&{argument}_name);"
+                ));
+                names.push_str(&name_lookup(&argument));
+            }
+            _ => parameters.push(format!("{}: {}", argument, rust_type(type_name))),
+        }
+        call_arguments.push(argument);
+    }
+    let call = match function.is_method() {
+        true => format!(
+            "builder.call_method(component_address, \"{}\", manifest_args!({}))",
+            function.name,
+            call_arguments.join(", ")
+        ),
+        false => format!(
+            "builder.call_function(package_address, \"{}\", \"{}\", manifest_args!({}))",
+            blueprint_name,
+            function.name,
+            call_arguments.join(", ")
+        ),
+    };
+    let target = match function.is_method() {
+        true => {
+            "let component_address = self.component_address.expect(\"Component address not set\");"
+                .to_string()
+        }
+        false => format!(
+            "let package_address = self.env.package_address(\"{}\");",
+            package_name
+        ),
+    };
+    // Buckets and proofs are injected from the environment account before the call
+    let instructions = match setup.is_empty() {
+        true => call,
+        false => {
+            names.push_str(
+                "
+        let account = self.env.account;",
+            );
+            format!(
+                "builder{setup}
+                .with_name_lookup(|builder, lookup| {{{lookups}
+                    {call}
+                }})"
+            )
+        }
+    };
+    format!(
+        "
+    pub fn {method_name}({parameters}) -> &mut Self {{
+        {target}{names}
+        self.add_instructions(\"{name}\", |builder| {{
+            {instructions}
+        }});
+        self
+    }}
+",
+        method_name = method_name(&function.name),
+        name = function.name,
+        parameters = parameters.join(", "),
+    )
+}
+
+fn name_lookup(argument: &str) -> String {
+    format!(
+        "
+        let {argument}_name = self.name(\"{argument}\");"
+    )
+}
+
+/// Rust type of a schema type name, `ManifestValue` for types not known to the generator
+fn rust_type(type_name: &str) -> String {
+    if let Some(element_type) = type_name
+        .strip_prefix("Vec<")
+        .and_then(|inner| inner.strip_suffix('>'))
+    {
+        return format!("Vec<{}>", rust_type(element_type));
+    }
+    match type_name {
+        "Bool" => "bool".to_string(),
+        "I8" | "I16" | "I32" | "I64" | "I128" | "U8" | "U16" | "U32" | "U64" | "U128" => {
+            type_name.to_lowercase()
+        }
+        "String"
+        | "Decimal"
+        | "PreciseDecimal"
+        | "ResourceAddress"
+        | "ComponentAddress"
+        | "PackageAddress"
+        | "GlobalAddress"
+        | "NonFungibleLocalId"
+        | "NonFungibleGlobalId" => type_name.to_string(),
+        _ => "ManifestValue".to_string(),
+    }
+}
+
+#[test]
+fn test_rust_type() {
+    assert_eq!(rust_type("U64"), "u64");
+    assert_eq!(rust_type("Decimal"), "Decimal");
+    assert_eq!(rust_type("Vec<ResourceAddress>"), "Vec<ResourceAddress>");
+    assert_eq!(rust_type("PoolConfig"), "ManifestValue");
+}
+
+#[test]
+fn test_helper_method() {
+    let function = BlueprintFunction {
+        name: "swap".to_string(),
+        receiver: Some(Receiver::SelfRefMut),
+        arguments: vec![
+            ("input".to_string(), "Bucket".to_string()),
+            ("min_output".to_string(), "Decimal".to_string()),
+        ],
+        output: "Bucket".to_string(),
+    };
+
+    let source = helper_method("hello_swap", "HelloSwap", &function);
+
+    assert!(source.contains(
+        "pub fn swap(&mut self, arg_input: (ResourceAddress, Decimal), arg_min_output: Decimal)"
+    ));
+    assert!(source.contains("manifest_args!(arg_input, arg_min_output)"));
+    assert!(source.contains("self.add_instructions(\"swap\", |builder| {"));
+    assert!(source.contains(".take_from_worktop(arg_input.0, arg_input.1, &arg_input_name)"));
+}
+
+#[test]
+fn test_reserved_method_names() {
+    let function = BlueprintFunction {
+        name: "new".to_string(),
+        receiver: None,
+        arguments: vec![],
+        output: "Own".to_string(),
+    };
+
+    let source = helper_method("hello_swap", "HelloSwap", &function);
+
+    assert!(source.contains("pub fn new_(&mut self) -> &mut Self"));
+    assert!(source.contains("self.add_instructions(\"new\", |builder| {"));
+    assert_eq!(method_name("execute"), "execute_");
+    assert_eq!(method_name("name"), "name_");
+    assert_eq!(method_name("label"), "label_");
+    assert_eq!(method_name("add_instructions"), "add_instructions_");
+    assert_eq!(method_name("swap"), "swap");
+}
+
+#[test]
+fn test_generated_example_helpers() {
+    use crate::TestEnvironment;
+
+    let packages: HashMap<&str, &str> = HashMap::from([("hello_swap", "examples/hello_swap")]);
+    let test_environment = TestEnvironment::new(packages);
+    let definition = &test_environment.package_definitions["hello_swap"];
+
+    // The example includes the generated helpers, regenerate them if the generator changes
+    assert_eq!(
+        generate_helper_stubs("hello_swap", definition),
+        std::fs::read_to_string("examples/hello_swap/tests/generated/helpers.rs").unwrap()
+    );
+}
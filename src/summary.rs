@@ -0,0 +1,106 @@
+use radix_engine::{
+    system::system_modules::execution_trace::{ResourceSpecifier, WorktopChange},
+    transaction::TransactionResult,
+};
+use scrypto::prelude::*;
+
+use crate::golden::AddressLabeler;
+use crate::{Receipt, TransactionReceiptOutputBuckets};
+
+impl Receipt {
+    /// Readable summary of the execution grouped by instruction label (resources taken from and
+    /// put on the worktop, cost units) followed by the emitted events.
+    /// Printed by `TestHelperExecution::execute` in verbose mode.
+    pub fn summary(&self) -> String {
+        let mut labeler = AddressLabeler::new(&self.address_labels);
+        let mut lines = vec![
+            format!("outcome: {}", self.outcome()),
+            format!(
+                "fee: {} XRD, cost units: {}",
+                self.fee_paid_xrd(),
+                self.total_cost_units()
+            ),
+        ];
+        // Worktop changes are only traced by the preview, which is the receipt itself for
+        // summaries of `TestHelperExecution::preview`
+        let worktop_changes = self
+            .try_preview_receipt()
+            .or(Some(&*self.execution_receipt))
+            .and_then(|preview_receipt| match &preview_receipt.result {
+                TransactionResult::Commit(commit_result) => commit_result
+                    .execution_trace
+                    .as_ref()
+                    .map(|execution_trace| execution_trace.worktop_changes()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let cost_by_instruction = self
+            .execution_receipt
+            .cost_by_instruction()
+            .unwrap_or_default();
+        let mut labels: Vec<(&String, &Vec<usize>)> =
+            self.instruction_ids_by_label.iter().collect();
+        labels.sort_by_key(|(_, instruction_ids)| instruction_ids.first().copied());
+        for (label, instruction_ids) in labels {
+            lines.push(format!("[{}] instructions {:?}", label, instruction_ids));
+            for instruction_id in instruction_ids {
+                for change in worktop_changes.get(instruction_id).into_iter().flatten() {
+                    let (direction, resource) = match change {
+                        WorktopChange::Take(resource) => ("in: ", resource),
+                        WorktopChange::Put(resource) => ("out:", resource),
+                    };
+                    lines.push(format!(
+                        "  {} {}",
+                        direction,
+                        format_resource_specifier(resource, &mut labeler)
+                    ));
+                }
+                if let Some(cost_units) = cost_by_instruction.get(*instruction_id) {
+                    lines.push(format!("  cost units: {}", cost_units));
+                }
+            }
+        }
+        lines.push("events:".to_string());
+        for event in self.labeled_events(&mut labeler) {
+            lines.push(format!("  {}", event));
+        }
+        lines.join("\n")
+    }
+}
+
+fn format_resource_specifier(resource: &ResourceSpecifier, labeler: &mut AddressLabeler) -> String {
+    match resource {
+        ResourceSpecifier::Amount(address, amount) => {
+            format!("{} {}", amount, labeler.label((*address).into()))
+        }
+        ResourceSpecifier::Ids(address, ids) => format!(
+            "{} {:?}",
+            labeler.label((*address).into()),
+            ids.iter().map(|id| id.to_string()).collect::<Vec<_>>()
+        ),
+    }
+}
+
+#[test]
+fn test_summary() {
+    use crate::{TestEnvironment, TestHelperExecution};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (account, a_address) = (test_environment.account, test_environment.a_address);
+    test_environment.call_with_bucket("deposit", a_address, dec!(1), |builder, a_bucket| {
+        builder.try_deposit_or_abort(account, None, a_bucket)
+    });
+
+    let summary = test_environment.execute_expect_success(false).summary();
+
+    assert!(summary.starts_with("outcome: success"));
+    assert!(summary.contains("[deposit] instructions"));
+    assert!(summary.contains("events:"));
+
+    // Verbose previews print the same summary
+    test_environment.call_with_bucket("deposit", a_address, dec!(1), |builder, a_bucket| {
+        builder.try_deposit_or_abort(account, None, a_bucket)
+    });
+    test_environment.preview(true).expect_commit_success();
+}
@@ -0,0 +1,201 @@
+use radix_engine::blueprints::consensus_manager::{
+    ConsensusManagerField, ConsensusManagerStateFieldSubstate,
+};
+use radix_substate_store_interface::{
+    db_key_mapper::SpreadPrefixKeyMapper, interface::SubstateDatabase,
+};
+use scrypto::prelude::*;
+use std::time::Duration;
+
+use crate::TestEnvironment;
+
+const DEFAULT_EPOCH_DURATION: Duration = Duration::from_secs(5 * 60);
+
+impl TestEnvironment {
+    pub fn current_epoch(&mut self) -> Epoch {
+        self.test_runner.get_current_epoch()
+    }
+
+    /// Moves the ledger `epochs` epochs forward, e.g. to reach vesting or expiry epochs
+    pub fn advance_epochs(&mut self, epochs: u64) {
+        let epoch = self.current_epoch().after(epochs).expect("Epoch overflow");
+        self.test_runner.set_current_epoch(epoch);
+    }
+
+    /// Round within the current epoch
+    pub fn current_round(&self) -> Round {
+        self.test_runner
+            .substate_db()
+            .get_mapped::<SpreadPrefixKeyMapper, ConsensusManagerStateFieldSubstate>(
+                CONSENSUS_MANAGER.as_node_id(),
+                MAIN_BASE_PARTITION,
+                &ConsensusManagerField::State.into(),
+            )
+            .expect("Consensus manager state not found")
+            .into_payload()
+            .into_latest()
+            .round
+    }
+
+    /// Moves the ledger `rounds` rounds forward, keeping the proposer timestamp.
+    /// Reaching `GenesisConfig::rounds_per_epoch` starts a new epoch (at round 0). The default
+    /// genesis has a single round per epoch, so configure more rounds to stay within an epoch.
+    pub fn advance_rounds(&mut self, rounds: u64) {
+        let round = Round::of(self.current_round().number() + rounds);
+        let timestamp_ms = self.proposer_timestamp_ms();
+        self.test_runner
+            .advance_to_round_at_timestamp(round, timestamp_ms)
+            .expect_commit_success();
+    }
+
+    /// Timestamp of the current round proposer, as read by the Clock
+    pub fn proposer_timestamp_ms(&mut self) -> i64 {
+        self.test_runner.get_current_proposer_timestamp_ms()
+    }
+
+    /// Moves the proposer timestamp `duration` forward together with the epoch,
+    /// which advances once per `TestEnvironmentBuilder::epoch_duration` passed, e.g.:
+    /// ```ignore
+    /// test_environment.advance_time(Duration::from_secs(7 * 24 * 60 * 60));
+    /// ```
+    pub fn advance_time(&mut self, duration: Duration) {
+        let epoch_ms = self
+            .config()
+            .epoch_duration
+            .unwrap_or(DEFAULT_EPOCH_DURATION)
+            .as_millis() as i64;
+        let timestamp_ms = self.proposer_timestamp_ms();
+        let new_timestamp_ms = timestamp_ms + duration.as_millis() as i64;
+        let epochs = new_timestamp_ms.div_euclid(epoch_ms) - timestamp_ms.div_euclid(epoch_ms);
+        let epoch = self
+            .current_epoch()
+            .after(epochs as u64)
+            .expect("Epoch overflow");
+
+        self.set_proposer_timestamp(new_timestamp_ms);
+        // Advancing the round may have started a new epoch already, which must not be undone
+        if self.current_epoch() < epoch {
+            self.test_runner.set_current_epoch(epoch);
+        }
+    }
+
+    /// Sets the timestamp read by the Clock by advancing to the next round proposed at `timestamp_ms`.
+    /// Like `advance_rounds`, this starts a new epoch with the default genesis (one round per epoch).
+    pub fn set_proposer_timestamp(&mut self, timestamp_ms: i64) {
+        let round = Round::of(self.current_round().number() + 1);
+        self.test_runner
+            .advance_to_round_at_timestamp(round, timestamp_ms)
+            .expect_commit_success();
+    }
+}
+
+#[test]
+fn test_advance_epochs() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let epoch = test_environment.current_epoch();
+
+    test_environment.advance_epochs(10);
+
+    assert_eq!(test_environment.current_epoch(), epoch.after(10).unwrap());
+}
+
+#[test]
+fn test_advance_rounds() {
+    use crate::{GenesisConfig, TestEnvironmentBuilder};
+
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .genesis(GenesisConfig {
+            rounds_per_epoch: 100,
+            ..Default::default()
+        })
+        .build();
+    let (epoch, round) = (
+        test_environment.current_epoch(),
+        test_environment.current_round(),
+    );
+
+    test_environment.advance_rounds(5);
+
+    assert_eq!(test_environment.current_epoch(), epoch);
+    assert_eq!(
+        test_environment.current_round().number(),
+        round.number() + 5
+    );
+}
+
+#[test]
+fn test_set_proposer_timestamp() {
+    use crate::{GenesisConfig, TestEnvironmentBuilder};
+
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let mut test_environment_long_epochs = TestEnvironmentBuilder::new()
+        .genesis(GenesisConfig {
+            rounds_per_epoch: 100,
+            ..Default::default()
+        })
+        .build();
+    let epoch = test_environment.current_epoch();
+    let epoch_long_epochs = test_environment_long_epochs.current_epoch();
+
+    test_environment.set_proposer_timestamp(1_700_000_000_000);
+    test_environment_long_epochs.set_proposer_timestamp(1_700_000_000_000);
+
+    assert_eq!(test_environment.proposer_timestamp_ms(), 1_700_000_000_000);
+    assert_eq!(test_environment.current_epoch(), epoch.next().unwrap());
+    assert_eq!(
+        test_environment_long_epochs.proposer_timestamp_ms(),
+        1_700_000_000_000
+    );
+    assert_eq!(
+        test_environment_long_epochs.current_epoch(),
+        epoch_long_epochs
+    );
+}
+
+#[test]
+fn test_advance_time() {
+    let packages: HashMap<&str, &str> = HashMap::new();
+    let mut test_environment = TestEnvironment::new(packages);
+    let (epoch, timestamp_ms) = (
+        test_environment.current_epoch(),
+        test_environment.proposer_timestamp_ms(),
+    );
+    let week = Duration::from_secs(7 * 24 * 60 * 60);
+    assert_eq!(timestamp_ms, 0);
+
+    test_environment.advance_time(week);
+
+    assert_eq!(
+        test_environment.proposer_timestamp_ms(),
+        week.as_millis() as i64
+    );
+    // 12 epochs of 5 minutes per hour
+    assert_eq!(
+        test_environment.current_epoch(),
+        epoch.after(7 * 24 * 12).unwrap()
+    );
+}
+
+#[test]
+fn test_advance_time_within_epoch() {
+    use crate::{GenesisConfig, TestEnvironmentBuilder};
+
+    let mut test_environment = TestEnvironmentBuilder::new()
+        .genesis(GenesisConfig {
+            rounds_per_epoch: 100,
+            ..Default::default()
+        })
+        .epoch_duration(Duration::from_secs(60))
+        .build();
+    let epoch = test_environment.current_epoch();
+
+    test_environment.advance_time(Duration::from_secs(30));
+    assert_eq!(test_environment.proposer_timestamp_ms(), 30_000);
+    assert_eq!(test_environment.current_epoch(), epoch);
+
+    test_environment.advance_time(Duration::from_secs(30));
+    assert_eq!(test_environment.proposer_timestamp_ms(), 60_000);
+    assert_eq!(test_environment.current_epoch(), epoch.next().unwrap());
+}
@@ -0,0 +1,71 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::TestEnvironmentConfig;
+
+/// Time spent setting up a TestEnvironment, split by setup phase
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetupTimings {
+    /// Generating a new (packageless) environment from scratch
+    pub generation: Duration,
+    /// Compiling packages which were not found in the package cache
+    pub compilation: Duration,
+    /// Publishing the compiled packages to the ledger
+    pub publishing: Duration,
+    /// Reviving environments from cached snapshots
+    pub revival: Duration,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+impl SetupTimings {
+    pub fn total(&self) -> Duration {
+        self.generation + self.compilation + self.publishing + self.revival
+    }
+}
+
+impl AddAssign<&SetupTimings> for SetupTimings {
+    fn add_assign(&mut self, other: &SetupTimings) {
+        self.generation += other.generation;
+        self.compilation += other.compilation;
+        self.publishing += other.publishing;
+        self.revival += other.revival;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+    }
+}
+
+lazy_static! {
+    static ref SETUP_TIMINGS: RwLock<HashMap<TestEnvironmentConfig, SetupTimings>> =
+        RwLock::new(HashMap::new());
+}
+
+pub(crate) fn record_setup_timings(key: &TestEnvironmentConfig, timings: &SetupTimings) {
+    let mut write_lock = SETUP_TIMINGS.write().unwrap();
+    *write_lock.entry(key.clone()).or_default() += timings;
+}
+
+/// Accumulated setup timings of all TestEnvironments created in this process so far,
+/// keyed by their environment cache key
+pub fn setup_timings() -> HashMap<TestEnvironmentConfig, SetupTimings> {
+    SETUP_TIMINGS.read().unwrap().clone()
+}
+
+#[test]
+fn test_setup_timings() {
+    use crate::TestEnvironmentBuilder;
+
+    // Unique account count, so no other test shares the cache key
+    let _cache_guard = crate::cache::lock_cache_for_test();
+    let first = TestEnvironmentBuilder::new().additional_accounts(7).build();
+    let second = TestEnvironmentBuilder::new().additional_accounts(7).build();
+    let timings = setup_timings()[first.config()].clone();
+
+    assert_eq!(first.config(), second.config());
+    assert_eq!(timings.cache_hits + timings.cache_misses, 2);
+    assert!(timings.cache_misses >= 1);
+    assert!(timings.total() >= timings.generation);
+}
@@ -0,0 +1,142 @@
+use radix_engine::blueprints::package::PackageDefinition;
+use scrypto_compiler::ScryptoCompiler;
+use std::path::{Path, PathBuf};
+
+use crate::TestEnvError;
+
+/// Builds the `package_dirs` of the Cargo workspace at `workspace_dir` with a single Scrypto
+/// compiler invocation, sharing the workspace target directory and thereby the build of
+/// common dependencies
+pub(crate) fn compile_workspace(
+    workspace_dir: &Path,
+    package_dirs: &[PathBuf],
+) -> Result<Vec<(PathBuf, (Vec<u8>, PackageDefinition))>, TestEnvError> {
+    let target_dir = match std::env::var("CARGO_TARGET_DIR") {
+        Ok(target_dir) => workspace_dir.join(target_dir),
+        Err(_) => workspace_dir.join("target"),
+    };
+    let mut wasm_names = vec![];
+    let mut compiler_builder = ScryptoCompiler::builder();
+    compiler_builder
+        .manifest_path(workspace_dir.join("Cargo.toml"))
+        .target_directory(target_dir);
+    for package_dir in package_dirs {
+        let manifest = CargoManifest::read(package_dir)?;
+        compiler_builder.package(&manifest.package_name);
+        wasm_names.push(manifest.wasm_name());
+    }
+    let build_artifacts =
+        compiler_builder
+            .compile()
+            .map_err(|error| TestEnvError::CompilationFailed {
+                package_dir: workspace_dir.to_path_buf(),
+                error: format!("{:?}", error),
+            })?;
+
+    package_dirs
+        .iter()
+        .zip(wasm_names)
+        .map(|(package_dir, wasm_name)| {
+            let build_artifact = build_artifacts
+                .iter()
+                .find(|build_artifacts| {
+                    build_artifacts
+                        .wasm
+                        .path
+                        .file_stem()
+                        .is_some_and(|file_stem| file_stem == wasm_name.as_str())
+                })
+                .ok_or_else(|| {
+                    TestEnvError::SetupFailed(format!(
+                        "No wasm built for package {:?} in workspace {:?}",
+                        package_dir, workspace_dir
+                    ))
+                })?;
+            Ok((
+                package_dir.clone(),
+                (
+                    build_artifact.wasm.content.clone(),
+                    build_artifact.package_definition.content.clone(),
+                ),
+            ))
+        })
+        .collect()
+}
+
+/// The parts of a package's Cargo.toml needed to select it and find its wasm
+#[derive(Debug, PartialEq, Eq)]
+struct CargoManifest {
+    package_name: String,
+    lib_name: Option<String>,
+}
+
+impl CargoManifest {
+    fn read(package_dir: &Path) -> Result<Self, TestEnvError> {
+        let manifest_path = package_dir.join("Cargo.toml");
+        let manifest = std::fs::read_to_string(&manifest_path).map_err(|error| {
+            TestEnvError::SetupFailed(format!("Failed to read {:?}: {}", manifest_path, error))
+        })?;
+        Self::parse(&manifest).ok_or_else(|| {
+            TestEnvError::SetupFailed(format!("No package name in {:?}", manifest_path))
+        })
+    }
+
+    fn parse(manifest: &str) -> Option<Self> {
+        let manifest: toml::Table = manifest.parse().ok()?;
+        let name = |section: &str| {
+            manifest
+                .get(section)?
+                .get("name")?
+                .as_str()
+                .map(str::to_string)
+        };
+        Some(Self {
+            package_name: name("package")?,
+            lib_name: name("lib"),
+        })
+    }
+
+    /// Name of the wasm file built for the package, which is named after its library target
+    fn wasm_name(&self) -> String {
+        self.lib_name
+            .clone()
+            .unwrap_or_else(|| self.package_name.replace('-', "_"))
+    }
+}
+
+#[test]
+fn test_cargo_manifest() {
+    let manifest = CargoManifest::parse(
+        "[package]\nname = \"hello-swap\"\nversion = \"1.0.0\"\n\n[dependencies]\nscrypto = { version = \"1.2.0\" }\n",
+    )
+    .unwrap();
+    assert_eq!(manifest.package_name, "hello-swap");
+    assert_eq!(manifest.wasm_name(), "hello_swap");
+
+    // A `name` in a section before [package] must not be taken for the package name
+    let manifest = CargoManifest::parse(
+        "[lib]\nname = \"custom_swap\"\ncrate-type = [\"cdylib\", \"lib\"]\n\n[package]\nname = \"hello-swap\"\n",
+    )
+    .unwrap();
+    assert_eq!(manifest.package_name, "hello-swap");
+    assert_eq!(manifest.wasm_name(), "custom_swap");
+
+    assert_eq!(CargoManifest::parse("[workspace]\nmembers = []\n"), None);
+}
+
+#[test]
+fn test_compile_workspace() {
+    let workspace_dir = Path::new("testdata/workspace");
+    let package_dirs = vec![
+        workspace_dir.join("first"),
+        workspace_dir.join("second-package"),
+    ];
+
+    let compiled_packages = compile_workspace(workspace_dir, &package_dirs).unwrap();
+
+    assert_eq!(compiled_packages.len(), 2);
+    assert_eq!(compiled_packages[0].0, package_dirs[0]);
+    assert!(compiled_packages[0].1 .1.blueprints.contains_key("First"));
+    assert_eq!(compiled_packages[1].0, package_dirs[1]);
+    assert!(compiled_packages[1].1 .1.blueprints.contains_key("Second"));
+}
@@ -0,0 +1,18 @@
+use scrypto::prelude::*;
+
+#[blueprint]
+mod compiler_options {
+    struct CompilerOptions {}
+
+    impl CompilerOptions {
+        /// Whether the package was compiled with the `test_hook` feature
+        pub fn test_hook_enabled() -> bool {
+            cfg!(feature = "test_hook")
+        }
+
+        /// Value of `TESTENV_GREETING` at compile time
+        pub fn greeting() -> String {
+            option_env!("TESTENV_GREETING").unwrap_or("none").to_string()
+        }
+    }
+}
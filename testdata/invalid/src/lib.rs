@@ -0,0 +1,13 @@
+use scrypto::prelude::*;
+
+// Fails to compile on purpose, to test the reporting of compilation failures
+#[blueprint]
+mod invalid {
+    struct Invalid {}
+
+    impl Invalid {
+        pub fn instantiate() -> Global<Invalid> {
+            undefined_function()
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use scrypto::prelude::*;
+
+#[blueprint]
+mod kv_store {
+    struct KvStore {
+        prices: KeyValueStore<String, Decimal>,
+    }
+
+    impl KvStore {
+        pub fn instantiate() -> Global<KvStore> {
+            let prices = KeyValueStore::new();
+            prices.insert("XRD".to_string(), dec!(2));
+            Self { prices }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize()
+        }
+    }
+}
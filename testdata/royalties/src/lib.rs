@@ -0,0 +1,34 @@
+use scrypto::prelude::*;
+
+#[blueprint]
+mod counter {
+    struct Counter {
+        count: u64,
+    }
+
+    impl Counter {
+        pub fn instantiate(owner_badge: ResourceAddress) -> Global<Counter> {
+            Self { count: 0 }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::Updatable(rule!(require(owner_badge))))
+                .enable_component_royalties(component_royalties! {
+                    roles {
+                        royalty_setter => OWNER;
+                        royalty_setter_updater => OWNER;
+                        royalty_locker => OWNER;
+                        royalty_locker_updater => rule!(deny_all);
+                        royalty_claimer => OWNER;
+                        royalty_claimer_updater => rule!(deny_all);
+                    },
+                    init {
+                        increment => Xrd(2.into()), updatable;
+                    }
+                })
+                .globalize()
+        }
+
+        pub fn increment(&mut self) {
+            self.count += 1;
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use scrypto::prelude::*;
+
+#[blueprint]
+mod first {
+    struct First {}
+
+    impl First {
+        pub fn instantiate() -> Global<First> {
+            Self {}
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize()
+        }
+    }
+}
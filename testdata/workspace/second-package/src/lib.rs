@@ -0,0 +1,15 @@
+use scrypto::prelude::*;
+
+#[blueprint]
+mod second {
+    struct Second {}
+
+    impl Second {
+        pub fn instantiate() -> Global<Second> {
+            Self {}
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize()
+        }
+    }
+}